@@ -0,0 +1,429 @@
+//! The lossless syntax tree, which keeps every byte of the source
+//!
+//! The green tree is immutable and doesn't know where it is, so subtrees can
+//! be shared. Whitespace and comments are trivia, kept on the token after
+//! them, with any at the end of the file on the `EOF` token. The red tree is
+//! a view of the green tree that knows offsets and parents. The `Node` tree
+//! the compiler uses is derived from it, leaving out tokens and trivia.
+
+use std::{fmt, ops::Range, rc::Rc};
+
+use crate::{node::NodeValue, token::Token, Node};
+
+/// Whitespace or a comment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: Token,
+    pub text: String,
+}
+
+impl Trivia {
+    /// The text of a `///` doc comment, without the `///` and the space
+    /// after it. `////` isn't a doc comment.
+    pub fn doc(&self) -> Option<&str> {
+        let text = self.text.strip_prefix("///")?;
+        if self.kind != Token::Comment || text.starts_with('/') {
+            return None;
+        }
+        Some(text.strip_prefix(' ').unwrap_or(text))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreenToken {
+    pub kind: Token,
+    pub text: String,
+    /// The trivia between the previous token and this one
+    pub leading: Vec<Trivia>,
+}
+
+impl GreenToken {
+    pub fn width(&self) -> usize {
+        self.leading_width() + self.text.len()
+    }
+
+    fn leading_width(&self) -> usize {
+        self.leading.iter().map(|trivia| trivia.text.len()).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn width(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.width,
+            GreenElement::Token(token) => token.width(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreenNode {
+    pub kind: NodeValue,
+    /// Length of the text of the node, with trivia
+    pub width: usize,
+    pub children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: NodeValue, children: Vec<GreenElement>) -> Self {
+        Self {
+            kind,
+            width: children.iter().map(GreenElement::width).sum(),
+            children,
+        }
+    }
+
+    fn first_token(&self) -> Option<&GreenToken> {
+        self.children.iter().find_map(|child| match child {
+            GreenElement::Node(node) => node.first_token(),
+            GreenElement::Token(token) => Some(token),
+        })
+    }
+
+    /// The `Node` for this, which starts at `offset` in the source. `doc` is
+    /// from the item this is wrapped in.
+    fn to_node(&self, offset: usize, doc: Option<String>) -> Node {
+        let mut doc = doc;
+        if is_item(self.kind) {
+            let lines: Vec<_> = self
+                .first_token()
+                .into_iter()
+                .flat_map(|token| &token.leading)
+                .filter_map(Trivia::doc)
+                .collect();
+            if !lines.is_empty() {
+                let own = lines.join("\n");
+                doc = Some(match doc {
+                    Some(doc) => format!("{doc}\n{own}"),
+                    None => own,
+                });
+            }
+        }
+        // Everything in a wrapper is the one item it wraps, but an `extern`
+        // block has any number of items
+        let wraps = match self.kind {
+            NodeValue::Pub | NodeValue::Attribute => true,
+            NodeValue::Extern => !self.children.iter().any(|child| {
+                matches!(child, GreenElement::Token(token) if token.kind == Token::OpenBrace)
+            }),
+            _ => false,
+        };
+
+        let mut children = vec![];
+        let mut start = None;
+        let mut end = offset;
+        let mut pos = offset;
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => {
+                    let inherited = doc.clone().filter(|_| wraps && is_item(node.kind));
+                    let child = node.to_node(pos, inherited);
+                    if child.start < child.end {
+                        start.get_or_insert(child.start);
+                        end = child.end;
+                    }
+                    children.push(child);
+                }
+                GreenElement::Token(token) if token.kind != Token::EOF => {
+                    start.get_or_insert(pos + token.leading_width());
+                    end = pos + token.width();
+                }
+                GreenElement::Token(_) => {}
+            }
+            pos += child.width();
+        }
+
+        Node {
+            node: self.kind,
+            start: start.unwrap_or(end),
+            end,
+            children,
+            doc: doc.filter(|_| {
+                matches!(
+                    self.kind,
+                    NodeValue::FnDef | NodeValue::FnDecl | NodeValue::Extern
+                )
+            }),
+        }
+    }
+}
+
+/// Kinds that can have doc comments, or wrap an item that can
+fn is_item(kind: NodeValue) -> bool {
+    matches!(
+        kind,
+        NodeValue::FnDef
+            | NodeValue::FnDecl
+            | NodeValue::Extern
+            | NodeValue::Pub
+            | NodeValue::Attribute
+    )
+}
+
+impl fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => write!(f, "{node}")?,
+                GreenElement::Token(token) => {
+                    for trivia in &token.leading {
+                        write!(f, "{}", trivia.text)?;
+                    }
+                    write!(f, "{}", token.text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a green tree bottom up, as the parser reads tokens
+#[derive(Default)]
+pub struct GreenBuilder {
+    /// Open nodes, and the index of their first child in `children`
+    parents: Vec<(NodeValue, usize)>,
+    children: Vec<GreenElement>,
+}
+
+/// A position in the children of the open node, so a node can be started
+/// after its first child was already read, like the left side of `a + b`
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint(usize);
+
+impl GreenBuilder {
+    pub fn start_node(&mut self, kind: NodeValue) {
+        self.parents.push((kind, self.children.len()));
+    }
+
+    /// How many nodes are open
+    pub fn depth(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.children.len())
+    }
+
+    /// Starts a node whose first child is the one read at `checkpoint`
+    pub fn start_node_at(&mut self, checkpoint: Checkpoint, kind: NodeValue) {
+        self.parents.push((kind, checkpoint.0));
+    }
+
+    pub fn token(&mut self, token: GreenToken) {
+        self.children.push(GreenElement::Token(Rc::new(token)));
+    }
+
+    pub fn finish_node(&mut self) {
+        let (kind, first) = self.parents.pop().expect("No node to finish");
+        let children = self.children.split_off(first);
+        self.children
+            .push(GreenElement::Node(Rc::new(GreenNode::new(kind, children))));
+    }
+
+    /// The root, once every node is finished
+    pub fn finish(mut self) -> Rc<GreenNode> {
+        assert!(self.parents.is_empty(), "Unfinished node");
+        match self.children.pop() {
+            Some(GreenElement::Node(root)) if self.children.is_empty() => root,
+            _ => panic!("A tree needs a single root node"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+/// A node of the red tree
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    /// Where the node starts in the source, with the trivia of its first token
+    offset: usize,
+    parent: Option<SyntaxNode>,
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        Self(Rc::new(NodeData {
+            green,
+            offset: 0,
+            parent: None,
+        }))
+    }
+
+    pub fn kind(&self) -> NodeValue {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.0.parent.as_ref()
+    }
+
+    /// The bytes of the source the node covers, with leading trivia
+    pub fn full_range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.width
+    }
+
+    /// The bytes of the source the node covers, without leading trivia
+    pub fn text_range(&self) -> Range<usize> {
+        let node = self.to_node();
+        node.start..node.end
+    }
+
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+        self.0.green.children.iter().map(move |child| {
+            let element = match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    offset,
+                    parent: Some(self.clone()),
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    offset,
+                    parent: self.clone(),
+                }),
+            };
+            offset += child.width();
+            element
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens()
+            .filter_map(|element| match element {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+    }
+
+    /// Every token in the node, in source order
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = vec![];
+        for element in self.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    pub fn first_token(&self) -> Option<SyntaxToken> {
+        self.children_with_tokens()
+            .find_map(|element| match element {
+                SyntaxElement::Node(node) => node.first_token(),
+                SyntaxElement::Token(token) => Some(token),
+            })
+    }
+
+    /// The AST node, without tokens and trivia
+    pub fn to_node(&self) -> Node {
+        self.0.green.to_node(self.0.offset, None)
+    }
+}
+
+/// The source text of the node, exactly as it was parsed
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.green)
+    }
+}
+
+/// A token of the red tree
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    /// Where the token starts in the source, with its trivia
+    offset: usize,
+    parent: SyntaxNode,
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> Token {
+        self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn leading_trivia(&self) -> &[Trivia] {
+        &self.green.leading
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+
+    /// The bytes of the token in the source, without trivia
+    pub fn text_range(&self) -> Range<usize> {
+        let start = self.offset + self.green.leading_width();
+        start..start + self.green.text.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, parse_cst, token::Token};
+
+    const SOURCE: &str = "// A comment
+fn main() -> int {
+    // Inside
+    let x = (1 + 2) * 3;
+    x
+}
+
+extern \"C\" fn putchar(c: int) -> int;
+// Trailing
+";
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(parse_cst(SOURCE).unwrap().to_string(), SOURCE);
+    }
+
+    #[test]
+    fn trivia_is_on_the_next_token() {
+        let root = parse_cst(SOURCE).unwrap();
+        let tokens = root.tokens();
+        let fn_token = &tokens[0];
+        assert_eq!(fn_token.kind(), Token::Fn);
+        assert_eq!(fn_token.leading_trivia()[0].text, "// A comment");
+        assert_eq!(fn_token.text_range(), 13..15);
+
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.kind(), Token::EOF);
+        assert_eq!(eof.leading_trivia()[1].text, "// Trailing");
+    }
+
+    #[test]
+    fn ast_matches_the_tree() {
+        let root = parse_cst(SOURCE).unwrap();
+        let items: Vec<_> = root.children().map(|child| child.to_node()).collect();
+        assert_eq!(items, parse(SOURCE).unwrap());
+        let main = &SOURCE[items[0].start..items[0].end];
+        assert!(main.starts_with("fn main") && main.ends_with("x\n}"));
+        assert_eq!(
+            root.children().next().unwrap().text_range(),
+            items[0].start..items[0].end
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! The source formatter, which prints the syntax tree back with normalized
+//! whitespace
+//!
+//! Blocks are indented by four spaces with the opening brace on the line of
+//! what it belongs to, binary operators get a space on each side, and items
+//! that aren't one line are separated by a blank line. Comments are kept,
+//! and so is a single blank line between statements. The last statement of a
+//! block ends in `;` if it's a `let` or `return`, never if it's an `if`, and
+//! otherwise as it was written, since that doesn't change what it means.
+
+use crate::{
+    cst::{SyntaxElement, SyntaxNode, SyntaxToken},
+    node::NodeValue,
+    parse_cst,
+    token::Token,
+    ParseError,
+};
+
+/// Formats a whole source. Formatting an already formatted source doesn't
+/// change it.
+pub fn format_source(source: &str) -> Result<String, ParseError> {
+    let root = parse_cst(source)?;
+    let mut printer = Printer::default();
+    printer.node(&root);
+    Ok(printer.out)
+}
+
+/// What goes between the last text written and the next
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Break {
+    #[default]
+    None,
+    Space,
+    Line,
+    BlankLine,
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    pending: Break,
+    /// The last token written
+    last: Option<Token>,
+    /// No space before the next token, even if it would usually get one
+    glue: bool,
+}
+
+impl Printer {
+    fn node(&mut self, node: &SyntaxNode) {
+        let elements: Vec<_> = node.children_with_tokens().collect();
+        let open = elements.iter().position(|element| {
+            matches!(element, SyntaxElement::Token(token) if token.kind() == Token::OpenBrace)
+        });
+
+        match node.kind() {
+            NodeValue::Root => {
+                let (eof, items) = elements.split_last().expect("Root has an EOF token");
+                self.items(items);
+                if let SyntaxElement::Token(eof) = eof {
+                    self.comments(eof);
+                }
+                if !self.out.is_empty() {
+                    self.out.push('\n');
+                }
+            }
+            NodeValue::FnDef | NodeValue::If => {
+                let open = open.expect("Block without `{`");
+                self.elements(&elements[..open]);
+                self.statements(&elements[open..]);
+            }
+            NodeValue::Module | NodeValue::Extern | NodeValue::Trait | NodeValue::Impl => {
+                match open {
+                    Some(open) => {
+                        self.elements(&elements[..open]);
+                        self.block_of_items(&elements[open..]);
+                    }
+                    None => self.elements(&elements),
+                }
+            }
+            // The item an attribute is on goes on the next line
+            NodeValue::Attribute => {
+                let (item, attribute) = elements.split_last().expect("Attribute without item");
+                self.elements(attribute);
+                self.pending = self.pending.max(Break::Line);
+                self.elements(std::slice::from_ref(item));
+            }
+            // `<T>`, `|x|`, `f(x)` and `fn f(x: int)` have no space inside
+            // the delimiters
+            NodeValue::TypeParams | NodeValue::ClosureParams => {
+                if let Some((close, inner)) = elements.split_last() {
+                    self.glue = node.kind() == NodeValue::TypeParams;
+                    self.elements(&inner[..1]);
+                    self.glue = true;
+                    // The trailing comma isn't followed by the `>` or `|` in
+                    // the slice, so it's left out here
+                    match &inner[1..] {
+                        [params @ .., SyntaxElement::Token(comma)]
+                            if comma.kind() == Token::Comma =>
+                        {
+                            self.elements(params);
+                            self.comments(comma);
+                        }
+                        params => self.elements(params),
+                    }
+                    self.glue = true;
+                    self.elements(std::slice::from_ref(close));
+                }
+            }
+            NodeValue::FnDefArgSet | NodeValue::FnCallArgSet => {
+                self.glue = !elements.is_empty();
+                self.elements(&elements);
+            }
+            _ => self.elements(&elements),
+        }
+    }
+
+    /// Trailing commas are left out
+    fn elements(&mut self, elements: &[SyntaxElement]) {
+        for (i, element) in elements.iter().enumerate() {
+            match element {
+                SyntaxElement::Node(node) => self.node(node),
+                SyntaxElement::Token(token)
+                    if token.kind() == Token::Comma && closes_list(elements.get(i + 1)) =>
+                {
+                    self.comments(token);
+                }
+                SyntaxElement::Token(token) => self.token(token),
+            }
+        }
+    }
+
+    /// Items, one per line, with a blank line around any that aren't a
+    /// single line
+    fn items(&mut self, items: &[SyntaxElement]) {
+        let mut last: Option<&SyntaxNode> = None;
+        for element in items {
+            match element {
+                SyntaxElement::Node(item) => {
+                    self.pending = self.pending.max(match last {
+                        Some(last) if is_one_line(last) && is_one_line(item) => Break::Line,
+                        Some(_) => Break::BlankLine,
+                        None => Break::Line,
+                    });
+                    self.node(item);
+                    last = Some(item);
+                }
+                SyntaxElement::Token(token) => self.token(token),
+            }
+        }
+    }
+
+    /// `{ items }`, from the `{` to the `}`
+    fn block_of_items(&mut self, elements: &[SyntaxElement]) {
+        let (open, items, close) = split_block(elements);
+        self.token(open);
+        self.indent += 1;
+        self.items(items);
+        self.close(close, items.is_empty());
+    }
+
+    /// `{ statements }`, from the `{` to the `}`
+    fn statements(&mut self, elements: &[SyntaxElement]) {
+        let (open, inner, close) = split_block(elements);
+        self.token(open);
+        self.indent += 1;
+
+        let mut statements: Vec<(&[SyntaxElement], Option<&SyntaxToken>)> = vec![];
+        let mut start = 0;
+        for (i, element) in inner.iter().enumerate() {
+            if let SyntaxElement::Token(token) = element {
+                if token.kind() == Token::Semicolon {
+                    statements.push((&inner[start..i], Some(token)));
+                    start = i + 1;
+                }
+            }
+        }
+        if start < inner.len() {
+            statements.push((&inner[start..], None));
+        }
+
+        let count = statements.len();
+        for (i, (statement, semicolon)) in statements.into_iter().enumerate() {
+            self.pending = self.pending.max(Break::Line);
+            self.elements(statement);
+
+            let first = statement.iter().find_map(|element| match element {
+                SyntaxElement::Node(node) => Some(node.kind()),
+                SyntaxElement::Token(_) => None,
+            });
+            let keep = i + 1 < count
+                || match first {
+                    Some(NodeValue::VarAssign | NodeValue::Return) => true,
+                    Some(NodeValue::If) => false,
+                    _ => semicolon.is_some(),
+                };
+            match (semicolon, keep) {
+                (Some(semicolon), true) => self.token(semicolon),
+                (Some(semicolon), false) => {
+                    self.comments(semicolon);
+                }
+                (None, true) => self.text(Token::Semicolon, ";"),
+                (None, false) => {}
+            }
+        }
+
+        self.close(close, count == 0);
+    }
+
+    /// The `}` of a block, with the comments before it still indented
+    fn close(&mut self, close: &SyntaxToken, empty: bool) {
+        let (comments, _) = self.comments(close);
+        self.indent -= 1;
+        if empty && !comments {
+            self.glue = true;
+        } else {
+            self.pending = Break::Line;
+        }
+        self.text(close.kind(), close.text());
+    }
+
+    fn token(&mut self, token: &SyntaxToken) {
+        let (_, blank_line) = self.comments(token);
+        if blank_line && self.pending == Break::Line {
+            self.pending = Break::BlankLine;
+        }
+        self.text(token.kind(), token.text());
+    }
+
+    /// Writes the comments before a token. Returns whether there were any,
+    /// and whether there's a blank line between them and the token.
+    fn comments(&mut self, token: &SyntaxToken) -> (bool, bool) {
+        let mut any = false;
+        let mut lines = 0;
+        let trivia = token.leading_trivia();
+        for (i, comment) in trivia.iter().enumerate() {
+            match comment.kind {
+                Token::LineBreak => lines += line_count(&comment.text),
+                Token::Comment => {
+                    let block = comment.text.starts_with("/*");
+                    // A comment on the line of the last token stays there
+                    if lines == 0 && !self.out.is_empty() {
+                        if !block || space_between(self.last, Token::Comment) {
+                            self.out.push(' ');
+                        }
+                    } else {
+                        self.pending = self.pending.max(match lines > 1 {
+                            true => Break::BlankLine,
+                            false => Break::Line,
+                        });
+                        self.flush();
+                    }
+                    self.out.push_str(comment.text.trim_end());
+                    // A `/* */` with more after it on its line stays inline,
+                    // spaced like a token
+                    let inline = block
+                        && !trivia[i + 1..]
+                            .iter()
+                            .any(|trivia| trivia.kind == Token::LineBreak);
+                    if inline {
+                        self.last = Some(Token::Comment);
+                    } else {
+                        self.pending = self.pending.max(Break::Line);
+                    }
+                    any = true;
+                    lines = 0;
+                }
+                _ => {}
+            }
+        }
+        (any, lines > 1)
+    }
+
+    fn text(&mut self, kind: Token, text: &str) {
+        let glue = std::mem::take(&mut self.glue);
+        if self.pending == Break::None && !glue && space_between(self.last, kind) {
+            self.pending = Break::Space;
+        }
+        self.flush();
+        self.out.push_str(text);
+        self.last = Some(kind);
+    }
+
+    fn flush(&mut self) {
+        match std::mem::take(&mut self.pending) {
+            Break::None => {}
+            Break::Space => self.out.push(' '),
+            _ if self.out.is_empty() => {}
+            pending => {
+                // No blank line right after a `{`
+                if pending == Break::BlankLine && !self.out.ends_with('{') {
+                    self.out.push('\n');
+                }
+                self.out.push('\n');
+                self.out.push_str(&"    ".repeat(self.indent));
+            }
+        }
+    }
+}
+
+/// Whether two tokens on the same line are written with a space between
+fn space_between(last: Option<Token>, next: Token) -> bool {
+    !matches!(
+        (last, next),
+        (None, _)
+            | (
+                _,
+                Token::CloseParen
+                    | Token::CloseBracket
+                    | Token::Comma
+                    | Token::Semicolon
+                    | Token::Colon
+                    | Token::PathSep
+            )
+            | (
+                Some(Token::OpenParen | Token::OpenBracket | Token::Hash | Token::PathSep),
+                _
+            )
+    )
+}
+
+/// Whether an element is the `)` or `]` that ends a list
+fn closes_list(element: Option<&SyntaxElement>) -> bool {
+    matches!(
+        element,
+        Some(SyntaxElement::Token(token))
+            if matches!(token.kind(), Token::CloseParen | Token::CloseBracket)
+    )
+}
+
+/// Items that are formatted as a single line, which don't need a blank line
+/// between them
+fn is_one_line(item: &SyntaxNode) -> bool {
+    match item.kind() {
+        NodeValue::Import | NodeValue::FnDecl => true,
+        NodeValue::Extern => {
+            let has_block = item.children_with_tokens().any(|element| {
+                matches!(element, SyntaxElement::Token(token) if token.kind() == Token::OpenBrace)
+            });
+            !has_block
+                && item
+                    .children()
+                    .last()
+                    .is_some_and(|item| is_one_line(&item))
+        }
+        _ => false,
+    }
+}
+
+/// The `{`, what's between, and the `}` of a block
+fn split_block(elements: &[SyntaxElement]) -> (&SyntaxToken, &[SyntaxElement], &SyntaxToken) {
+    match elements {
+        [SyntaxElement::Token(open), inner @ .., SyntaxElement::Token(close)] => {
+            (open, inner, close)
+        }
+        _ => unreachable!("A block starts with `{{` and ends with `}}`"),
+    }
+}
+
+/// How many lines a line break token ends, `\r\n` being one
+fn line_count(text: &str) -> usize {
+    text.len() - text.matches("\r\n").count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+
+    #[test]
+    fn normalizes_whitespace() {
+        let input = "fn  add(a:int,b :int)->int{a+b*2}";
+        assert_eq!(
+            format_source(input).unwrap(),
+            "fn add(a: int, b: int) -> int {\n    a + b * 2\n}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let input = "// Header\n\nfn main -> int { // Trailing\n  // Own line\n  0 }\n// End\n";
+        assert_eq!(
+            format_source(input).unwrap(),
+            "// Header\n\nfn main -> int { // Trailing\n    // Own line\n    0\n}\n// End\n"
+        );
+    }
+
+    #[test]
+    fn block_comments() {
+        let input = "/* A\n   /* nested */ header */\nfn main(/* none */)->int{ 1 /* one */ + 2 }";
+        assert_eq!(
+            format_source(input).unwrap(),
+            "/* A\n   /* nested */ header */\nfn main(/* none */) -> int {\n    1 /* one */ + 2\n}\n"
+        );
+    }
+
+    #[test]
+    fn last_semicolon() {
+        let input = "fn main -> void { if x { let y = 1 }; return }";
+        assert_eq!(
+            format_source(input).unwrap(),
+            "fn main -> void {\n    if x {\n        let y = 1;\n    };\n    return;\n}\n"
+        );
+        let input = "fn main -> void { f(); if x { g() }; }";
+        assert_eq!(
+            format_source(input).unwrap(),
+            "fn main -> void {\n    f();\n    if x {\n        g()\n    }\n}\n"
+        );
+    }
+}
@@ -1,4 +1,34 @@
+pub mod cst;
+pub mod format;
 pub mod node;
 pub mod token;
 
-pub use node::parser;
+use std::{fmt, ops::Range};
+
+pub use format::format_source;
+pub use node::{parse, parse_all, parse_cst, Node};
+
+/// Where and why a source couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// The token the parser stopped at
+    pub span: Range<usize>,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
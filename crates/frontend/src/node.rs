@@ -1,18 +1,17 @@
-use std::rc::Rc;
+//! The parser, a recursive descent over items and statements, with a Pratt
+//! loop for binary operators. It builds the lossless syntax tree, and the
+//! `Node` tree is derived from that.
 
-use lang_pt::{
-    production::{
-        Concat, EOFProd, List, Node, Nullable, SeparatedList, TokenField, TokenFieldSet, Union,
-    },
-    DefaultParser, NodeImpl,
+use crate::{
+    cst::{Checkpoint, GreenBuilder, GreenToken, SyntaxNode, Trivia},
+    token::{lex, Lexeme, Token},
+    ParseError,
 };
 
-use crate::token::{self, Token};
-
-#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NodeValue {
-    NULL,
+    /// The whole source, only in the syntax tree
+    Root,
     Id,
     Number,
     Add,
@@ -24,10 +23,8 @@ pub enum NodeValue {
     EQ,
     LT,
     LTE,
-    Product,
-    Sum,
-    Expr,
-    Root,
+    /// `lhs op rhs`, with the operator as the middle child
+    Binary,
     VarAssign,
     FnDef,
     FnCall,
@@ -35,209 +32,812 @@ pub enum NodeValue {
     FnCallArgSet,
     Extern,
     FnDecl,
-    BoolExpr,
     True,
     False,
     If,
+    Closure,
+    ClosureParams,
+    ClosureParam,
+    TypeParams,
+    TypeParam,
+    SelfParam,
+    Trait,
+    Impl,
+    Import,
+    Module,
+    Path,
+    Pub,
+    Abi,
+    Attribute,
+    Return,
+}
+
+/// A node of the parse tree, with the byte range of the source it covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub node: NodeValue,
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<Node>,
+    /// The `///` comments before a `FnDef`, `FnDecl` or `Extern`, one line
+    /// each without the `///`. Those before `pub`, an attribute, or an
+    /// `extern` of a single item are on the item too.
+    pub doc: Option<String>,
+}
+
+/// Errors reported before the parser gives up on a source
+const MAX_ERRORS: usize = 100;
+/// How deeply items and expressions can nest, each level is a few calls
+/// deeper in the parser and every pass after it
+const MAX_DEPTH: usize = 256;
+
+/// Parses a whole source into its items
+pub fn parse(source: &str) -> Result<Vec<Node>, ParseError> {
+    Ok(parse_cst(source)?.to_node().children)
 }
 
-impl NodeImpl for NodeValue {
-    fn null() -> Self {
-        Self::NULL
+/// Parses a whole source into its items like [`parse`], carrying on after an
+/// error to report every one
+pub fn parse_all(source: &str) -> Result<Vec<Node>, Vec<ParseError>> {
+    Ok(parse_cst_all(source)?.to_node().children)
+}
+
+/// Parses a whole source into its lossless syntax tree, a `Root` node
+pub fn parse_cst(source: &str) -> Result<SyntaxNode, ParseError> {
+    parse_cst_all(source).map_err(|mut errors| errors.remove(0))
+}
+
+/// Parses a whole source into its lossless syntax tree, or every error in it.
+/// After an error the rest of the statement or item is skipped.
+fn parse_cst_all(source: &str) -> Result<SyntaxNode, Vec<ParseError>> {
+    let lexemes = lex(source).map_err(|error| vec![error])?;
+    let structural = lexemes
+        .iter()
+        .enumerate()
+        .filter(|(_, lexeme)| lexeme.token.is_structural())
+        .map(|(i, _)| i)
+        .collect();
+    let mut parser = Parser {
+        source,
+        lexemes,
+        structural,
+        pos: 0,
+        next: 0,
+        builder: GreenBuilder::default(),
+        errors: vec![],
+        depth: 0,
+    };
+
+    parser.builder.start_node(NodeValue::Root);
+    // The end of file token holds the trivia after the last item
+    let parsed = parser
+        .items(Token::EOF)
+        .and_then(|()| parser.expect(Token::EOF));
+    if let Err(error) = parsed {
+        parser.push_error(error);
+    }
+    if !parser.errors.is_empty() {
+        return Err(parser.errors);
     }
+    parser.builder.finish_node();
+    Ok(SyntaxNode::new_root(parser.builder.finish()))
 }
 
-pub fn parser() -> DefaultParser<NodeValue, Token> {
-    let identifier = Rc::new(TokenField::new(Token::Id, Some(NodeValue::Id)));
-    let number = Rc::new(TokenField::new(Token::Number, Some(NodeValue::Number)));
-    let end_of_file = Rc::new(EOFProd::new(None));
-
-    let add_ops = Rc::new(TokenFieldSet::new(vec![
-        (Token::Add, Some(NodeValue::Add)),
-        (Token::Sub, Some(NodeValue::Sub)),
-    ]));
-    let mul_ops = Rc::new(TokenFieldSet::new(vec![
-        (Token::Mul, Some(NodeValue::Mul)),
-        (Token::Div, Some(NodeValue::Div)),
-    ]));
-    let bool_ops = Rc::new(TokenFieldSet::new(vec![
-        (Token::GT, Some(NodeValue::GT)),
-        (Token::GTE, Some(NodeValue::GTE)),
-        (Token::EQ, Some(NodeValue::EQ)),
-        (Token::LT, Some(NodeValue::LT)),
-        (Token::LTE, Some(NodeValue::LTE)),
-    ]));
-
-    let paren_expr = Rc::new(Concat::init("paren_expr"));
-
-    let value = Rc::new(Union::init("value"));
-
-    let product = Rc::new(SeparatedList::new(&value, &mul_ops, true));
-    let product_node = Rc::new(Node::new(&product, NodeValue::Product));
-
-    let sum = Rc::new(SeparatedList::new(&product_node, &add_ops, true));
-    let sum_node = Rc::new(Node::new(&sum, NodeValue::Sum));
-
-    let bool_expr = Rc::new(SeparatedList::new(&sum_node, &bool_ops, true));
-    let bool_expr_node = Rc::new(Node::new(&bool_expr, NodeValue::BoolExpr));
-
-    let fn_call = Rc::new(Concat::init("call"));
-    let fn_call_node = Rc::new(Node::new(&fn_call, NodeValue::FnCall));
-
-    let fn_decl = Rc::new(Concat::init("fn_declare"));
-    let fn_decl_node = Rc::new(Node::new(&fn_decl, NodeValue::FnDecl));
-
-    let extern_ = Rc::new(Concat::init("extern"));
-    let extern_node = Rc::new(Node::new(&extern_, NodeValue::Extern));
-
-    let let_ = Rc::new(Concat::init("let"));
-    let let_node = Rc::new(Node::new(&let_, NodeValue::VarAssign));
-
-    let fn_def = Rc::new(Concat::init("fn_def"));
-    let fn_def_node = Rc::new(Node::new(&fn_def, NodeValue::FnDef));
-
-    let semicolon = Rc::new(TokenField::new(Token::Semicolon, None));
-
-    let if_expr = Rc::new(Concat::init("if_expr"));
-    let if_expr_node = Rc::new(Node::new(&if_expr, NodeValue::If));
-
-    let expression = Rc::new(Union::new(
-        "expression",
-        vec![
-            if_expr_node.clone(),
-            bool_expr_node.clone(),
-            let_node.clone(),
-            fn_call_node.clone(),
-            fn_def_node.clone(),
-            // sum_node.clone(),
-        ],
-    ));
-    let expr_node = Rc::new(Node::new(&expression, NodeValue::Expr));
-    let exprs = Rc::new(SeparatedList::new(&expr_node, &semicolon, true));
-    let top = Rc::new(Union::new(
-        "top_level",
-        vec![
-            extern_node.clone(),
-            fn_decl_node.clone(),
-            fn_def_node.clone(),
-        ],
-    ));
-    let root = Rc::new(Concat::new(
-        "root",
-        vec![Rc::new(List::new(&top)), end_of_file],
-    ));
-
-    if_expr
-        .set_symbols(vec![
-            Rc::new(TokenField::new(Token::If, None)),
-            bool_expr_node.clone(),
-            Rc::new(TokenField::new(Token::OpenBrace, None)),
-            exprs.clone(),
-            Rc::new(TokenField::new(Token::CloseBrace, None)),
-        ])
-        .unwrap();
-
-    let open_paren = Rc::new(TokenField::new(Token::OpenParen, None));
-    let close_paren = Rc::new(TokenField::new(Token::CloseParen, None));
-    paren_expr
-        .set_symbols(vec![
-            open_paren.clone(),
-            expr_node.clone(),
-            close_paren.clone(),
-        ])
-        .unwrap();
-
-    let typed_identifier = Rc::new(Concat::new(
-        "typed_identifier",
-        vec![
-            identifier.clone(),
-            Rc::new(TokenField::new(Token::Colon, None)),
-            identifier.clone(),
-        ],
-    ));
-
-    let comma = Rc::new(TokenField::new(Token::Comma, None));
-    let fn_def_arg_set = Rc::new(Concat::new(
-        "fn_def_arg_set",
-        vec![
-            open_paren.clone(),
-            Rc::new(SeparatedList::new(&typed_identifier, &comma, false)),
-            close_paren.clone(),
-        ],
-    ));
-    let fn_def_arg_set_node = Rc::new(Node::new(&fn_def_arg_set, NodeValue::FnDefArgSet));
-    fn_def
-        .set_symbols(vec![
-            Rc::new(TokenField::new(Token::Fn, None)),
-            identifier.clone(),
-            Rc::new(Nullable::new(&fn_def_arg_set_node)),
-            Rc::new(TokenField::new(Token::TypeArrow, None)),
-            identifier.clone(),
-            Rc::new(TokenField::new(Token::OpenBrace, None)),
-            exprs.clone(),
-            Rc::new(TokenField::new(Token::CloseBrace, None)),
-        ])
-        .unwrap();
-    let fn_call_arg_set = Rc::new(SeparatedList::new(&expr_node, &comma, false));
-    let fn_call_arg_set_node = Rc::new(Node::new(&fn_call_arg_set, NodeValue::FnCallArgSet));
-    fn_call
-        .set_symbols(vec![
-            identifier.clone(),
-            Rc::new(TokenField::new(Token::OpenParen, None)),
-            Rc::new(Nullable::new(&fn_call_arg_set_node)),
-            Rc::new(TokenField::new(Token::CloseParen, None)),
-        ])
-        .unwrap();
-    let_.set_symbols(vec![
-        Rc::new(TokenField::new(Token::Let, None)),
-        identifier.clone(),
-        Rc::new(TokenField::new(Token::Assign, None)),
-        expr_node.clone(),
-    ])
-    .unwrap();
-    value
-        .set_symbols(vec![
-            fn_call_node.clone(),
-            number,
-            identifier.clone(),
-            paren_expr.clone(),
-            Rc::new(TokenField::new(Token::True, Some(NodeValue::True))),
-            Rc::new(TokenField::new(Token::False, Some(NodeValue::False))),
-        ])
-        .unwrap();
-    fn_decl
-        .set_symbols(vec![
-            Rc::new(TokenField::new(Token::Fn, None)),
-            identifier.clone(),
-            Rc::new(Nullable::new(&fn_def_arg_set_node)),
-            Rc::new(TokenField::new(Token::TypeArrow, None)),
-            identifier.clone(),
-            semicolon.clone(),
-        ])
-        .unwrap();
-    extern_
-        .set_symbols(vec![
-            Rc::new(TokenField::new(Token::Extern, None)),
-            Rc::new(Union::new(
-                "extern_inner",
-                vec![
-                    Rc::new(Concat::new(
-                        "extern_inner_multi",
-                        vec![
-                            Rc::new(TokenField::new(Token::OpenBrace, None)),
-                            Rc::new(List::new(&top)),
-                            Rc::new(TokenField::new(Token::CloseBrace, None)),
-                        ],
-                    )),
-                    top.clone(),
-                ],
-            )),
-        ])
-        .unwrap();
-
-    let mut parser = DefaultParser::new(Rc::new(token::tokenizer()), root).unwrap();
-
-    parser.add_debug_production("value", &value);
-
-    parser
+/// How tightly an operator binds, operators that bind tighter are grouped
+/// first. Every operator is left associative.
+fn binding_power(token: Token) -> Option<(u8, NodeValue)> {
+    Some(match token {
+        Token::GT => (1, NodeValue::GT),
+        Token::GTE => (1, NodeValue::GTE),
+        Token::EQ => (1, NodeValue::EQ),
+        Token::LT => (1, NodeValue::LT),
+        Token::LTE => (1, NodeValue::LTE),
+        Token::Add => (2, NodeValue::Add),
+        Token::Sub => (2, NodeValue::Sub),
+        Token::Mul => (3, NodeValue::Mul),
+        Token::Div => (3, NodeValue::Div),
+        _ => return None,
+    })
+}
+
+type Parsed = Result<(), ParseError>;
+
+struct Parser<'a> {
+    source: &'a str,
+    /// Every token, with trivia
+    lexemes: Vec<Lexeme>,
+    /// Indices of the tokens that aren't trivia
+    structural: Vec<usize>,
+    /// The next token to read, in `structural`
+    pos: usize,
+    /// The first lexeme that isn't in the tree yet
+    next: usize,
+    builder: GreenBuilder,
+    /// Errors recovered from so far
+    errors: Vec<ParseError>,
+    /// How many items and expressions are being parsed, one in another
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn lexeme(&self) -> Lexeme {
+        match self.structural.get(self.pos) {
+            Some(&i) => self.lexemes[i],
+            None => Lexeme {
+                token: Token::EOF,
+                start: self.source.len(),
+                end: self.source.len(),
+            },
+        }
+    }
+
+    fn peek(&self) -> Token {
+        self.lexeme().token
+    }
+
+    fn peek_nth(&self, n: usize) -> Token {
+        self.structural
+            .get(self.pos + n)
+            .map_or(Token::EOF, |&i| self.lexemes[i].token)
+    }
+
+    /// Adds the next token to the open node, with the trivia before it
+    fn bump(&mut self) {
+        let lexeme = self.lexeme();
+        let index = self
+            .structural
+            .get(self.pos)
+            .copied()
+            .unwrap_or(self.lexemes.len());
+        let leading = self.lexemes[self.next..index]
+            .iter()
+            .map(|trivia| Trivia {
+                kind: trivia.token,
+                text: self.source[trivia.start..trivia.end].to_string(),
+            })
+            .collect();
+        self.builder.token(GreenToken {
+            kind: lexeme.token,
+            text: self.source[lexeme.start..lexeme.end].to_string(),
+            leading,
+        });
+        self.next = (index + 1).min(self.lexemes.len());
+        self.pos += 1;
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        let found = self.peek() == token;
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    /// An error at the current token, saying what was expected instead
+    fn expected(&self, expected: impl std::fmt::Display) -> ParseError {
+        let lexeme = self.lexeme();
+        let found = match lexeme.token {
+            Token::EOF => "end of file".to_string(),
+            _ => format!("`{}`", &self.source[lexeme.start..lexeme.end]),
+        };
+        ParseError::new(
+            format!("Expected {expected}, found {found}"),
+            lexeme.start..lexeme.end,
+        )
+    }
+
+    fn expect(&mut self, token: Token) -> Parsed {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.expected(token))
+        }
+    }
+
+    /// Records an error, unless it's at the token of the last one, which
+    /// skipping can leave behind
+    fn push_error(&mut self, error: ParseError) {
+        if self.errors.last().map(|last| &last.span) != Some(&error.span) {
+            self.errors.push(error);
+        }
+    }
+
+    /// Records `error` and closes the nodes opened since `open` were, so the
+    /// caller can skip what's broken and carry on. Past `MAX_ERRORS` the error
+    /// is returned instead, and parsing stops.
+    fn recover(&mut self, error: ParseError, open: usize) -> Parsed {
+        if self.errors.len() + 1 >= MAX_ERRORS {
+            return Err(error);
+        }
+        self.push_error(error);
+        while self.builder.depth() > open {
+            self.builder.finish_node();
+        }
+        Ok(())
+    }
+
+    /// Skips to the next item or the `}` closing the items, moving past the
+    /// token at `start` if the item stopped there
+    fn skip_item(&mut self, start: usize) {
+        if self.pos == start {
+            self.bump();
+        }
+        let mut depth = 0usize;
+        loop {
+            match self.peek() {
+                Token::EOF => return,
+                Token::CloseBrace if depth == 0 => return,
+                Token::Fn
+                | Token::Pub
+                | Token::Extern
+                | Token::Trait
+                | Token::Impl
+                | Token::Mod
+                | Token::Import
+                | Token::Hash
+                    if depth == 0 =>
+                {
+                    return
+                }
+                Token::OpenBrace => depth += 1,
+                Token::CloseBrace => depth -= 1,
+                _ => {}
+            }
+            self.bump();
+        }
+    }
+
+    /// Skips past the next `;`, or up to the `}` closing the block
+    fn skip_statement(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.peek() {
+                Token::EOF => return,
+                Token::CloseBrace if depth == 0 => return,
+                Token::Semicolon if depth == 0 => {
+                    self.bump();
+                    return;
+                }
+                Token::OpenBrace => depth += 1,
+                Token::CloseBrace => depth -= 1,
+                _ => {}
+            }
+            self.bump();
+        }
+    }
+
+    /// Runs `parse` a level deeper, failing past `MAX_DEPTH` rather than
+    /// overflowing the stack
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Parsed) -> Parsed {
+        if self.depth == MAX_DEPTH {
+            return Err(self.too_deep());
+        }
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    fn too_deep(&self) -> ParseError {
+        let lexeme = self.lexeme();
+        ParseError::new(
+            format!("Nested more than {MAX_DEPTH} deep"),
+            lexeme.start..lexeme.end,
+        )
+    }
+
+    /// A node of just the next token
+    fn leaf(&mut self, node: NodeValue) {
+        self.builder.start_node(node);
+        self.bump();
+        self.builder.finish_node();
+    }
+
+    fn ident(&mut self) -> Parsed {
+        if self.peek() != Token::Id {
+            return Err(self.expected(Token::Id));
+        }
+        self.leaf(NodeValue::Id);
+        Ok(())
+    }
+
+    /// `open item, item, ... close`, a trailing separator is allowed
+    fn list(
+        &mut self,
+        open: Token,
+        close: Token,
+        mut item: impl FnMut(&mut Self) -> Parsed,
+    ) -> Parsed {
+        self.expect(open)?;
+        while self.peek() != close {
+            item(self)?;
+            if !self.eat(Token::Comma) {
+                break;
+            }
+        }
+        if self.peek() != close {
+            return Err(self.expected(format!("`,` or {close}")));
+        }
+        self.bump();
+        Ok(())
+    }
+
+    fn items(&mut self, close: Token) -> Parsed {
+        while self.peek() != close && self.peek() != Token::EOF {
+            let (open, start) = (self.builder.depth(), self.pos);
+            if let Err(error) = self.item() {
+                self.recover(error, open)?;
+                self.skip_item(start);
+            }
+        }
+        Ok(())
+    }
+
+    fn item(&mut self) -> Parsed {
+        self.nested(Self::item_kind)
+    }
+
+    fn item_kind(&mut self) -> Parsed {
+        match self.peek() {
+            // `#[name]` or `#[name(arg, ...)]`, wrapping the item after it
+            Token::Hash => {
+                self.builder.start_node(NodeValue::Attribute);
+                self.bump();
+                self.expect(Token::OpenBracket)?;
+                self.ident()?;
+                if self.peek() == Token::OpenParen {
+                    self.list(Token::OpenParen, Token::CloseParen, Self::ident)?;
+                }
+                self.expect(Token::CloseBracket)?;
+                self.item()?;
+            }
+            Token::Import => {
+                self.builder.start_node(NodeValue::Import);
+                self.bump();
+                self.ident()?;
+                self.expect(Token::Semicolon)?;
+            }
+            Token::Mod => {
+                self.builder.start_node(NodeValue::Module);
+                self.bump();
+                self.ident()?;
+                self.block_of_items()?;
+            }
+            // `extern "C"`, the ABI is optional, then a block or a single item
+            Token::Extern => {
+                self.builder.start_node(NodeValue::Extern);
+                self.bump();
+                if self.peek() == Token::String {
+                    self.leaf(NodeValue::Abi);
+                }
+                if self.peek() == Token::OpenBrace {
+                    self.block_of_items()?;
+                } else {
+                    self.item()?;
+                }
+            }
+            Token::Trait => {
+                self.builder.start_node(NodeValue::Trait);
+                self.bump();
+                self.ident()?;
+                self.expect(Token::OpenBrace)?;
+                while !self.eat(Token::CloseBrace) {
+                    self.function(false)?;
+                }
+            }
+            Token::Impl => {
+                self.builder.start_node(NodeValue::Impl);
+                self.bump();
+                self.ident()?;
+                self.expect(Token::For)?;
+                self.ident()?;
+                self.expect(Token::OpenBrace)?;
+                while !self.eat(Token::CloseBrace) {
+                    self.function(true)?;
+                }
+            }
+            Token::Pub => {
+                self.builder.start_node(NodeValue::Pub);
+                self.bump();
+                self.function(true)?;
+            }
+            Token::Fn => {
+                // A declaration ends in `;` where a definition has a body
+                let is_decl = self.structural[self.pos..]
+                    .iter()
+                    .map(|&i| self.lexemes[i].token)
+                    .find(|token| matches!(token, Token::Semicolon | Token::OpenBrace))
+                    == Some(Token::Semicolon);
+                return self.function(!is_decl);
+            }
+            _ => return Err(self.expected("an item")),
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    fn block_of_items(&mut self) -> Parsed {
+        self.expect(Token::OpenBrace)?;
+        self.items(Token::CloseBrace)?;
+        self.expect(Token::CloseBrace)
+    }
+
+    /// `fn name<T: Bound>(args) -> ret { body }`, or `fn name(args) -> ret;`
+    /// without a body. The type and value parameters are always children,
+    /// empty if they weren't written.
+    fn function(&mut self, body: bool) -> Parsed {
+        self.builder.start_node(match body {
+            true => NodeValue::FnDef,
+            false => NodeValue::FnDecl,
+        });
+        self.expect(Token::Fn)?;
+        self.ident()?;
+
+        if body {
+            self.builder.start_node(NodeValue::TypeParams);
+            if self.peek() == Token::LT {
+                self.list(Token::LT, Token::GT, Self::type_param)?;
+            }
+            self.builder.finish_node();
+        }
+
+        self.builder.start_node(NodeValue::FnDefArgSet);
+        if self.peek() == Token::OpenParen {
+            self.list(Token::OpenParen, Token::CloseParen, |p| {
+                if p.peek() == Token::SelfValue {
+                    p.leaf(NodeValue::SelfParam);
+                    return Ok(());
+                }
+                p.ident()?;
+                p.expect(Token::Colon)?;
+                p.ident()
+            })?;
+        }
+        self.builder.finish_node();
+
+        self.expect(Token::TypeArrow)?;
+        self.ident()?;
+
+        if body {
+            self.block()?;
+        } else {
+            self.expect(Token::Semicolon)?;
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// `T` or `T: Bound + OtherBound`
+    fn type_param(&mut self) -> Parsed {
+        self.builder.start_node(NodeValue::TypeParam);
+        self.ident()?;
+        if self.eat(Token::Colon) {
+            self.ident()?;
+            while self.eat(Token::Add) {
+                self.ident()?;
+            }
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// `{ expr; expr; ... }`, the last `;` is optional
+    fn block(&mut self) -> Parsed {
+        self.expect(Token::OpenBrace)?;
+        while !matches!(self.peek(), Token::CloseBrace | Token::EOF) {
+            let open = self.builder.depth();
+            if let Err(error) = self.statement() {
+                self.recover(error, open)?;
+                self.skip_statement();
+            }
+        }
+        self.expect(Token::CloseBrace)
+    }
+
+    /// An expression, and the `;` after it unless it ends the block
+    fn statement(&mut self) -> Parsed {
+        self.expr()?;
+        match self.peek() {
+            Token::Semicolon => self.bump(),
+            Token::CloseBrace => {}
+            _ => return Err(self.expected("`;` or `}`")),
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Parsed {
+        self.nested(Self::expr_kind)
+    }
+
+    fn expr_kind(&mut self) -> Parsed {
+        match self.peek() {
+            Token::If => {
+                self.builder.start_node(NodeValue::If);
+                self.bump();
+                self.binary(0)?;
+                self.block()?;
+            }
+            // `return` or `return value`
+            Token::Return => {
+                self.builder.start_node(NodeValue::Return);
+                self.bump();
+                if !matches!(
+                    self.peek(),
+                    Token::Semicolon | Token::CloseBrace | Token::EOF
+                ) {
+                    self.expr()?;
+                }
+            }
+            Token::Pipe => {
+                self.builder.start_node(NodeValue::Closure);
+                self.builder.start_node(NodeValue::ClosureParams);
+                self.list(Token::Pipe, Token::Pipe, |p| {
+                    p.builder.start_node(NodeValue::ClosureParam);
+                    p.ident()?;
+                    if p.eat(Token::Colon) {
+                        p.ident()?;
+                    }
+                    p.builder.finish_node();
+                    Ok(())
+                })?;
+                self.builder.finish_node();
+                self.expr()?;
+            }
+            Token::Let => {
+                self.builder.start_node(NodeValue::VarAssign);
+                self.bump();
+                self.ident()?;
+                self.expect(Token::Assign)?;
+                self.expr()?;
+            }
+            // Not allowed here, but parsed so lowering can say so
+            Token::Fn => return self.function(true),
+            _ => return self.binary(0),
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Operands separated by operators that bind at least as tightly as
+    /// `min_power`
+    fn binary(&mut self, min_power: u8) -> Parsed {
+        let lhs = self.builder.checkpoint();
+        self.operand()?;
+        // Each operator wraps everything before it, so a chain is as deep as
+        // it is long
+        let depth = self.depth;
+        let parsed = self.operators(lhs, min_power);
+        self.depth = depth;
+        parsed
+    }
+
+    /// The operators after an operand that bind at least as tightly as
+    /// `min_power`, and their right hand sides
+    fn operators(&mut self, lhs: Checkpoint, min_power: u8) -> Parsed {
+        while let Some((power, op)) = binding_power(self.peek()) {
+            if power < min_power {
+                break;
+            }
+            if self.depth == MAX_DEPTH {
+                return Err(self.too_deep());
+            }
+            self.depth += 1;
+            self.builder.start_node_at(lhs, NodeValue::Binary);
+            self.leaf(op);
+            self.binary(power + 1)?;
+            self.builder.finish_node();
+        }
+        Ok(())
+    }
+
+    fn operand(&mut self) -> Parsed {
+        match self.peek() {
+            Token::Number => self.leaf(NodeValue::Number),
+            Token::True => self.leaf(NodeValue::True),
+            Token::False => self.leaf(NodeValue::False),
+            Token::SelfValue => self.leaf(NodeValue::Id),
+            Token::Id if matches!(self.peek_nth(1), Token::OpenParen | Token::PathSep) => {
+                return self.call()
+            }
+            Token::Id => self.leaf(NodeValue::Id),
+            // The parentheses are tokens of the node around them
+            Token::OpenParen => {
+                self.bump();
+                self.expr()?;
+                return self.expect(Token::CloseParen);
+            }
+            _ => return Err(self.expected("an expression")),
+        }
+        Ok(())
+    }
+
+    /// `name(args)` or `module::name(args)`
+    fn call(&mut self) -> Parsed {
+        self.builder.start_node(NodeValue::FnCall);
+        self.builder.start_node(NodeValue::Path);
+        self.ident()?;
+        while self.eat(Token::PathSep) {
+            self.ident()?;
+        }
+        self.builder.finish_node();
+
+        self.builder.start_node(NodeValue::FnCallArgSet);
+        self.list(Token::OpenParen, Token::CloseParen, Self::expr)?;
+        self.builder.finish_node();
+        self.builder.finish_node();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_all, Node, NodeValue};
+
+    fn body(input: &str) -> Vec<Node> {
+        let items = parse(input).unwrap();
+        items[0].children[4..].to_vec()
+    }
+
+    /// The operators of a binary expression, in prefix order
+    fn ops(node: &Node) -> String {
+        match node.node {
+            NodeValue::Binary => format!(
+                "({:?} {} {})",
+                node.children[1].node,
+                ops(&node.children[0]),
+                ops(&node.children[2])
+            ),
+            _ => "_".to_string(),
+        }
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        let body = body("fn main -> int { 1 - 2 + 3 * 4 / 5 < 6 }");
+        assert_eq!(ops(&body[0]), "(LT (Add (Sub _ _) (Div (Mul _ _) _)) _)");
+    }
+
+    #[test]
+    fn parentheses_group() {
+        let body = body("fn main -> int { 1 - (2 - 3) }");
+        assert_eq!(ops(&body[0]), "(Sub _ (Sub _ _))");
+    }
+
+    #[test]
+    fn items() {
+        let input = "
+        import math;
+        #[allow(dead_code)]
+        extern \"C\" {
+            fn puts -> int;
+        }
+        trait Double {
+            fn double(self) -> Self;
+        }
+        impl Double for int {
+            fn double(self) -> int { self * 2 }
+        }
+        pub fn twice<T: Double>(x: T) -> T {
+            let f = |y| double(y);
+            math::id(f(x));
+            return x
+        }
+        ";
+        let kinds = parse(input)
+            .unwrap()
+            .iter()
+            .map(|item| item.node)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                NodeValue::Import,
+                NodeValue::Attribute,
+                NodeValue::Trait,
+                NodeValue::Impl,
+                NodeValue::Pub
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cover_the_source() {
+        let input = "fn main -> int { add(1, 2) }";
+        let call = &body(input)[0];
+        assert_eq!(call.node, NodeValue::FnCall);
+        assert_eq!(&input[call.start..call.end], "add(1, 2)");
+    }
+
+    #[test]
+    fn doc_comments() {
+        let input = "
+        /// Adds
+        ///two numbers
+        //// Not a doc comment
+        fn add(a: int, b: int) -> int { a + b }
+
+        /// Imported
+        #[allow(dead_code)]
+        extern \"C\" fn putchar(c: int) -> int;
+
+        /// Only on the block
+        extern {
+            /// Writes a string
+            pub fn puts(s: int) -> int { 0 }
+        }
+        ";
+        let items = parse(input).unwrap();
+        assert_eq!(items[0].doc.as_deref(), Some("Adds\ntwo numbers"));
+
+        let putchar = items[1].children.last().unwrap();
+        assert_eq!(putchar.doc.as_deref(), Some("Imported"));
+        assert_eq!(putchar.children[1].doc.as_deref(), Some("Imported"));
+
+        assert_eq!(items[2].doc.as_deref(), Some("Only on the block"));
+        let puts = &items[2].children[0].children[0];
+        assert_eq!(puts.doc.as_deref(), Some("Writes a string"));
+    }
+
+    #[test]
+    fn recovers_after_errors() {
+        let input = "
+        fn main -> int {
+            let a = ;
+            if a { let b = * 2; };
+            let c = \"}\"; // }
+            1
+        }
+        fn -> int { 2 }
+        fn other -> int { 3 }
+        ";
+        let errors = parse_all(input).unwrap_err();
+        let messages = errors
+            .iter()
+            .map(|error| error.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "Expected an expression, found `;`",
+                "Expected an expression, found `*`",
+                "Expected an expression, found `\"}\"`",
+                "Expected an identifier, found `->`",
+            ]
+        );
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth: usize| {
+            format!(
+                "fn main -> int {{ {}1{} }}",
+                "(".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        assert!(parse(&nested(200)).is_ok());
+
+        let error = parse(&nested(100_000)).unwrap_err();
+        assert_eq!(error.message, "Nested more than 256 deep");
+
+        let error = parse(&"mod m { ".repeat(1_000)).unwrap_err();
+        assert_eq!(error.message, "Nested more than 256 deep");
+
+        // Operators nest what comes before them, without recursing
+        let chain = |terms: usize| format!("fn main -> int {{ 1{} }}", "+1".repeat(terms - 1));
+        assert!(parse(&chain(200)).is_ok());
+        let error = parse(&chain(100_000)).unwrap_err();
+        assert_eq!(error.message, "Nested more than 256 deep");
+    }
+
+    #[test]
+    fn expected_found() {
+        let input = "fn main -> int {\n    let a = 1\n    let b = 2;\n}";
+        let error = parse(input).unwrap_err();
+        assert_eq!(error.message, "Expected `;` or `}`, found `let`");
+        assert_eq!(&input[error.span], "let");
+
+        let error = parse("fn main -> int { 1").unwrap_err();
+        assert_eq!(error.message, "Expected `;` or `}`, found end of file");
+    }
 }
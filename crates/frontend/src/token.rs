@@ -1,9 +1,6 @@
-use std::rc::Rc;
+use std::fmt;
 
-use lang_pt::{
-    lexeme::{Mapper, Pattern, Punctuations},
-    TokenImpl, Tokenizer,
-};
+use crate::ParseError;
 
 #[allow(dead_code)]
 #[derive(Debug, Hash, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
@@ -44,59 +41,64 @@ pub enum Token {
     EQ,
     LT,
     LTE,
-    // Comment
+    Pipe,
+    Trait,
+    Impl,
+    SelfValue,
+    Import,
+    Mod,
+    PathSep,
+    Pub,
+    String,
+    Hash,
+    Return,
+    /// `// ...` to the end of the line, or `/* ... */`, which can nest
     Comment,
 }
 
-impl TokenImpl for Token {
-    fn eof() -> Self {
-        Self::EOF
+impl Token {
+    /// Whitespace and comments are kept by the lexer, but skipped by the parser
+    pub fn is_structural(&self) -> bool {
+        !matches!(self, Token::Space | Token::LineBreak | Token::Comment)
     }
 
-    fn is_structural(&self) -> bool {
-        match self {
-            Token::Space | Token::LineBreak | Token::Comment => false,
-            _ => true,
-        }
+    fn keyword(word: &str) -> Option<Self> {
+        Some(match word {
+            "if" => Token::If,
+            "else" => Token::Else,
+            "while" => Token::While,
+            "for" => Token::For,
+            "true" => Token::True,
+            "false" => Token::False,
+            "null" => Token::Null,
+            "undefined" => Token::Undefined,
+            "let" => Token::Let,
+            "fn" => Token::Fn,
+            "extern" => Token::Extern,
+            "trait" => Token::Trait,
+            "impl" => Token::Impl,
+            "self" => Token::SelfValue,
+            "import" => Token::Import,
+            "mod" => Token::Mod,
+            "pub" => Token::Pub,
+            "return" => Token::Return,
+            _ => return None,
+        })
     }
-}
 
-pub fn tokenizer() -> Tokenizer<Token> {
-    // (\/\/.*)|(\/\*[^]*\*\/)
-    let comment: Pattern<Token> = Pattern::new(Token::Comment, r#"//.*"#).unwrap();
-
-    let identifier: Pattern<Token> = Pattern::new(Token::Id, r#"^[_$a-zA-Z][_$\w]*"#).unwrap();
-    let mapping_identifier = Mapper::new(
-        identifier,
-        vec![
-            ("if", Token::If),
-            ("else", Token::Else),
-            ("while", Token::While),
-            ("for", Token::For),
-            ("true", Token::True),
-            ("false", Token::False),
-            ("null", Token::Null),
-            ("undefined", Token::Undefined),
-            ("let", Token::Let),
-            ("fn", Token::Fn),
-            ("extern", Token::Extern),
-        ],
-    )
-    .unwrap();
-    let number_literal =
-        Pattern::new(Token::Number, r"^(0|[\d--0]\d*)(\.\d+)?([eE][+-]?\d+)?").unwrap();
-    let non_break_space: Pattern<Token> = Pattern::new(Token::Space, r"^[^\S\r\n]+").unwrap();
-    let line_break: Pattern<Token> = Pattern::new(Token::LineBreak, r"^[\r\n]+").unwrap();
-    let expression_punctuations: Punctuations<Token> = Punctuations::new(vec![
+    /// Punctuation, longest first so `<=` isn't read as `<` and `=`
+    const PUNCTUATION: &'static [(&'static str, Token)] = &[
+        ("<=", Token::LTE),
+        (">=", Token::GTE),
+        ("==", Token::EQ),
+        ("->", Token::TypeArrow),
+        ("::", Token::PathSep),
         ("+", Token::Add),
         ("-", Token::Sub),
         ("*", Token::Mul),
         ("/", Token::Div),
         ("<", Token::LT),
-        ("<=", Token::LTE),
         (">", Token::GT),
-        (">=", Token::GTE),
-        ("==", Token::EQ),
         ("=", Token::Assign),
         ("{", Token::OpenBrace),
         ("}", Token::CloseBrace),
@@ -105,43 +107,244 @@ pub fn tokenizer() -> Tokenizer<Token> {
         ("[", Token::OpenBracket),
         ("]", Token::CloseBracket),
         (";", Token::Semicolon),
-        ("->", Token::TypeArrow),
         (":", Token::Colon),
         (",", Token::Comma),
-    ])
-    .unwrap();
-
-    let tokenizer = Tokenizer::new(vec![
-        Rc::new(comment),
-        Rc::new(non_break_space),
-        Rc::new(mapping_identifier),
-        Rc::new(number_literal),
-        Rc::new(expression_punctuations),
-        Rc::new(line_break),
-    ]);
-
-    tokenizer
+        ("|", Token::Pipe),
+        ("#", Token::Hash),
+    ];
+}
+
+/// How a token is named in "expected" messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Id => return write!(f, "an identifier"),
+            Token::Number => return write!(f, "a number"),
+            Token::String => return write!(f, "a string"),
+            Token::Space | Token::LineBreak => return write!(f, "whitespace"),
+            Token::Comment => return write!(f, "a comment"),
+            Token::EOF => return write!(f, "end of file"),
+            Token::If => "if",
+            Token::Else => "else",
+            Token::While => "while",
+            Token::For => "for",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Let => "let",
+            Token::Null => "null",
+            Token::Fn => "fn",
+            Token::Undefined => "undefined",
+            Token::Extern => "extern",
+            Token::Trait => "trait",
+            Token::Impl => "impl",
+            Token::SelfValue => "self",
+            Token::Import => "import",
+            Token::Mod => "mod",
+            Token::Pub => "pub",
+            Token::Return => "return",
+            punctuation => Token::PUNCTUATION
+                .iter()
+                .find(|(_, token)| token == punctuation)
+                .map(|(text, _)| *text)
+                .unwrap_or_default(),
+        };
+        write!(f, "`{text}`")
+    }
+}
+
+/// A token and the bytes of the source it was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lexeme {
+    pub token: Token,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Splits a source into tokens, including whitespace and comments
+pub fn lex(source: &str) -> Result<Vec<Lexeme>, ParseError> {
+    let mut lexemes = vec![];
+    let mut pos = 0;
+    while let Some(c) = source[pos..].chars().next() {
+        let start = pos;
+        let rest = &source[pos..];
+        let token = if rest.starts_with("//") {
+            pos += rest.find(['\r', '\n']).unwrap_or(rest.len());
+            Token::Comment
+        } else if rest.starts_with("/*") {
+            pos += block_comment_len(rest)
+                .ok_or_else(|| ParseError::new("Unterminated comment", start..source.len()))?;
+            Token::Comment
+        } else if c == '\r' || c == '\n' {
+            pos += len_while(rest, |c| c == '\r' || c == '\n');
+            Token::LineBreak
+        } else if c.is_whitespace() {
+            pos += len_while(rest, |c| c.is_whitespace() && c != '\r' && c != '\n');
+            Token::Space
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            pos += len_while(rest, |c| c.is_alphanumeric() || c == '_' || c == '$');
+            Token::keyword(&source[start..pos]).unwrap_or(Token::Id)
+        } else if c.is_ascii_digit() {
+            pos += number_len(rest);
+            Token::Number
+        } else if c == '"' {
+            let len = rest[1..]
+                .find(['"', '\r', '\n'])
+                .filter(|&i| rest[1 + i..].starts_with('"'))
+                .ok_or_else(|| ParseError::new("Unterminated string", start..source.len()))?;
+            pos += len + 2;
+            Token::String
+        } else {
+            let (text, token) = Token::PUNCTUATION
+                .iter()
+                .find(|(text, _)| rest.starts_with(text))
+                .ok_or_else(|| {
+                    ParseError::new(
+                        format!("Unexpected character `{c}`"),
+                        start..start + c.len_utf8(),
+                    )
+                })?;
+            pos += text.len();
+            *token
+        };
+        lexemes.push(Lexeme {
+            token,
+            start,
+            end: pos,
+        });
+    }
+
+    Ok(lexemes)
+}
+
+fn len_while(s: &str, f: impl Fn(char) -> bool) -> usize {
+    s.find(|c| !f(c)).unwrap_or(s.len())
+}
+
+/// The length of the `/* ... */` at the start of `s`, with any nested
+/// comments, or `None` if it isn't closed
+fn block_comment_len(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        if s[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if s[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += s[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+/// `0` or digits not starting with `0`, then an optional fraction and
+/// exponent. Fractions are read so they can be rejected as invalid integers.
+fn number_len(s: &str) -> usize {
+    let digits = |s: &str| len_while(s, |c| c.is_ascii_digit());
+    let mut len = if s.starts_with('0') { 1 } else { digits(s) };
+
+    let rest = &s[len..];
+    if rest.starts_with('.') && digits(&rest[1..]) > 0 {
+        len += 1 + digits(&rest[1..]);
+    }
+
+    let rest = &s[len..];
+    if rest.starts_with(['e', 'E']) {
+        let sign = usize::from(rest[1..].starts_with(['+', '-']));
+        let exponent = digits(&rest[1 + sign..]);
+        if exponent > 0 {
+            len += 1 + sign + exponent;
+        }
+    }
+
+    len
 }
 
 #[cfg(test)]
 mod tests {
-    // These have been removed
-    #[ignore]
+    use super::{lex, Token};
+
+    fn tokens(input: &str) -> Vec<Token> {
+        lex(input)
+            .unwrap()
+            .into_iter()
+            .map(|lexeme| lexeme.token)
+            .filter(Token::is_structural)
+            .collect()
+    }
+
     #[test]
     fn multi_line_comment() {
         let input = "/*
             Hello, World!
         */fn other_func -> void { other_func() }";
-        // let input = "/**/
-        // fn other_func -> void { 0 }";
-        let parser = crate::parser();
-        parser.parse(input.as_bytes()).unwrap();
+        crate::parse(input).unwrap();
+        let input = "/**/
+        fn other_func -> void { 0 }";
+        crate::parse(input).unwrap();
+    }
+
+    #[test]
+    fn nested_comment() {
+        assert_eq!(
+            tokens("a /* b /* c */ d */ e /* f */"),
+            [Token::Id, Token::Id]
+        );
+
+        let error = lex("a /* b /* c */ d").unwrap_err();
+        assert_eq!(error.message, "Unterminated comment");
+        assert_eq!(error.span, 2..16);
     }
 
     #[test]
     fn single_line_comment() {
         let input = "// Hello, World!
         fn other_func -> void { other_func() }";
-        crate::parser().parse(input.as_bytes()).unwrap();
+        crate::parse(input).unwrap();
+    }
+
+    #[test]
+    fn longest_punctuation() {
+        assert_eq!(
+            tokens("a <= b->c::d"),
+            [
+                Token::Id,
+                Token::LTE,
+                Token::Id,
+                Token::TypeArrow,
+                Token::Id,
+                Token::PathSep,
+                Token::Id
+            ]
+        );
+    }
+
+    #[test]
+    fn keywords_and_numbers() {
+        assert_eq!(
+            tokens("let letter = 10 1.5 007"),
+            [
+                Token::Let,
+                Token::Id,
+                Token::Assign,
+                Token::Number,
+                Token::Number,
+                Token::Number,
+                Token::Number,
+                Token::Number
+            ]
+        );
+    }
+
+    #[test]
+    fn unexpected_character() {
+        let error = lex("fn main -> int { 1 @ 2 }").unwrap_err();
+        assert_eq!(error.message, "Unexpected character `@`");
+        assert_eq!(error.span, 19..20);
     }
 }
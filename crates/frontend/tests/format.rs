@@ -0,0 +1,60 @@
+//! Every file in `tests/format/input` has to format to the file of the same
+//! name in `tests/format/expected`, which has to format to itself
+
+use std::fs::{read_dir, read_to_string};
+
+use frontend::{format_source, parse, Node};
+
+/// The kinds of a tree, without spans, which formatting changes
+fn shape(node: &Node) -> String {
+    let children: String = node
+        .children
+        .iter()
+        .map(|child| shape(child) + " ")
+        .collect();
+    format!("({:?} {children})", node.node)
+}
+
+fn shapes(source: &str) -> Vec<String> {
+    parse(source).unwrap().iter().map(shape).collect()
+}
+
+#[test]
+fn golden_corpus() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/format");
+    let mut paths = read_dir(format!("{dir}/input"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let input = read_to_string(&path).unwrap();
+        let expected = read_to_string(format!(
+            "{dir}/expected/{}",
+            path.file_name().unwrap().to_string_lossy()
+        ))
+        .unwrap();
+
+        let formatted = format_source(&input).unwrap();
+        assert_eq!(
+            formatted,
+            expected,
+            "{} formatted differently",
+            path.display()
+        );
+        assert_eq!(
+            format_source(&formatted).unwrap(),
+            formatted,
+            "{} isn't formatted the same twice",
+            path.display()
+        );
+        assert_eq!(
+            shapes(&input),
+            shapes(&formatted),
+            "{} changed meaning",
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,520 @@
+//! What the server knows about a document, worked out by the compiler passes
+//!
+//! The passes borrow the source, so everything the server needs is copied
+//! out of them into owned tables: what each name refers to, the type of each
+//! variable, and the items for the outline.
+
+use std::{collections::HashMap, path::Path};
+
+use lqdc_common::{
+    codepass::CodePass,
+    emit::to_json,
+    hir::{Expr, ExprKind, FnSig, Ident, Item, ItemKind, NodeId, Param, Span},
+    lower::LowerPass,
+    make_signatures::{qualify, MakeSignaturesPass, Signatures},
+    modules,
+    parsepass::ParsePass,
+    source_map::{FileId, SourceMap},
+    type_::Type,
+    type_check::TypeCheckPass,
+};
+use miette::Report;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub code: Option<String>,
+    pub help: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Function,
+    Variable,
+    Module,
+    Trait,
+    Impl,
+}
+
+/// Something with a name, which uses of the name go to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub kind: Kind,
+    /// The name where it's defined
+    pub span: Span,
+    /// The signature of a function, or the name and type of a variable
+    pub detail: String,
+}
+
+/// An item in the outline of a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: Kind,
+    /// The whole item
+    pub span: Span,
+    /// The name of the item
+    pub name_span: Span,
+    pub detail: Option<String>,
+    pub children: Vec<Symbol>,
+}
+
+#[derive(Default)]
+pub struct Analysis {
+    /// The document, then every file it imports
+    pub source_map: SourceMap,
+    pub diagnostics: Vec<Diagnostic>,
+    pub definitions: Vec<Definition>,
+    /// Each use of a name, and the definition it refers to
+    pub references: Vec<(Span, usize)>,
+    pub symbols: Vec<Symbol>,
+    /// Every function that can be called from the root module, by path, and
+    /// its definition
+    pub functions: Vec<(String, usize)>,
+}
+
+impl Analysis {
+    /// Analyses `source`, the contents of the file at `path`. The files it
+    /// imports are read from next to it.
+    pub fn new(path: &Path, source: &str) -> Self {
+        // Without the imports, the missing modules are reported by the passes
+        let source_map = modules::load_source(path, source.to_string())
+            .unwrap_or_else(|_| SourceMap::new(path.display().to_string(), source));
+        let mut analysis = Analysis::default();
+        analysis.run(&source_map.source, source.len());
+        analysis.source_map = source_map;
+        analysis
+    }
+
+    /// Runs the passes over the combined source, of which the document is
+    /// the first `len` bytes
+    fn run(&mut self, source: &str, len: usize) {
+        let items = match ParsePass::pass((), source, &mut ())
+            .and_then(|parsed| LowerPass::pass(parsed, source, &mut ()))
+        {
+            Ok(lowered) => lowered.items,
+            Err(error) => return self.report(error, len),
+        };
+
+        // Type checking takes the signatures, but they're needed even if it fails
+        let make_signatures = || {
+            MakeSignaturesPass::pass(
+                LowerPass {
+                    items: items.clone(),
+                },
+                source,
+                &mut (),
+            )
+        };
+        let signatures = match make_signatures() {
+            Ok(pass) => pass.signatures,
+            Err(error) => {
+                self.report(error, len);
+                Signatures::default()
+            }
+        };
+        let types =
+            match make_signatures().and_then(|pass| TypeCheckPass::pass(pass, source, &mut ())) {
+                Ok(pass) => pass
+                    .types
+                    .into_iter()
+                    .map(|((_, id), type_)| (id, type_))
+                    .collect(),
+                Err(error) => {
+                    self.report(error, len);
+                    HashMap::new()
+                }
+            };
+
+        let mut collector = Collector {
+            analysis: self,
+            signatures: &signatures,
+            types,
+            by_signature: HashMap::new(),
+            calls: vec![],
+            scope: vec![],
+            type_params: vec![],
+            module: String::new(),
+        };
+        let symbols = collector.items(&items);
+        let Collector {
+            by_signature,
+            calls,
+            ..
+        } = collector;
+
+        for (span, signature) in calls {
+            if let Some(&definition) = by_signature.get(&signature) {
+                self.references.push((span, definition));
+            }
+        }
+        let mut functions: Vec<_> = signatures
+            .functions
+            .iter()
+            .filter_map(|(path, function)| {
+                Some((path.clone(), *by_signature.get(&function.span.start)?))
+            })
+            .collect();
+        functions.sort();
+        self.functions = functions;
+
+        // Imported files are in `mod` blocks after the document
+        self.symbols = symbols
+            .into_iter()
+            .filter(|symbol| symbol.span.start < len)
+            .collect();
+    }
+
+    /// Adds the diagnostics for an error, leaving out those in imported
+    /// files, which are reported when the file is opened
+    fn report(&mut self, error: Report, len: usize) {
+        for value in to_json(&*error, None) {
+            let span = value["spans"]
+                .as_array()
+                .and_then(|spans| spans.first())
+                .map(|span| {
+                    let offset = |key: &str| span[key].as_u64().unwrap_or_default() as usize;
+                    Span::new(offset("start"), offset("end"))
+                });
+            let code = value["code"].as_str().map(str::to_string);
+            // The summary of several errors, which are reported one by one
+            if span.is_none() && code.is_none() || span.is_some_and(|span| span.start > len) {
+                continue;
+            }
+            self.diagnostics.push(Diagnostic {
+                span: span.unwrap_or(Span::new(0, 0)),
+                message: value["message"].as_str().unwrap_or_default().to_string(),
+                code,
+                help: value["help"].as_str().map(str::to_string),
+            });
+        }
+    }
+
+    /// The definition of the name at an offset, and the span of the name
+    pub fn definition_at(&self, offset: usize) -> Option<(&Definition, Span)> {
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+        let (span, definition) = self
+            .references
+            .iter()
+            .filter(|(span, _)| contains(span))
+            .map(|(span, definition)| (*span, *definition))
+            .chain(
+                self.definitions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, definition)| contains(&definition.span))
+                    .map(|(i, definition)| (definition.span, i)),
+            )
+            .min_by_key(|(span, _)| span.end - span.start)?;
+        Some((&self.definitions[definition], span))
+    }
+
+    /// The file a span is in, and the span relative to the start of the file
+    pub fn locate(&self, span: Span) -> Option<(FileId, Span)> {
+        let (file, start) = self.source_map.lookup(span.start)?;
+        Some((file, Span::new(start, start + (span.end - span.start))))
+    }
+}
+
+/// Walks the items, recording definitions and what each name refers to
+struct Collector<'a, 'input> {
+    analysis: &'a mut Analysis,
+    signatures: &'a Signatures<'input>,
+    types: HashMap<NodeId, Type>,
+    /// Functions by where their signature starts, which is how the
+    /// signature table finds them
+    by_signature: HashMap<usize, usize>,
+    /// Calls, and where the signature of the function called starts. They're
+    /// resolved once every function is defined.
+    calls: Vec<(Span, usize)>,
+    /// Variables in scope, innermost last
+    scope: Vec<(&'input str, usize)>,
+    /// Type parameters of the function being walked
+    type_params: Vec<&'input str>,
+    module: String,
+}
+
+impl<'input> Collector<'_, 'input> {
+    fn define(&mut self, name: Ident, kind: Kind, detail: String) -> usize {
+        self.analysis.definitions.push(Definition {
+            name: name.name.to_string(),
+            kind,
+            span: name.span,
+            detail,
+        });
+        self.analysis.definitions.len() - 1
+    }
+
+    fn items(&mut self, items: &[Item<'input>]) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        for item in items {
+            match &item.kind {
+                ItemKind::Import(_) => {}
+                ItemKind::Module { name, items } => {
+                    self.define(*name, Kind::Module, format!("mod {}", name.name));
+                    let parent = self.module.clone();
+                    self.module = qualify(&parent, name.name);
+                    let children = self.items(items);
+                    self.module = parent;
+                    symbols.push(symbol(*name, Kind::Module, item.span, None, children));
+                }
+                ItemKind::Extern { items, .. } => symbols.extend(self.items(items)),
+                ItemKind::Trait { name, methods } => {
+                    self.define(*name, Kind::Trait, format!("trait {}", name.name));
+                    let children = methods
+                        .iter()
+                        .map(|method| self.function(method, method.span, None))
+                        .collect();
+                    symbols.push(symbol(*name, Kind::Trait, item.span, None, children));
+                }
+                ItemKind::Impl {
+                    trait_,
+                    self_type,
+                    methods,
+                } => {
+                    let children = methods
+                        .iter()
+                        .map(|method| {
+                            let span = method
+                                .body
+                                .last()
+                                .map_or(method.sig.span, |last| method.sig.span.to(last.span));
+                            self.function(&method.sig, span, Some(&method.body))
+                        })
+                        .collect();
+                    let name = Ident {
+                        name: trait_.name,
+                        span: trait_.span.to(self_type.span),
+                    };
+                    let mut impl_ = symbol(name, Kind::Impl, item.span, None, children);
+                    impl_.name = format!("impl {} for {}", trait_.name, self_type.name);
+                    symbols.push(impl_);
+                }
+                ItemKind::FnDef(function) => {
+                    symbols.push(self.function(&function.sig, item.span, Some(&function.body)))
+                }
+                ItemKind::FnDecl(sig) => symbols.push(self.function(sig, item.span, None)),
+            }
+        }
+        symbols
+    }
+
+    fn function(
+        &mut self,
+        sig: &FnSig<'input>,
+        span: Span,
+        body: Option<&[Expr<'input>]>,
+    ) -> Symbol {
+        let detail = signature(sig);
+        let definition = self.define(sig.name, Kind::Function, detail.clone());
+        self.by_signature.insert(sig.span.start, definition);
+
+        if let Some(body) = body {
+            self.type_params = sig
+                .type_params
+                .iter()
+                .map(|param| param.name.name)
+                .collect();
+            for param in &sig.params {
+                if let Param::Named { name, type_ } = param {
+                    let definition = self.define(
+                        *name,
+                        Kind::Variable,
+                        format!("{}: {}", name.name, type_.name),
+                    );
+                    self.scope.push((name.name, definition));
+                }
+            }
+            for expr in body {
+                self.expr(expr);
+            }
+            self.scope.clear();
+        }
+
+        symbol(sig.name, Kind::Function, span, Some(detail), vec![])
+    }
+
+    fn expr(&mut self, expr: &Expr<'input>) {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::Bool(_) => {}
+            ExprKind::Var(name) => {
+                if let Some(definition) = self.variable(name) {
+                    self.analysis.references.push((expr.span, definition));
+                }
+            }
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Let { name, value } => {
+                self.expr(value);
+                let detail = match (&value.kind, self.types.get(&value.id)) {
+                    (ExprKind::Closure { .. }, _) | (_, None) => format!("let {}", name.name),
+                    (_, Some(type_)) => format!("let {}: {}", name.name, self.type_name(*type_)),
+                };
+                let definition = self.define(*name, Kind::Variable, detail);
+                self.scope.push((name.name, definition));
+            }
+            ExprKind::Call { path, args, .. } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                let name = path.joined();
+                if let Some(definition) = self.variable(&name) {
+                    // A closure
+                    self.analysis.references.push((path.span, definition));
+                } else if let Some((_, function)) =
+                    self.signatures.resolve_function(&name, &self.module)
+                {
+                    self.calls.push((path.span, function.span.start));
+                } else if let Ok(Some((_, method))) = self.signatures.trait_method(&name, &[]) {
+                    self.calls.push((path.span, method.span.start));
+                }
+            }
+            ExprKind::If { condition, body } => {
+                self.expr(condition);
+                let depth = self.scope.len();
+                for expr in body {
+                    self.expr(expr);
+                }
+                self.scope.truncate(depth);
+            }
+            ExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Closure { params, body } => {
+                let depth = self.scope.len();
+                for param in params {
+                    let detail = match param.type_ {
+                        Some(type_) => format!("{}: {}", param.name.name, type_.name),
+                        None => param.name.name.to_string(),
+                    };
+                    let definition = self.define(param.name, Kind::Variable, detail);
+                    self.scope.push((param.name.name, definition));
+                }
+                self.expr(body);
+                self.scope.truncate(depth);
+            }
+        }
+    }
+
+    fn variable(&self, name: &str) -> Option<usize> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(var, _)| *var == name)
+            .map(|(_, definition)| *definition)
+    }
+
+    fn type_name(&self, type_: Type) -> String {
+        match type_ {
+            Type::Int | Type::Number => "int".to_string(),
+            Type::Uint => "uint".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Generic(n) => self.type_params.get(n).unwrap_or(&"_").to_string(),
+            Type::Infer(_) | Type::Error => "_".to_string(),
+        }
+    }
+}
+
+fn symbol(
+    name: Ident,
+    kind: Kind,
+    span: Span,
+    detail: Option<String>,
+    children: Vec<Symbol>,
+) -> Symbol {
+    Symbol {
+        name: name.name.to_string(),
+        kind,
+        span,
+        name_span: name.span,
+        detail,
+        children,
+    }
+}
+
+/// `fn name<T: Bound>(arg: type) -> ret`
+fn signature(sig: &FnSig) -> String {
+    let type_params = sig
+        .type_params
+        .iter()
+        .map(|param| match param.bounds.as_slice() {
+            [] => param.name.name.to_string(),
+            bounds => {
+                let bounds: Vec<_> = bounds.iter().map(|bound| bound.name).collect();
+                format!("{}: {}", param.name.name, bounds.join(" + "))
+            }
+        })
+        .collect::<Vec<_>>();
+    let type_params = match type_params.is_empty() {
+        true => String::new(),
+        false => format!("<{}>", type_params.join(", ")),
+    };
+    let params = sig
+        .params
+        .iter()
+        .map(|param| match param {
+            Param::SelfParam(_) => "self".to_string(),
+            Param::Named { name, type_ } => format!("{}: {}", name.name, type_.name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "fn {}{type_params}({params}) -> {}",
+        sig.name.name, sig.ret.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path};
+
+    use lqdc_common::source_map::FileId;
+
+    use super::{Analysis, Kind};
+
+    #[test]
+    fn imports_are_followed() {
+        let dir = env::temp_dir().join(format!("lqd-lsp-imports-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("math.lqd"), "pub fn one -> int {\n    1\n}\n").unwrap();
+
+        let source = "import math;\n\nfn main -> int {\n    math::one()\n}\n";
+        let analysis = Analysis::new(&dir.join("main.lqd"), source);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(analysis.diagnostics, []);
+
+        let call = source.find("math::one").unwrap();
+        let (definition, _) = analysis.definition_at(call).unwrap();
+        assert_eq!(definition.detail, "fn one() -> int");
+        let (file, span) = analysis.locate(definition.span).unwrap();
+        assert_eq!(file, FileId(1));
+        assert_eq!(span.start, "pub fn ".len());
+
+        // The outline is of the document only
+        let names: Vec<_> = analysis.symbols.iter().map(|symbol| &symbol.name).collect();
+        assert_eq!(names, ["main"]);
+    }
+
+    #[test]
+    fn type_errors_keep_the_rest() {
+        let source = "trait Show {\n    fn show(self) -> void;\n}\n\nfn main -> int {\n    let b = true;\n    b\n}\n";
+        let analysis = Analysis::new(Path::new("main.lqd"), source);
+        assert_eq!(analysis.diagnostics.len(), 1);
+
+        let kinds: Vec<_> = analysis.symbols.iter().map(|symbol| symbol.kind).collect();
+        assert_eq!(kinds, [Kind::Trait, Kind::Function]);
+        assert_eq!(analysis.symbols[0].children[0].name, "show");
+
+        let use_ = source.rfind('b').unwrap();
+        let (definition, _) = analysis.definition_at(use_).unwrap();
+        assert_eq!(definition.detail, "let b");
+        assert_eq!(definition.span.start, source.find("b =").unwrap());
+    }
+}
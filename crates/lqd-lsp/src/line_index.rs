@@ -0,0 +1,71 @@
+//! Converting between byte offsets and LSP positions, which count lines and
+//! UTF-16 code units
+
+use lsp_types::{Position, Range};
+
+use lqdc_common::hir::Span;
+
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// Offset of the start of each line
+    lines: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let lines = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, lines }
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = self.lines.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.lines[line]..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum::<usize>();
+        Position::new(line as u32, character as u32)
+    }
+
+    /// The offset of a position, clamped to the end of its line
+    pub fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.lines.get(position.line as usize) else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            if c == '\n' || units >= position.character as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    pub fn range(&self, span: Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+
+    use super::LineIndex;
+
+    #[test]
+    fn round_trip() {
+        let text = "fn main -> int {\n    // é𝄞\n    0\n}";
+        let index = LineIndex::new(text);
+        for offset in text.char_indices().map(|(i, _)| i) {
+            assert_eq!(index.offset(index.position(offset)), offset);
+        }
+
+        let zero = text.find('0').unwrap();
+        assert_eq!(index.position(zero), Position::new(2, 4));
+        let after = text.find('𝄞').unwrap() + '𝄞'.len_utf8();
+        assert_eq!(index.position(after), Position::new(1, 10));
+    }
+}
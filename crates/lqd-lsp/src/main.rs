@@ -0,0 +1,274 @@
+//! A language server for Liquid, speaking LSP over stdin and stdout
+//!
+//! Documents are analysed whole on every change, by the same passes the
+//! compiler runs, and the diagnostics published. Requests are answered from
+//! the last analysis.
+
+mod analysis;
+mod line_index;
+
+use std::{collections::HashMap, path::PathBuf};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _},
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString, OneOf,
+    PublishDiagnosticsParams, ServerCapabilities, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+use miette::*;
+
+use lqdc_common::{hir::Span, source_map::FileId};
+
+use analysis::{Analysis, Kind, Symbol};
+use line_index::LineIndex;
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities).into_diagnostic()?)
+        .into_diagnostic()?;
+
+    Server {
+        connection,
+        documents: HashMap::new(),
+    }
+    .run()?;
+    io_threads.join().into_diagnostic()
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<Url, Document>,
+}
+
+impl Server {
+    fn run(mut self) -> Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self
+                        .connection
+                        .handle_shutdown(&request)
+                        .into_diagnostic()?
+                    {
+                        return Ok(());
+                    }
+                    let response = self.request(request);
+                    self.send(response.into())?;
+                }
+                Message::Notification(notification) => self.notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.connection.sender.send(message).into_diagnostic()
+    }
+
+    fn request(&self, request: Request) -> Response {
+        let params = request.params;
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => serde_json::from_value(params)
+                .and_then(|params| serde_json::to_value(self.definition(params))),
+            HoverRequest::METHOD => serde_json::from_value(params)
+                .and_then(|params| serde_json::to_value(self.hover(params))),
+            Completion::METHOD => serde_json::from_value(params)
+                .and_then(|params| serde_json::to_value(self.completion(params))),
+            DocumentSymbolRequest::METHOD => serde_json::from_value(params)
+                .and_then(|params| serde_json::to_value(self.symbols(params))),
+            method => {
+                return Response::new_err(
+                    request.id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unknown request {method}"),
+                )
+            }
+        };
+        match result {
+            Ok(result) => Response::new_ok(request.id, result),
+            Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        let params = notification.params;
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(params).into_diagnostic()?;
+                self.update(params.text_document.uri, params.text_document.text)
+            }
+            // Only whole documents are synced, so the last change has all of it
+            DidChangeTextDocument::METHOD => {
+                let mut params: DidChangeTextDocumentParams =
+                    serde_json::from_value(params).into_diagnostic()?;
+                match params.content_changes.pop() {
+                    Some(change) => self.update(params.text_document.uri, change.text),
+                    None => Ok(()),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(params).into_diagnostic()?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish(params.text_document.uri, vec![])
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Analyses a new version of a document, and publishes its diagnostics
+    fn update(&mut self, uri: Url, text: String) -> Result<()> {
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.path()));
+        let analysis = Analysis::new(&path, &text);
+
+        let index = LineIndex::new(&text);
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| lsp_types::Diagnostic {
+                range: index.range(diagnostic.span),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: diagnostic.code.clone().map(NumberOrString::String),
+                source: Some("lqdc".to_string()),
+                message: match &diagnostic.help {
+                    Some(help) => format!("{}\n{help}", diagnostic.message),
+                    None => diagnostic.message.clone(),
+                },
+                ..Default::default()
+            })
+            .collect();
+        self.publish(uri.clone(), diagnostics)?;
+
+        self.documents.insert(uri, Document { text, analysis });
+        Ok(())
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Result<()> {
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        self.send(Notification::new(PublishDiagnostics::METHOD.to_string(), params).into())
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = &position.text_document.uri;
+        let document = self.documents.get(uri)?;
+        let offset = LineIndex::new(&document.text).offset(position.position);
+        let (definition, _) = document.analysis.definition_at(offset)?;
+        location(uri, document, definition.span).map(GotoDefinitionResponse::Scalar)
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let index = LineIndex::new(&document.text);
+        let (definition, span) = document
+            .analysis
+            .definition_at(index.offset(position.position))?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```liquid\n{}\n```", definition.detail),
+            }),
+            range: Some(index.range(span)),
+        })
+    }
+
+    /// Every function, wherever the cursor is
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let uri = &params.text_document_position.text_document.uri;
+        let analysis = &self.documents.get(uri)?.analysis;
+        let items = analysis
+            .functions
+            .iter()
+            .map(|(path, definition)| CompletionItem {
+                label: path.clone(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(analysis.definitions[*definition].detail.clone()),
+                ..Default::default()
+            })
+            .collect();
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        let index = LineIndex::new(&document.text);
+        let symbols = document
+            .analysis
+            .symbols
+            .iter()
+            .map(|symbol| document_symbol(&index, symbol))
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+/// Where a span is, in the document or a file it imports
+fn location(uri: &Url, document: &Document, span: Span) -> Option<Location> {
+    let analysis = &document.analysis;
+    let (file, span) = analysis.locate(span)?;
+    if file == FileId(0) {
+        return Some(Location::new(
+            uri.clone(),
+            LineIndex::new(&document.text).range(span),
+        ));
+    }
+
+    let file = analysis.source_map.file(file);
+    let text = &analysis.source_map.source[file.start..file.start + file.len];
+    let uri = Url::from_file_path(&file.name).ok()?;
+    Some(Location::new(uri, LineIndex::new(text).range(span)))
+}
+
+#[allow(deprecated)]
+fn document_symbol(index: &LineIndex, symbol: &Symbol) -> DocumentSymbol {
+    DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: symbol.detail.clone(),
+        kind: match symbol.kind {
+            Kind::Function => SymbolKind::FUNCTION,
+            Kind::Variable => SymbolKind::VARIABLE,
+            Kind::Module => SymbolKind::MODULE,
+            Kind::Trait => SymbolKind::INTERFACE,
+            Kind::Impl => SymbolKind::OBJECT,
+        },
+        tags: None,
+        deprecated: None,
+        range: index.range(symbol.span),
+        selection_range: index.range(symbol.name_span),
+        children: Some(
+            symbol
+                .children
+                .iter()
+                .map(|child| document_symbol(index, child))
+                .collect(),
+        ),
+    }
+}
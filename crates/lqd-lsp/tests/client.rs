@@ -0,0 +1,170 @@
+//! Runs the server with a scripted client, checking its replies
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+const URI: &str = "file:///project/main.lqd";
+
+const SOURCE: &str = "fn add(a: int, b: int) -> int {
+    a + b
+}
+
+fn main -> int {
+    let x = add(1, 2);
+    x
+}
+";
+
+struct Client {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut len = 0;
+        loop {
+            let mut header = String::new();
+            self.stdout.read_line(&mut header).unwrap();
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length: ") {
+                len = value.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; len];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Sends a request and waits for its response, skipping notifications
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                return message["result"].clone();
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Waits for the diagnostics of the document
+    fn diagnostics(&mut self) -> Vec<Value> {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics" {
+                return message["params"]["diagnostics"].as_array().unwrap().clone();
+            }
+        }
+    }
+}
+
+fn position(line: u32, character: u32) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+#[test]
+fn scripted_session() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_lqd-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut client = Client {
+        stdin: server.stdin.take().unwrap(),
+        stdout: BufReader::new(server.stdout.take().unwrap()),
+        next_id: 0,
+    };
+
+    let init = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(init["capabilities"]["hoverProvider"], true);
+    client.notify("initialized", json!({}));
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": { "uri": URI, "languageId": "liquid", "version": 1, "text": SOURCE } }),
+    );
+    assert_eq!(client.diagnostics(), Vec::<Value>::new());
+
+    // `add` in `let x = add(1, 2)`
+    let definition = client.request("textDocument/definition", position(5, 13));
+    assert_eq!(definition["uri"], URI);
+    assert_eq!(
+        definition["range"],
+        json!({ "start": { "line": 0, "character": 3 }, "end": { "line": 0, "character": 6 } })
+    );
+
+    // `x` on the last line
+    let hover = client.request("textDocument/hover", position(6, 4));
+    assert_eq!(hover["contents"]["value"], "```liquid\nlet x: int\n```");
+    let definition = client.request("textDocument/definition", position(6, 4));
+    assert_eq!(
+        definition["range"]["start"],
+        json!({ "line": 5, "character": 8 })
+    );
+
+    let hover = client.request("textDocument/hover", position(5, 13));
+    assert_eq!(
+        hover["contents"]["value"],
+        "```liquid\nfn add(a: int, b: int) -> int\n```"
+    );
+
+    let completion = client.request("textDocument/completion", position(6, 4));
+    let labels: Vec<_> = completion
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert_eq!(labels, ["add", "main"]);
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let names: Vec<_> = symbols
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| symbol["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["add", "main"]);
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": SOURCE.replace("    x\n", "    y\n") }]
+        }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "L0001");
+    assert_eq!(
+        diagnostics[0]["range"]["start"],
+        json!({ "line": 6, "character": 4 })
+    );
+
+    client.request("shutdown", Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(server.wait().unwrap().success());
+}
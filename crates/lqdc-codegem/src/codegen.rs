@@ -3,236 +3,332 @@ use std::collections::HashMap;
 use codegem::ir::{
     FunctionId, ModuleBuilder, Operation, Terminator, ToIntegerOperation, Value, VariableId,
 };
-use lang_pt::ASTNode;
 use miette::*;
 
 use crate::{map_linkage, map_type, CodegemError};
-use frontend::node::NodeValue;
 use lqdc_common::{
-    codepass::{CodePass, Is},
+    closure_convert::LiftedClosure,
+    codepass::{CodePass, Is, PassOutput, Stage},
+    hir::{BinaryOp, Expr, ExprKind, NodeId},
     linkage::Linkage,
-    make_signatures::MakeSignaturesPass,
+    make_signatures::Function,
+    monomorphize::MonomorphizePass,
     type_::Type,
+    type_check::Types,
     Error, IntoLabelled,
 };
 
+/// Everything in the module that can be called, and where generic calls resolve to
+struct Declarations<'a, 'input> {
+    functions: HashMap<&'a str, (&'a Function<'input>, FunctionId)>,
+    closures: HashMap<NodeId, (&'a LiftedClosure<'input>, FunctionId)>,
+    call_targets: &'a HashMap<(String, NodeId), String>,
+    types: &'a Types,
+}
+
+impl Declarations<'_, '_> {
+    /// The type the type checker gave `expr`, in the function `symbol`
+    fn expr_type(&self, symbol: &str, expr: &Expr) -> Result<Type> {
+        Ok(*self
+            .types
+            .get(&(symbol.to_string(), expr.id))
+            .ok_or_else(|| {
+                Error::InternalCompilerError("Expression was never type checked".to_string())
+                    .labelled(expr.span.into())
+            })?)
+    }
+}
+
 pub struct CodegenPass;
 impl<'input> CodePass<'input> for CodegenPass {
-    type Prev = MakeSignaturesPass<'input>;
+    const NAME: &'static str = "codegen";
+    type Prev = MonomorphizePass<'input>;
     type Arg = &'input mut ModuleBuilder;
 
     fn pass(
         prev: Self::Prev,
-        input: &'input str,
+        _: &'input str,
         builder: &mut impl Is<Self::Arg>,
     ) -> miette::Result<Self> {
         let mut functions = HashMap::new();
         let builder = builder.is_mut();
-        for (name, (linkage, args, ret_type, nodes)) in prev.functions {
+        for (name, function) in &prev.functions {
             let func_id = builder.new_function(
                 name,
-                map_linkage(&linkage),
-                args.iter()
+                map_linkage(&function.linkage),
+                function
+                    .args
+                    .iter()
+                    .map(|(a, t)| (a.to_string(), map_type(*t)))
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+                &map_type(function.ret_type),
+            );
+            functions.insert(name.as_str(), (function, func_id));
+        }
+        let mut closures = HashMap::new();
+        for (id, closure) in &prev.closures {
+            let func_id = builder.new_function(
+                &closure.name,
+                map_linkage(&Linkage::Private),
+                closure
+                    .args()
+                    .iter()
                     .map(|(a, t)| (a.to_string(), map_type(*t)))
                     .collect::<Vec<_>>()
                     .as_slice(),
-                &map_type(ret_type),
+                &map_type(closure.ret_type),
             );
-            functions.insert(name, (linkage, args, ret_type, nodes, func_id));
+            closures.insert(*id, (closure, func_id));
         }
-        for (_, (_, args, _, nodes, func_id)) in &functions {
+        let decls = Declarations {
+            functions,
+            closures,
+            call_targets: &prev.call_targets,
+            types: &prev.types,
+        };
+
+        // Closures are compiled in the context of the function they were
+        // defined in, so their calls resolve the same way
+        let bodies = decls
+            .functions
+            .iter()
+            .map(|(symbol, (function, func_id))| {
+                (
+                    *symbol,
+                    function.args.clone(),
+                    function.body.as_slice(),
+                    function.ret_type,
+                    *func_id,
+                )
+            })
+            .chain(decls.closures.values().map(|(closure, func_id)| {
+                (
+                    closure.function.as_str(),
+                    closure.args(),
+                    std::slice::from_ref(&closure.body),
+                    closure.ret_type,
+                    *func_id,
+                )
+            }))
+            .collect::<Vec<_>>();
+        for (symbol, args, exprs, ret_type, func_id) in bodies {
             let mut vars = HashMap::new();
-            for ((name, type_), id) in args
-                .iter()
-                .zip(builder.get_function_args(*func_id).unwrap())
-            {
+            let mut closure_vars = HashMap::new();
+            let arg_ids = builder.get_function_args(func_id).ok_or_else(|| {
+                Error::InternalCompilerError(
+                    "Failed to get function, invalid function_id".to_string(),
+                )
+            })?;
+            for ((name, type_), id) in args.iter().zip(arg_ids) {
                 vars.insert(name.to_string(), (*type_, id));
             }
-            builder.switch_to_function(*func_id);
+            builder.switch_to_function(func_id);
             let block = builder
                 .push_block()
                 .map_err(CodegemError::ModuleCreationError)?;
             builder.switch_to_block(block);
-            for node in nodes {
-                compile_node(input, builder, node, &mut vars, &functions)?;
+            // A trailing `return` is compiled here, so it doesn't leave an
+            // unreachable block behind
+            let (exprs, returned) = match exprs.split_last() {
+                Some((
+                    Expr {
+                        kind: ExprKind::Return(returned),
+                        ..
+                    },
+                    init,
+                )) => (init, Some(returned.as_deref())),
+                _ => (exprs, None),
+            };
+            let mut value = None;
+            for expr in exprs {
+                value = compile_expr(builder, expr, &mut vars, &mut closure_vars, symbol, &decls)?;
+            }
+            if let Some(returned) = returned {
+                value = match returned {
+                    Some(expr) => Some(value_of(
+                        compile_expr(builder, expr, &mut vars, &mut closure_vars, symbol, &decls)?,
+                        expr,
+                    )?),
+                    None => None,
+                };
             }
+
+            // The last expression is returned
+            let terminator = match value {
+                _ if ret_type == Type::Void => Terminator::ReturnVoid,
+                Some(value) => Terminator::Return(value),
+                None => bail!(Error::InternalCompilerError(format!(
+                    "{symbol} has no value to return"
+                ))),
+            };
+            builder
+                .set_terminator(terminator)
+                .map_err(CodegemError::ModuleCreationError)?;
         }
 
         Ok(Self)
     }
 }
 
-#[allow(unused_macros)]
-fn compile_node(
-    input: &str,
+impl<'input> PassOutput<'input> for CodegenPass {
+    const STAGE: &'static str = "done";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::Done
+    }
+    fn from_stage(_: Stage<'input>) -> Option<Self> {
+        None
+    }
+}
+
+fn compile_expr(
     builder: &mut ModuleBuilder,
-    node: &ASTNode<NodeValue>,
+    expr: &Expr,
     vars: &mut HashMap<String, (Type, VariableId)>,
-    functions: &HashMap<
-        &str,
-        (
-            Linkage,
-            Vec<(&str, Type)>,
-            Type,
-            Vec<ASTNode<NodeValue>>,
-            FunctionId,
-        ),
-    >,
+    closure_vars: &mut HashMap<String, (NodeId, Vec<VariableId>)>,
+    symbol: &str,
+    decls: &Declarations,
 ) -> Result<Option<Value>> {
-    macro_rules! compile_node {
+    macro_rules! compile_expr {
         ($x:expr) => {
-            compile_node(input, builder, $x, vars, functions)
-        };
-    }
-    macro_rules! type_of {
-        ($x:expr) => {
-            type_of(input, $x, vars, functions)
+            compile_expr(builder, $x, vars, closure_vars, symbol, decls)
         };
     }
 
-    match node.node {
-        NodeValue::Id => {
-            let id = &input[node.start..node.end];
-            let (_, var_id) = if let Some(thing) = vars.get(id) {
+    match &expr.kind {
+        ExprKind::Var(id) => {
+            let (_, var_id) = if let Some(thing) = vars.get(*id) {
                 thing
             } else {
-                bail!(Error::VarDoesntExist(id.to_string(),).labelled((node.start..node.end).into()))
+                bail!(Error::VarDoesntExist(id.to_string(),).labelled(expr.span.into()))
             };
             Ok(builder
                 .push_instruction(Operation::GetVar(*var_id))
                 .map_err(CodegemError::ModuleCreationError)?)
         }
-        NodeValue::Number => {
-            let num = input[node.start..node.end].parse::<i64>().unwrap();
+        ExprKind::Number(number) => {
+            let num = number
+                .parse::<i64>()
+                .map_err(|_| Error::InvalidInteger.labelled(expr.span.into()))?;
             Ok(builder
                 .push_instruction(Operation::Integer(
-                    map_type(Type::Int),
+                    map_type(decls.expr_type(symbol, expr)?),
                     num.to_le_bytes().to_vec(),
                 ))
                 .map_err(CodegemError::ModuleCreationError)?)
         }
-        NodeValue::Product => {
-            if node.children.len() == 1 {
-                // Just a number
-                compile_node(
-                    input,
-                    builder,
-                    node.children.first().unwrap(),
-                    vars,
-                    functions,
-                )
-            } else if node.children.len() % 2 == 1 {
-                // dbg!(node.children.len());
-                let mut iter = node.children.iter();
-
-                let lhs = iter.next().unwrap();
-                let mut lhs_imm = compile_node(input, builder, lhs, vars, functions)?.unwrap();
-                while let Some(op) = iter.next() {
-                    let rhs = iter.next().unwrap();
-
-                    // let lhs_imm = compile_node(input,builder,lhs)?.unwrap();
-                    let rhs_imm = compile_node(input, builder, rhs, vars, functions)?.unwrap();
-                    lhs_imm = match op.node {
-                        NodeValue::Mul => builder
-                            .push_instruction(Operation::Mul(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?
-                            .unwrap(),
-                        NodeValue::Div => builder
-                            .push_instruction(Operation::Div(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?
-                            .unwrap(),
-                        _ => unreachable!(),
-                    };
-                }
-                Ok(Some(lhs_imm))
-            } else {
-                bail!(miette!("Even number of arguments"))
-            }
+        ExprKind::Bool(true) => Ok(builder
+            .push_instruction(0b1_u8.to_integer_operation())
+            .map_err(CodegemError::ModuleCreationError)?),
+        ExprKind::Bool(false) => Ok(builder
+            .push_instruction(0b0_u8.to_integer_operation())
+            .map_err(CodegemError::ModuleCreationError)?),
+        ExprKind::Binary { op, lhs, rhs } => {
+            let lhs_imm = value_of(compile_expr!(lhs)?, lhs)?;
+            let rhs_imm = value_of(compile_expr!(rhs)?, rhs)?;
+            let operation = match op {
+                BinaryOp::Add => Operation::Add(lhs_imm, rhs_imm),
+                BinaryOp::Sub => Operation::Sub(lhs_imm, rhs_imm),
+                BinaryOp::Mul => Operation::Mul(lhs_imm, rhs_imm),
+                BinaryOp::Div => Operation::Div(lhs_imm, rhs_imm),
+                BinaryOp::Gt => Operation::Gt(lhs_imm, rhs_imm),
+                BinaryOp::Gte => Operation::Ge(lhs_imm, rhs_imm),
+                BinaryOp::Eq => Operation::Eq(lhs_imm, rhs_imm),
+                BinaryOp::Lt => Operation::Lt(lhs_imm, rhs_imm),
+                BinaryOp::Lte => Operation::Le(lhs_imm, rhs_imm),
+            };
+            Ok(builder
+                .push_instruction(operation)
+                .map_err(CodegemError::ModuleCreationError)?)
         }
-        NodeValue::Sum => {
-            if node.children.len() == 1 {
-                compile_node(
-                    input,
-                    builder,
-                    node.children.first().unwrap(),
-                    vars,
-                    functions,
-                )
-            } else if node.children.len() % 2 == 1 {
-                let mut iter = node.children.iter();
-
-                let mut result = None;
-                while let Some(node) = iter.next() {
-                    let lhs = node;
-                    let op = iter.next().unwrap();
-                    let rhs = iter.next().unwrap();
-                    let lhs_imm = compile_node(input, builder, lhs, vars, functions)?.unwrap();
-                    let rhs_imm = compile_node(input, builder, rhs, vars, functions)?.unwrap();
-                    let _lhs_type = type_of(input, lhs, vars, functions);
-                    result = match op.node {
-                        NodeValue::Add => builder
-                            .push_instruction(Operation::Add(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?,
-                        NodeValue::Sub => builder
-                            .push_instruction(Operation::Sub(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?,
-                        _ => unreachable!(),
-                    };
+        ExprKind::Let { name, value } => {
+            let id = name.name;
+            if let ExprKind::Closure { .. } = value.kind {
+                // Closures only exist as their lifted function, calls are
+                // resolved through the binding. Captures are copied here,
+                // so later bindings don't change what the closure sees.
+                let (closure, _) = decls.closures.get(&value.id).ok_or_else(|| {
+                    Error::InternalCompilerError(format!("Closure {id} was never lifted"))
+                })?;
+                let mut env = vec![];
+                for (capture, type_) in &closure.env.captures {
+                    let (_, var_id) = vars.get(*capture).ok_or_else(|| {
+                        Error::VarDoesntExist(capture.to_string()).labelled(expr.span.into())
+                    })?;
+                    let value = builder
+                        .push_instruction(Operation::GetVar(*var_id))
+                        .map_err(CodegemError::ModuleCreationError)?
+                        .ok_or_else(|| {
+                            Error::InternalCompilerError(format!("Capture {capture} has no value"))
+                        })?;
+                    let env_id = builder
+                        .push_variable(&format!("{id}.{capture}"), &map_type(*type_))
+                        .map_err(CodegemError::ModuleCreationError)?;
+                    builder
+                        .push_instruction(Operation::SetVar(env_id, value))
+                        .map_err(CodegemError::ModuleCreationError)?;
+                    env.push(env_id);
                 }
-
-                Ok(result)
-            } else {
-                unreachable!()
-            }
-        }
-        NodeValue::Expr => {
-            let mut result = None;
-            for child in &node.children {
-                result = compile_node(input, builder, child, vars, functions)?;
+                closure_vars.insert(id.to_string(), (value.id, env));
+                return Ok(None);
             }
-            Ok(result)
-        }
-        NodeValue::VarAssign => {
-            let id = &node.children[0];
-            let id = &input[id.start..id.end];
-            let value = &node.children[1];
-            let value_imm = compile_node(input, builder, value, vars, functions)?.unwrap();
-            let type_ = type_of(input, value, vars, functions);
-            let var_id = builder.push_variable(id, &map_type(type_)).unwrap();
-            let result = builder.push_instruction(Operation::SetVar(var_id, value_imm));
+            closure_vars.remove(id);
+            let value_imm = value_of(compile_expr!(value)?, value)?;
+            let type_ = decls.expr_type(symbol, value)?;
+            let var_id = builder
+                .push_variable(id, &map_type(type_))
+                .map_err(CodegemError::ModuleCreationError)?;
+            builder
+                .push_instruction(Operation::SetVar(var_id, value_imm))
+                .map_err(CodegemError::ModuleCreationError)?;
             vars.insert(id.to_string(), (type_, var_id));
-            Ok(result.map_err(CodegemError::ModuleCreationError)?)
+            // A `let` has the value it binds
+            Ok(Some(value_imm))
         }
-        NodeValue::FnCall => {
-            let id = &node.children[0];
-            let id = &input[id.start..id.end];
+        ExprKind::Call {
+            path,
+            args: arg_exprs,
+            args_span,
+        } => {
+            let id = path.joined();
 
             let mut args = vec![];
-            let arg_set = &node.children[1];
-            match arg_set.node {
-                NodeValue::FnCallArgSet => {
-                    // dbg!(&arg_set.children);
-                    for arg in &arg_set.children {
-                        args.push(
-                            compile_node(input, builder, arg, vars, functions)?.ok_or_else(
-                                || {
-                                    Error::NotAllowedHere(
-                                        format!("{:?}", arg.node),
-                                        "function calls".to_string(),
-                                    )
-                                    .labelled((arg.start..arg.end).into())
-                                },
-                            )?,
-                        )
-                    }
+            for arg in arg_exprs {
+                args.push(value_of(compile_expr!(arg)?, arg)?);
+            }
+
+            if let Some((closure_id, env_ids)) = closure_vars.get(&id) {
+                let (_, function_id) = decls.closures.get(closure_id).ok_or_else(|| {
+                    Error::InternalCompilerError(format!("Closure {id} was never lifted"))
+                })?;
+                let mut env = vec![];
+                for env_id in env_ids {
+                    env.push(
+                        builder
+                            .push_instruction(Operation::GetVar(*env_id))
+                            .map_err(CodegemError::ModuleCreationError)?
+                            .ok_or_else(|| {
+                                Error::InternalCompilerError(format!(
+                                    "Environment of closure {id} has no value"
+                                ))
+                            })?,
+                    );
                 }
-                NodeValue::NULL => {}
-                _ => unreachable!(),
+                env.extend(args);
+                return Ok(builder
+                    .push_instruction(Operation::Call(*function_id, env))
+                    .map_err(CodegemError::ModuleCreationError)?);
             }
 
-            let (_, _, _, _, function_id) = functions
-                .get(id)
-                .ok_or_else(|| miette!("Unknown function"))?;
+            let target = decls
+                .call_targets
+                .get(&(symbol.to_string(), expr.id))
+                .unwrap_or(&id);
+            let (_, function_id) = decls
+                .functions
+                .get(target.as_str())
+                .ok_or_else(|| Error::FuncDoesntExist(id.clone()).labelled(path.span.into()))?;
             let func_args = builder.get_function_args(*function_id).ok_or_else(|| {
                 Error::InternalCompilerError(
                     "Failed to get function, invalid function_id".to_string(),
@@ -240,88 +336,52 @@ fn compile_node(
             })?;
             ensure!(
                 func_args.len() == args.len(),
-                Error::ExpectedNumArgs(func_args.len(), args.len())
-                    .labelled((arg_set.start..arg_set.end).into())
+                Error::ExpectedNumArgs(func_args.len(), args.len()).labelled((*args_span).into())
             );
 
             Ok(builder
                 .push_instruction(Operation::Call(*function_id, args))
                 .map_err(CodegemError::ModuleCreationError)?)
         }
-        NodeValue::BoolExpr => {
-            if node.children.len() == 1 {
-                compile_node(input, builder, &node.children[0], vars, functions)
-            } else {
-                let mut iter = node.children.iter();
-
-                let mut result = None;
-                while let Some(lhs) = iter.next() {
-                    let op = iter.next().unwrap();
-                    let rhs = iter.next().unwrap();
-                    let lhs_imm = compile_node!(lhs)?.unwrap();
-                    let rhs_imm = compile_node!(rhs)?.unwrap();
-                    result = match op.node {
-                        NodeValue::GT => builder
-                            .push_instruction(Operation::Gt(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?,
-                        NodeValue::GTE => builder
-                            .push_instruction(Operation::Ge(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?,
-                        NodeValue::EQ => builder
-                            .push_instruction(Operation::Eq(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?,
-                        NodeValue::LT => builder
-                            .push_instruction(Operation::Lt(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?,
-                        NodeValue::LTE => builder
-                            .push_instruction(Operation::Le(lhs_imm, rhs_imm))
-                            .map_err(CodegemError::ModuleCreationError)?,
-                        _ => unreachable!(),
-                    };
-                }
+        ExprKind::Return(value) => {
+            let terminator = match value {
+                Some(value) => Terminator::Return(value_of(compile_expr!(value)?, value)?),
+                None => Terminator::ReturnVoid,
+            };
+            builder
+                .set_terminator(terminator)
+                .map_err(CodegemError::ModuleCreationError)?;
 
-                Ok(result)
-            }
+            // Anything after the return goes in a block nothing jumps to
+            let unreachable = builder
+                .push_block()
+                .map_err(CodegemError::ModuleCreationError)?;
+            builder.switch_to_block(unreachable);
+
+            Ok(None)
         }
-        NodeValue::True => Ok(builder
-            .push_instruction(0b1_u8.to_integer_operation())
-            .map_err(CodegemError::ModuleCreationError)?),
-        NodeValue::False => Ok(builder
-            .push_instruction(0b0_u8.to_integer_operation())
-            .map_err(CodegemError::ModuleCreationError)?),
-        NodeValue::Add
-        | NodeValue::Sub
-        | NodeValue::Mul
-        | NodeValue::Div
-        | NodeValue::NULL
-        | NodeValue::Root
-        | NodeValue::FnDefArgSet
-        | NodeValue::FnCallArgSet
-        | NodeValue::GT
-        | NodeValue::GTE
-        | NodeValue::EQ
-        | NodeValue::LT
-        | NodeValue::LTE
-        | NodeValue::FnDef
-        | NodeValue::FnDecl
-        | NodeValue::Extern => {
-            unreachable!()
+        ExprKind::Closure { .. } => {
+            // The type checker only allows closures bound with `let`
+            bail!(
+                Error::InternalCompilerError("Closure outside of a let binding".to_string())
+                    .labelled(expr.span.into())
+            )
         }
-        NodeValue::If => {
-            let condition = &node.children[0];
-            let expr_nodes = &node.children[1..];
-            let mut exprs = vec![];
-            for expr in expr_nodes {
-                exprs.push(compile_node!(expr)?);
-            }
-            let condition = compile_node!(condition)?.unwrap();
+        ExprKind::If { condition, body } => {
+            let condition = value_of(compile_expr!(condition)?, condition)?;
 
             // Code to run if the value is true
-            let true_block = builder.push_block().unwrap();
+            let true_block = builder
+                .push_block()
+                .map_err(CodegemError::ModuleCreationError)?;
             // Code to run if the value is false (without and else statement, this is empty)
-            let else_block = builder.push_block().unwrap();
+            let else_block = builder
+                .push_block()
+                .map_err(CodegemError::ModuleCreationError)?;
             // Code to run after the if expression is complete
-            let after_block = builder.push_block().unwrap();
+            let after_block = builder
+                .push_block()
+                .map_err(CodegemError::ModuleCreationError)?;
 
             builder
                 .set_terminator(Terminator::Branch(condition, true_block, else_block))
@@ -330,6 +390,14 @@ fn compile_node(
             // True block
 
             builder.switch_to_block(true_block);
+            // Bindings in the body end with it
+            let outer_vars = vars.clone();
+            let outer_closure_vars = closure_vars.clone();
+            for expr in body {
+                compile_expr!(expr)?;
+            }
+            *vars = outer_vars;
+            *closure_vars = outer_closure_vars;
 
             builder
                 .set_terminator(Terminator::Jump(after_block))
@@ -351,93 +419,107 @@ fn compile_node(
     }
 }
 
-fn type_of(
-    input: &str,
-    node: &ASTNode<NodeValue>,
-    vars: &mut HashMap<String, (Type, VariableId)>,
-    functions: &HashMap<
-        &str,
-        (
-            Linkage,
-            Vec<(&str, Type)>,
-            Type,
-            Vec<ASTNode<NodeValue>>,
-            FunctionId,
-        ),
-    >,
-) -> Type {
-    match node.node {
-        NodeValue::Number => Type::Int,
-        // Type of left hand side
-        NodeValue::Sum => type_of(input, &node.children[0], vars, functions),
-        NodeValue::Product => type_of(input, &node.children[0], vars, functions),
-        NodeValue::Expr => type_of(input, node.children.last().unwrap(), vars, functions),
-        // Retrieve from variable list
-        // It can be unwrapped, because it will already have been compiled, thus already checked
-        NodeValue::Id => vars.get(&input[node.start..node.end]).unwrap().0,
-        NodeValue::True => Type::Bool,
-        NodeValue::False => Type::Bool,
-        a => {
-            dbg!(a);
-            Type::Void
-        }
-    }
+/// The value of an expression used as an operand, which calls to void
+/// functions don't have
+fn value_of(value: Option<Value>, expr: &Expr) -> Result<Value> {
+    Ok(value.ok_or_else(|| Error::VoidValue.labelled(expr.span.into()))?)
 }
 
 #[cfg(test)]
 mod tests {
-    #[cfg(debug_assertions)]
-    mod type_of {
-        use std::collections::HashMap;
-
-        use frontend::parser;
-        use lqdc_common::type_::Type;
-
-        use crate::codegen::type_of;
-
-        #[test]
-        fn true_() {
-            let type_ = type_of(
-                "",
-                parser()
-                    .debug_production_at("value", "true".as_bytes(), 0)
-                    .unwrap()
-                    .first()
-                    .unwrap(),
-                &mut HashMap::new(),
-                &mut HashMap::new(),
-            );
-            assert_eq!(type_, Type::Bool,);
-        }
+    use codegem::ir::ModuleBuilder;
+    use lqdc_common::{
+        closure_convert::ClosureConvertPass, codepass::PassRunner, lower::LowerPass,
+        make_signatures::MakeSignaturesPass, monomorphize::MonomorphizePass, parsepass::ParsePass,
+        type_check::TypeCheckPass,
+    };
+    use miette::*;
+
+    use super::CodegenPass;
+    use crate::CodegemError;
+
+    fn compile(input: &str) -> Result<()> {
+        let mut builder = ModuleBuilder::default().with_name("test");
+        PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?
+            .run::<ClosureConvertPass>()?
+            .run::<MonomorphizePass>()?
+            .set_arg(&mut builder)
+            .run::<CodegenPass>()?;
+        builder.build().map_err(CodegemError::ModuleCreationError)?;
+
+        Ok(())
+    }
 
-        #[test]
-        fn false_() {
-            let type_ = type_of(
-                "",
-                parser()
-                    .debug_production_at("value", "false".as_bytes(), 0)
-                    .unwrap()
-                    .first()
-                    .unwrap(),
-                &mut HashMap::new(),
-                &mut HashMap::new(),
-            );
-            assert_eq!(type_, Type::Bool);
-        }
+    #[test]
+    fn literal_argument() -> Result<()> {
+        compile(
+            "
+            fn main -> uint {
+                half(4 + 4)
+            }
 
-        #[test]
-        fn number() {
-            let type_ = type_of(
-                "",
-                parser()
-                    .debug_production_at("value", "158910".as_bytes(), 0)
-                    .unwrap()
-                    .first()
-                    .unwrap(),
-                &mut HashMap::new(),
-                &mut HashMap::new(),
-            );
-            assert_eq!(type_, Type::Int);
-        }
+            fn half(x: uint) -> uint {
+                x / 2
+            }
+            ",
+        )
+    }
+
+    #[test]
+    fn generic_and_closure() -> Result<()> {
+        compile(
+            "
+            fn main -> bool {
+                let offset = 1;
+                let add = |x| x + offset;
+                first(add(2) > 2, false)
+            }
+
+            fn first<T>(a: T, b: T) -> T {
+                a
+            }
+            ",
+        )
+    }
+
+    #[test]
+    fn closure_captures_at_definition() -> Result<()> {
+        compile(
+            "
+            fn main -> int {
+                let offset = 1;
+                let add = |x| x + offset;
+                let offset = true;
+                add(1)
+            }
+            ",
+        )
+    }
+
+    #[test]
+    fn every_body_returns() -> Result<()> {
+        compile(
+            "
+            fn main -> int {
+                let x = 5
+            }
+
+            fn early(x: int) -> int {
+                if x > 1 {
+                    return x;
+                };
+                return 1;
+            }
+
+            fn nothing -> void {
+                early(2);
+                return;
+            }
+            ",
+        )
     }
 }
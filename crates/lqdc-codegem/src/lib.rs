@@ -21,7 +21,7 @@ pub fn map_type(type_: Type) -> codegem::ir::Type {
         Type::Bool => codegem::ir::Type::Integer(false, 8),
         Type::Void => codegem::ir::Type::Void,
         Type::Uint => codegem::ir::Type::Integer(false, 64),
-        Type::Number => unreachable!(),
+        Type::Number | Type::Generic(_) | Type::Infer(_) | Type::Error => unreachable!(),
     }
 }
 pub(crate) fn map_linkage(linkage: &Linkage) -> codegem::ir::Linkage {
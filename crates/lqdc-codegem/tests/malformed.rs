@@ -0,0 +1,103 @@
+//! Every program in `tests/malformed` has to be rejected with the right
+//! diagnostic, pointing at the problem, without the compiler panicking
+
+use std::{
+    collections::HashMap,
+    fs::{read_dir, read_to_string},
+};
+
+use codegem::ir::ModuleBuilder;
+use lqdc_codegem::{codegen::CodegenPass, CodegemError};
+use lqdc_common::{
+    closure_convert::ClosureConvertPass, codepass::PassRunner, emit::to_json, lower::LowerPass,
+    make_signatures::MakeSignaturesPass, monomorphize::MonomorphizePass, parsepass::ParsePass,
+    type_check::TypeCheckPass,
+};
+use miette::*;
+
+/// The code of the first error for each program
+const EXPECTED: [(&str, &str); 23] = [
+    ("bool_arithmetic", "L0028"),
+    ("closure_in_generic", "L0007"),
+    ("extern_definition", "L0018"),
+    ("float_literal", "L0009"),
+    ("integer_too_large", "L0009"),
+    ("missing_semicolon", "L0003"),
+    ("missing_trait_method", "L0014"),
+    ("nested_fn", "L0007"),
+    ("non_bool_condition", "L0023"),
+    ("return_type_mismatch", "L0023"),
+    ("unbound_closure", "L0007"),
+    ("unclosed_brace", "L0003"),
+    ("uninferred_param", "L0029"),
+    ("unknown_attribute", "L0020"),
+    ("unknown_function", "L0002"),
+    ("unknown_lint", "L0021"),
+    ("unknown_type", "L0005"),
+    ("unknown_variable", "L0001"),
+    ("unsupported_abi", "L0019"),
+    ("void_argument", "L0022"),
+    ("void_let", "L0022"),
+    ("void_operand", "L0022"),
+    ("wrong_arg_count", "L0008"),
+];
+
+/// What internal compiler errors are reported as
+const INTERNAL_COMPILER_ERROR: &str = "L0006";
+
+fn compile(input: &str) -> Result<()> {
+    let mut builder = ModuleBuilder::default().with_name("malformed");
+    PassRunner::<(), ()>::new(input)
+        .run::<ParsePass>()?
+        .run::<LowerPass>()?
+        .run::<MakeSignaturesPass>()?
+        .run::<TypeCheckPass>()?
+        .run::<ClosureConvertPass>()?
+        .run::<MonomorphizePass>()?
+        .set_arg(&mut builder)
+        .run::<CodegenPass>()?;
+    builder.build().map_err(CodegemError::ModuleCreationError)?;
+
+    Ok(())
+}
+
+#[test]
+fn malformed_programs_are_rejected() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/malformed");
+    let mut paths = read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    let expected = HashMap::from(EXPECTED);
+    assert_eq!(paths.len(), expected.len());
+
+    for path in paths {
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        let input = read_to_string(&path).unwrap();
+        let Err(error) = compile(&input) else {
+            panic!("{name} compiled without errors");
+        };
+
+        // A pass that finds several errors reports them together, last
+        let diagnostics = to_json(error.as_ref(), None);
+        let errors = match diagnostics.split_last() {
+            Some((_, errors)) if !errors.is_empty() => errors,
+            _ => &diagnostics,
+        };
+        for error in errors {
+            let code = error["code"].as_str();
+            assert_ne!(code, Some(INTERNAL_COMPILER_ERROR), "{name}: {error}");
+            assert!(code.is_some(), "{name} has no error code: {error}");
+            assert!(
+                !error["spans"].as_array().unwrap().is_empty(),
+                "{name} doesn't point at the error: {error}"
+            );
+        }
+        assert_eq!(
+            errors[0]["code"].as_str(),
+            expected.get(name).copied(),
+            "{name}"
+        );
+    }
+}
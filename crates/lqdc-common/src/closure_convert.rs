@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use miette::*;
+
+use crate::{
+    codepass::{CodePass, Is},
+    hir::{Expr, NodeId},
+    make_signatures::Signatures,
+    mangle::{mangle, mangle_closure},
+    type_::Type,
+    type_check::{Call, TypeCheckPass, Types},
+    Error, IntoLabelled,
+};
+
+/// The variables a closure captures from the scope it was defined in.
+/// These are passed to the lifted function ahead of its own parameters.
+#[derive(Clone)]
+pub struct ClosureEnv<'input> {
+    pub captures: Vec<(&'input str, Type)>,
+}
+
+/// A closure, lifted out of the function it was defined in
+pub struct LiftedClosure<'input> {
+    pub name: String,
+    /// Symbol of the function the closure was defined in
+    pub function: String,
+    pub env: ClosureEnv<'input>,
+    pub params: Vec<(&'input str, Type)>,
+    pub ret_type: Type,
+    pub body: Expr<'input>,
+}
+
+impl<'input> LiftedClosure<'input> {
+    /// Parameters of the lifted function, the environment followed by the
+    /// closure's own parameters
+    pub fn args(&self) -> Vec<(&'input str, Type)> {
+        self.env
+            .captures
+            .iter()
+            .chain(self.params.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+pub struct ClosureConvertPass<'input> {
+    pub signatures: Signatures<'input>,
+    pub types: Types,
+    /// Lifted closures, keyed by the node id of the closure expression
+    pub closures: HashMap<NodeId, LiftedClosure<'input>>,
+    pub(crate) calls: HashMap<NodeId, Call>,
+}
+
+impl<'input> CodePass<'input> for ClosureConvertPass<'input> {
+    const NAME: &'static str = "closure_convert";
+    type Prev = TypeCheckPass<'input>;
+    type Arg = ();
+
+    fn pass(prev: Self::Prev, _: &'input str, _: &mut impl Is<Self::Arg>) -> miette::Result<Self> {
+        let mut closures = HashMap::new();
+        let mut counts = HashMap::new();
+        for closure in prev.closures {
+            // The lifted function would need to be generic as well
+            let in_generic = matches!(
+                prev.signatures.functions.get(closure.function.as_str()),
+                Some(function) if function.is_generic()
+            );
+            ensure!(
+                !in_generic,
+                Error::NotAllowedHere("Closures".to_string(), "generic functions".to_string())
+                    .labelled(closure.span.into())
+            );
+
+            // Impl methods are already keyed by their symbol
+            let parent = if prev.signatures.functions.contains_key(&closure.function) {
+                mangle(&closure.function, &[])
+            } else {
+                closure.function.clone()
+            };
+            let count = counts.entry(closure.function.clone()).or_insert(0);
+            let name = mangle_closure(&parent, *count);
+            *count += 1;
+
+            let params = closure
+                .params
+                .iter()
+                .map(|(param, type_)| (param.name, *type_))
+                .collect();
+            closures.insert(
+                closure.id,
+                LiftedClosure {
+                    name,
+                    function: closure.function,
+                    env: ClosureEnv {
+                        captures: closure.captures,
+                    },
+                    params,
+                    ret_type: closure.ret_type,
+                    body: closure.body,
+                },
+            );
+        }
+
+        Ok(Self {
+            signatures: prev.signatures,
+            types: prev.types,
+            closures,
+            calls: prev.calls,
+        })
+    }
+}
@@ -1,4 +1,16 @@
+use std::{fs::read_to_string, time::Instant};
+
+use miette::bail;
+
+use crate::{
+    closure_convert::ClosureConvertPass, lower::LowerPass, make_signatures::MakeSignaturesPass,
+    monomorphize::MonomorphizePass, parsepass::ParsePass, source_map::SourceMap,
+    type_check::TypeCheckPass, Error, Errors,
+};
+
 pub trait CodePass<'input>: Sized {
+    /// What the pass is called in `-Z time-passes` and `-Z disable-pass`
+    const NAME: &'static str;
     type Prev;
     type Arg;
 
@@ -14,7 +26,7 @@ pub trait CodePass<'input>: Sized {
     fn check(
         previous: Self::Prev,
         input: &'input str,
-        arg: &impl Is<Self::Arg>,
+        arg: &mut impl Is<Self::Arg>,
     ) -> miette::Result<Self::Prev> {
         unimplemented!()
     }
@@ -24,36 +36,66 @@ pub struct PassRunner<'input, P, A> {
     cur: P,
     input: &'input str,
     arg: A,
+    /// The most errors reported from a single pass
+    error_limit: Option<usize>,
+    /// Which file each part of `input` is from, for errors
+    source_map: Option<&'input SourceMap>,
 }
 impl<'input, P, A> PassRunner<'input, P, A> {
-    pub fn new(input: &'input str) -> PassRunner<(), ()> {
+    pub fn new(input: &'input str) -> PassRunner<'input, (), ()> {
         PassRunner::<(), ()> {
             cur: (),
             input,
             arg: (),
+            error_limit: None,
+            source_map: None,
+        }
+    }
+
+    /// Names errors by the file they are in, `input` has to be the source of
+    /// the map
+    pub fn with_source_map(self, source_map: &'input SourceMap) -> Self {
+        Self {
+            source_map: Some(source_map),
+            ..self
+        }
+    }
+
+    pub fn with_error_limit(self, error_limit: Option<usize>) -> Self {
+        Self {
+            error_limit,
+            ..self
         }
     }
 
+    /// The output of the last pass run
+    pub fn into_inner(self) -> P {
+        self.cur
+    }
+
     pub fn set_arg<N>(self, arg: N) -> PassRunner<'input, P, N> {
         PassRunner {
             cur: self.cur,
             input: self.input,
             arg,
+            error_limit: self.error_limit,
+            source_map: self.source_map,
         }
     }
 
-    pub fn inject<N: CodePass<'input>>(self) -> miette::Result<Self>
+    pub fn inject<N: CodePass<'input>>(mut self) -> miette::Result<Self>
     where
         P: From<N::Prev>,
         N::Prev: From<P>,
         A: Is<N::Arg>,
     {
-        let next = N::check(self.cur.into(), self.input, &self.arg)
-            .map_err(|e| e.with_source_code(self.input.to_string()))?;
+        let next = match N::check(self.cur.into(), self.input, &mut self.arg) {
+            Ok(next) => next,
+            Err(e) => return Err(report(e, self.input, self.source_map, self.error_limit)),
+        };
         Ok(Self {
             cur: next.into(),
-            input: self.input,
-            arg: self.arg,
+            ..self
         })
     }
 
@@ -76,12 +118,16 @@ impl<'input, P, A> PassRunner<'input, P, A> {
         P: Into<N::Prev>,
         A: Is<N::Arg>,
     {
-        let cur = N::pass(self.cur.into(), self.input, &mut self.arg)
-            .map_err(|e| e.with_source_code(self.input.to_string()))?;
+        let cur = match N::pass(self.cur.into(), self.input, &mut self.arg) {
+            Ok(cur) => cur,
+            Err(e) => return Err(report(e, self.input, self.source_map, self.error_limit)),
+        };
         Ok(PassRunner::<N, A> {
             cur,
             input: self.input,
             arg: self.arg,
+            error_limit: self.error_limit,
+            source_map: self.source_map,
         })
     }
 
@@ -102,6 +148,27 @@ impl<'input, P, A> PassRunner<'input, P, A> {
     // }
 }
 
+/// Applies the error limit, and attaches the source
+fn report(
+    error: miette::Report,
+    input: &str,
+    source_map: Option<&SourceMap>,
+    error_limit: Option<usize>,
+) -> miette::Report {
+    let error = match (error.downcast::<Errors>(), error_limit) {
+        (Ok(mut errors), Some(limit)) => {
+            errors.limit(limit);
+            errors.into()
+        }
+        (Ok(errors), None) => errors.into(),
+        (Err(error), _) => error,
+    };
+    match source_map {
+        Some(source_map) => error.with_source_code(source_map.clone()),
+        None => error.with_source_code(input.to_string()),
+    }
+}
+
 pub trait Is<T> {
     fn is(&self) -> &T;
     fn is_mut(&mut self) -> &mut T;
@@ -114,3 +181,418 @@ impl<T> Is<T> for T {
         self
     }
 }
+
+/// The output of a pass, as kept by the `PassManager` between passes
+pub enum Stage<'input> {
+    /// Before any pass has run
+    Start,
+    Parsed(ParsePass),
+    Lowered(LowerPass<'input>),
+    Signatures(MakeSignaturesPass<'input>),
+    TypeChecked(TypeCheckPass<'input>),
+    ClosureConverted(ClosureConvertPass<'input>),
+    Monomorphized(MonomorphizePass<'input>),
+    /// After a pass whose output no other pass uses, like codegen
+    Done,
+}
+
+impl Stage<'_> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Start => <()>::STAGE,
+            Stage::Parsed(_) => ParsePass::STAGE,
+            Stage::Lowered(_) => LowerPass::STAGE,
+            Stage::Signatures(_) => MakeSignaturesPass::STAGE,
+            Stage::TypeChecked(_) => TypeCheckPass::STAGE,
+            Stage::ClosureConverted(_) => ClosureConvertPass::STAGE,
+            Stage::Monomorphized(_) => MonomorphizePass::STAGE,
+            Stage::Done => "done",
+        }
+    }
+}
+
+/// A pass output the `PassManager` can keep
+pub trait PassOutput<'input>: Sized {
+    /// Name of the stage, passes are checked to read the stage the pass before
+    /// them produces
+    const STAGE: &'static str;
+
+    fn into_stage(self) -> Stage<'input>;
+    fn from_stage(stage: Stage<'input>) -> Option<Self>;
+}
+
+impl<'input> PassOutput<'input> for () {
+    const STAGE: &'static str = "start";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::Start
+    }
+    fn from_stage(stage: Stage<'input>) -> Option<Self> {
+        match stage {
+            Stage::Start => Some(()),
+            _ => None,
+        }
+    }
+}
+
+impl<'input> PassOutput<'input> for ParsePass {
+    const STAGE: &'static str = "parsed";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::Parsed(self)
+    }
+    fn from_stage(stage: Stage<'input>) -> Option<Self> {
+        match stage {
+            Stage::Parsed(pass) => Some(pass),
+            _ => None,
+        }
+    }
+}
+
+impl<'input> PassOutput<'input> for LowerPass<'input> {
+    const STAGE: &'static str = "lowered";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::Lowered(self)
+    }
+    fn from_stage(stage: Stage<'input>) -> Option<Self> {
+        match stage {
+            Stage::Lowered(pass) => Some(pass),
+            _ => None,
+        }
+    }
+}
+
+impl<'input> PassOutput<'input> for MakeSignaturesPass<'input> {
+    const STAGE: &'static str = "signatures";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::Signatures(self)
+    }
+    fn from_stage(stage: Stage<'input>) -> Option<Self> {
+        match stage {
+            Stage::Signatures(pass) => Some(pass),
+            _ => None,
+        }
+    }
+}
+
+impl<'input> PassOutput<'input> for TypeCheckPass<'input> {
+    const STAGE: &'static str = "type_checked";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::TypeChecked(self)
+    }
+    fn from_stage(stage: Stage<'input>) -> Option<Self> {
+        match stage {
+            Stage::TypeChecked(pass) => Some(pass),
+            _ => None,
+        }
+    }
+}
+
+impl<'input> PassOutput<'input> for ClosureConvertPass<'input> {
+    const STAGE: &'static str = "closure_converted";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::ClosureConverted(self)
+    }
+    fn from_stage(stage: Stage<'input>) -> Option<Self> {
+        match stage {
+            Stage::ClosureConverted(pass) => Some(pass),
+            _ => None,
+        }
+    }
+}
+
+impl<'input> PassOutput<'input> for MonomorphizePass<'input> {
+    const STAGE: &'static str = "monomorphized";
+
+    fn into_stage(self) -> Stage<'input> {
+        Stage::Monomorphized(self)
+    }
+    fn from_stage(stage: Stage<'input>) -> Option<Self> {
+        match stage {
+            Stage::Monomorphized(pass) => Some(pass),
+            _ => None,
+        }
+    }
+}
+
+type RunPass<'input, 'a> =
+    Box<dyn FnMut(Stage<'input>, &'input str) -> miette::Result<Stage<'input>> + 'a>;
+
+/// A pass added to a `PassManager`
+pub struct Pass<'input, 'a> {
+    pub name: &'static str,
+    /// The stage the pass reads
+    pub input: &'static str,
+    /// The stage the pass produces
+    pub output: &'static str,
+    pub enabled: bool,
+    run: RunPass<'input, 'a>,
+}
+
+/// Runs a list of passes chosen at runtime. Unlike `PassRunner`, passes can be
+/// added depending on flags and disabled by name, as long as every pass still
+/// gets the stage it reads.
+pub struct PassManager<'input, 'a> {
+    input: &'input str,
+    passes: Vec<Pass<'input, 'a>>,
+    /// The most errors reported from a single pass
+    error_limit: Option<usize>,
+    /// Which file each part of `input` is from, for errors
+    source_map: Option<&'input SourceMap>,
+    /// Print how long each pass took, and how much memory it used
+    time_passes: bool,
+}
+
+impl<'input, 'a> PassManager<'input, 'a> {
+    pub fn new(input: &'input str) -> Self {
+        Self {
+            input,
+            passes: vec![],
+            error_limit: None,
+            source_map: None,
+            time_passes: false,
+        }
+    }
+
+    pub fn with_error_limit(self, error_limit: Option<usize>) -> Self {
+        Self {
+            error_limit,
+            ..self
+        }
+    }
+
+    /// Names errors by the file they are in, `input` has to be the source of
+    /// the map
+    pub fn with_source_map(self, source_map: &'input SourceMap) -> Self {
+        Self {
+            source_map: Some(source_map),
+            ..self
+        }
+    }
+
+    pub fn with_time_passes(self, time_passes: bool) -> Self {
+        Self {
+            time_passes,
+            ..self
+        }
+    }
+
+    /// Adds a pass that takes no argument
+    pub fn add<N>(self) -> Self
+    where
+        N: CodePass<'input, Arg = ()> + PassOutput<'input> + 'a,
+        N::Prev: PassOutput<'input>,
+    {
+        self.add_with_arg::<N, ()>(())
+    }
+
+    pub fn add_with_arg<N, A>(mut self, mut arg: A) -> Self
+    where
+        N: CodePass<'input> + PassOutput<'input> + 'a,
+        N::Prev: PassOutput<'input>,
+        A: Is<N::Arg> + 'a,
+    {
+        self.passes.push(Pass {
+            name: N::NAME,
+            input: N::Prev::STAGE,
+            output: N::STAGE,
+            enabled: true,
+            run: Box::new(move |stage, input| {
+                let prev = take_stage::<N::Prev>(stage, N::NAME)?;
+                Ok(N::pass(prev, input, &mut arg)?.into_stage())
+            }),
+        });
+        self
+    }
+
+    /// Adds a pass that only checks its input, like `PassRunner::inject`
+    pub fn add_check<N, A>(mut self, mut arg: A) -> Self
+    where
+        N: CodePass<'input> + 'a,
+        N::Prev: PassOutput<'input>,
+        A: Is<N::Arg> + 'a,
+    {
+        self.passes.push(Pass {
+            name: N::NAME,
+            input: N::Prev::STAGE,
+            output: N::Prev::STAGE,
+            enabled: true,
+            run: Box::new(move |stage, input| {
+                let prev = take_stage::<N::Prev>(stage, N::NAME)?;
+                Ok(N::check(prev, input, &mut arg)?.into_stage())
+            }),
+        });
+        self
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = &Pass<'input, 'a>> {
+        self.passes.iter()
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), Error> {
+        let pass = self
+            .passes
+            .iter_mut()
+            .find(|pass| pass.name == name)
+            .ok_or_else(|| Error::UnknownPass(name.to_string()))?;
+        pass.enabled = enabled;
+        Ok(())
+    }
+
+    /// Checks every enabled pass gets the stage it reads, then runs them
+    pub fn run(self) -> miette::Result<Stage<'input>> {
+        let mut produced = <()>::STAGE;
+        for pass in self.passes.iter().filter(|pass| pass.enabled) {
+            if pass.input != produced {
+                bail!(Error::PassInputMissing(
+                    pass.name.to_string(),
+                    pass.input.to_string(),
+                    produced.to_string()
+                ));
+            }
+            produced = pass.output;
+        }
+
+        let mut stage = Stage::Start;
+        for mut pass in self.passes.into_iter().filter(|pass| pass.enabled) {
+            let start = Instant::now();
+            let memory_before = resident_memory();
+
+            stage = match (pass.run)(stage, self.input) {
+                Ok(stage) => stage,
+                Err(e) => return Err(report(e, self.input, self.source_map, self.error_limit)),
+            };
+
+            if self.time_passes {
+                print_time(pass.name, start, memory_before, resident_memory());
+            }
+        }
+
+        Ok(stage)
+    }
+}
+
+fn take_stage<'input, T: PassOutput<'input>>(
+    stage: Stage<'input>,
+    pass: &str,
+) -> miette::Result<T> {
+    let name = stage.name();
+    T::from_stage(stage).ok_or_else(|| {
+        Error::InternalCompilerError(format!("{pass} needs {}, but got {name}", T::STAGE)).into()
+    })
+}
+
+/// Resident set size in bytes, where the OS says what it is
+fn resident_memory() -> Option<usize> {
+    let status = read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+fn print_time(name: &str, start: Instant, before: Option<usize>, after: Option<usize>) {
+    const MB: f64 = 1024.0 * 1024.0;
+
+    let memory = match (before, after) {
+        (Some(before), Some(after)) => format!(
+            "{:>8.1}MB ({:+.1}MB)",
+            after as f64 / MB,
+            (after as f64 - before as f64) / MB
+        ),
+        _ => String::new(),
+    };
+    eprintln!(
+        "time: {:>8.3}s  rss: {memory}  {name}",
+        start.elapsed().as_secs_f64()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Result;
+
+    use crate::{
+        closure_convert::ClosureConvertPass,
+        lint::{LintPass, Lints},
+        lower::LowerPass,
+        make_signatures::MakeSignaturesPass,
+        monomorphize::MonomorphizePass,
+        parsepass::ParsePass,
+        type_check::TypeCheckPass,
+        Error,
+    };
+
+    use super::{PassManager, Stage};
+
+    const INPUT: &str = "
+    fn main -> int {
+        let unused = 1;
+        2
+    }
+    ";
+
+    fn manager<'input: 'a, 'a>(
+        input: &'input str,
+        lints: &'a mut Lints,
+    ) -> PassManager<'input, 'a> {
+        PassManager::new(input)
+            .add::<ParsePass>()
+            .add::<LowerPass>()
+            .add::<MakeSignaturesPass>()
+            .add::<TypeCheckPass>()
+            .add_check::<LintPass, _>(lints)
+            .add::<ClosureConvertPass>()
+            .add::<MonomorphizePass>()
+    }
+
+    #[test]
+    fn runs_every_pass() -> Result<()> {
+        let mut lints = Lints::default();
+        let stage = manager(INPUT, &mut lints).run()?;
+
+        assert!(matches!(stage, Stage::Monomorphized(_)));
+        assert_eq!(lints.warnings.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disable_check() -> Result<()> {
+        let mut lints = Lints::default();
+        let mut manager = manager(INPUT, &mut lints);
+        manager.set_enabled("lint", false)?;
+        manager.run()?;
+
+        assert!(lints.warnings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_input() {
+        let mut lints = Lints::default();
+        let mut manager = manager(INPUT, &mut lints);
+        manager.set_enabled("type_check", false).unwrap();
+        let error = manager.run().err().unwrap();
+
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::PassInputMissing(..))
+        ));
+    }
+
+    #[test]
+    fn unknown_pass() {
+        let mut lints = Lints::default();
+        let mut manager = manager(INPUT, &mut lints);
+
+        assert!(matches!(
+            manager.set_enabled("optimize", true),
+            Err(Error::UnknownPass(_))
+        ));
+    }
+}
@@ -0,0 +1,591 @@
+//! API documentation, generated by `lqdc doc` from the signature table and
+//! the `///` comments on functions
+//!
+//! There's a page for every module, `index` in a directory named after its
+//! path, so `math::trig` is `math/trig/index.html`. Traits are on the page of
+//! the root module, since they aren't in a module. In doc comments,
+//! `` [`path`] `` or `[path]` links to the function or trait it names,
+//! resolved like a call in the module of the function.
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::{
+    linkage::Linkage,
+    make_signatures::{Function, Signatures},
+    type_::Type,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocFormat {
+    Html,
+    Markdown,
+}
+
+impl DocFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Markdown => "md",
+        }
+    }
+}
+
+impl FromStr for DocFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Self::Html),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(format!("Unknown doc format {s}, expected html or markdown")),
+        }
+    }
+}
+
+impl fmt::Display for DocFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Html => write!(f, "html"),
+            Self::Markdown => write!(f, "markdown"),
+        }
+    }
+}
+
+/// A page of documentation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// Relative to the output directory, with `/` between directories
+    pub path: String,
+    pub contents: String,
+}
+
+/// Documents a program called `name`, with a page for each of its modules
+pub fn document(name: &str, signatures: &Signatures, format: DocFormat) -> Vec<Page> {
+    // Every module with a function, and the modules they're in
+    let mut modules: BTreeMap<&str, Vec<(&str, &Function)>> = BTreeMap::new();
+    modules.insert("", vec![]);
+    for (path, function) in &signatures.functions {
+        let name = path.rsplit("::").next().unwrap_or(path);
+        modules
+            .entry(&function.module)
+            .or_default()
+            .push((name, function));
+        let mut module = function.module.as_str();
+        while let Some((parent, _)) = module.rsplit_once("::") {
+            modules.entry(parent).or_default();
+            module = parent;
+        }
+    }
+
+    modules
+        .iter()
+        .map(|(module, functions)| {
+            let submodules = modules
+                .keys()
+                .filter(|other| parent(other) == Some(module))
+                .copied()
+                .collect();
+            let mut writer = Writer {
+                signatures,
+                format,
+                name,
+                module,
+                out: String::new(),
+            };
+            writer.page(submodules, functions);
+            Page {
+                path: page_path(module, format),
+                contents: writer.out,
+            }
+        })
+        .collect()
+}
+
+/// The module a module is in, `None` for the root
+fn parent(module: &str) -> Option<&str> {
+    match module.rsplit_once("::") {
+        Some((parent, _)) => Some(parent),
+        None if module.is_empty() => None,
+        None => Some(""),
+    }
+}
+
+fn page_path(module: &str, format: DocFormat) -> String {
+    let index = format!("index.{}", format.extension());
+    match module {
+        "" => index,
+        _ => format!("{}/{index}", module.replace("::", "/")),
+    }
+}
+
+/// Writes the page of a module
+struct Writer<'a, 'input> {
+    signatures: &'a Signatures<'input>,
+    format: DocFormat,
+    /// Of the program, which is the name of the root module
+    name: &'a str,
+    module: &'a str,
+    out: String,
+}
+
+impl Writer<'_, '_> {
+    fn page(&mut self, submodules: Vec<&str>, functions: &[(&str, &Function)]) {
+        let title = match self.module {
+            "" => self.name.to_string(),
+            module => format!("{}::{module}", self.name),
+        };
+        match self.format {
+            DocFormat::Html => {
+                self.out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+                self.out += &format!("<title>{}</title>\n", escape(&title));
+                self.out += "</head>\n<body>\n";
+            }
+            DocFormat::Markdown => {}
+        }
+        self.breadcrumbs();
+
+        if !submodules.is_empty() {
+            self.heading(2, "Modules", None);
+            let items = submodules
+                .iter()
+                .map(|module| {
+                    let name = module.rsplit("::").next().unwrap_or(module);
+                    self.link(&self.href(module, None), name, true)
+                })
+                .collect();
+            self.list(items);
+        }
+
+        if self.module.is_empty() && !self.signatures.traits.is_empty() {
+            self.heading(2, "Traits", None);
+            self.traits();
+        }
+
+        if !functions.is_empty() {
+            self.heading(2, "Functions", None);
+            let mut functions = functions.to_vec();
+            functions.sort_by_key(|(name, _)| *name);
+            for (name, function) in functions {
+                self.heading(3, name, Some(&format!("fn.{name}")));
+                self.signature(name, function);
+                self.doc(function);
+            }
+        }
+
+        match self.format {
+            DocFormat::Html => self.out += "</body>\n</html>\n",
+            DocFormat::Markdown => {
+                self.out.truncate(self.out.trim_end().len());
+                self.out.push('\n');
+            }
+        }
+    }
+
+    /// The title, linking to every module the page is in
+    fn breadcrumbs(&mut self) {
+        let mut parts = vec![];
+        let mut path = String::new();
+        for (i, segment) in std::iter::once(self.name)
+            .chain(self.module.split("::").filter(|s| !s.is_empty()))
+            .enumerate()
+        {
+            if i > 1 {
+                path += "::";
+            }
+            if i > 0 {
+                path += segment;
+            }
+            parts.push(match path == self.module {
+                true => self.text(segment),
+                false => self.link(&self.href(&path, None), segment, false),
+            });
+        }
+        let title = parts.join("::");
+        match self.format {
+            DocFormat::Html => self.out += &format!("<h1>{title}</h1>\n"),
+            DocFormat::Markdown => self.out += &format!("# {title}\n\n"),
+        }
+    }
+
+    /// Every trait, with its methods and the types that implement it
+    fn traits(&mut self) {
+        let mut traits: Vec<_> = self.signatures.traits.iter().collect();
+        traits.sort_by_key(|(name, _)| *name);
+        for (name, trait_) in traits {
+            self.heading(3, name, Some(&format!("trait.{name}")));
+
+            let mut methods: Vec<_> = trait_.methods.iter().collect();
+            methods.sort_by_key(|(name, _)| **name);
+            for (method, function) in methods {
+                self.signature(method, function);
+                self.doc(function);
+            }
+
+            let mut types: Vec<_> = self
+                .signatures
+                .impls
+                .keys()
+                .filter(|(trait_, _)| trait_ == name)
+                .map(|(_, type_)| type_name(*type_, &[]))
+                .collect();
+            types.sort();
+            if !types.is_empty() {
+                let types = types
+                    .iter()
+                    .map(|type_| self.code(type_))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                self.paragraph(&format!("Implemented for {types}"));
+            }
+        }
+    }
+
+    fn heading(&mut self, level: usize, text: &str, id: Option<&str>) {
+        match self.format {
+            DocFormat::Html => {
+                let id = id.map(|id| format!(" id=\"{id}\"")).unwrap_or_default();
+                self.out += &format!("<h{level}{id}>{}</h{level}>\n", escape(text));
+            }
+            // Markdown renderers make their own ids for headings, so an
+            // anchor is added for links
+            DocFormat::Markdown => {
+                if let Some(id) = id {
+                    self.out += &format!("<a id=\"{id}\"></a>\n\n");
+                }
+                self.out += &format!("{} {text}\n\n", "#".repeat(level));
+            }
+        }
+    }
+
+    /// `pub fn name<T: Bound>(arg: type) -> type`, with the bounds linking
+    /// to their traits
+    fn signature(&mut self, name: &str, function: &Function) {
+        let mut prefix = String::new();
+        if function.no_mangle {
+            prefix += "#[no_mangle]\n";
+        }
+        prefix += match function.linkage {
+            Linkage::Public => "pub ",
+            Linkage::External => "extern \"C\" ",
+            Linkage::Private => "",
+        };
+
+        let mut signature = match self.format {
+            DocFormat::Html => escape(&format!("{prefix}fn {name}")),
+            DocFormat::Markdown => format!("{prefix}fn {name}"),
+        };
+        // Trait methods have `Self` as a type parameter, which isn't written
+        let type_params = match function.type_params.as_slice() {
+            ["Self"] => &[][..],
+            type_params => type_params,
+        };
+        if !type_params.is_empty() {
+            let params = type_params
+                .iter()
+                .zip(&function.bounds)
+                .map(|(param, bounds)| {
+                    if bounds.is_empty() {
+                        return param.to_string();
+                    }
+                    let bounds = bounds
+                        .iter()
+                        .map(|bound| match self.format {
+                            DocFormat::Html => self.link(
+                                &self.href("", Some(&format!("trait.{bound}"))),
+                                bound,
+                                false,
+                            ),
+                            DocFormat::Markdown => bound.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" + ");
+                    format!("{param}: {bounds}")
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            signature += &match self.format {
+                DocFormat::Html => format!("&lt;{params}&gt;"),
+                DocFormat::Markdown => format!("<{params}>"),
+            };
+        }
+
+        let args = function
+            .args
+            .iter()
+            .map(|(arg, type_)| match *arg {
+                "self" => "self".to_string(),
+                arg => format!("{arg}: {}", type_name(*type_, &function.type_params)),
+            })
+            .collect::<Vec<_>>();
+        if !args.is_empty() {
+            signature += &format!("({})", args.join(", "));
+        }
+        let ret = match self.format {
+            DocFormat::Html => " -&gt; ",
+            DocFormat::Markdown => " -> ",
+        };
+        signature += ret;
+        signature += &type_name(function.ret_type, &function.type_params);
+
+        match self.format {
+            DocFormat::Html => self.out += &format!("<pre><code>{signature}</code></pre>\n"),
+            DocFormat::Markdown => self.out += &format!("```liquid\n{signature}\n```\n\n"),
+        }
+    }
+
+    /// The doc comment of a function, with links resolved from its module
+    fn doc(&mut self, function: &Function) {
+        let Some(doc) = &function.doc else {
+            return;
+        };
+        for paragraph in doc.split("\n\n") {
+            let paragraph = paragraph.trim();
+            if !paragraph.is_empty() {
+                let text = self.inline(paragraph, &function.module);
+                self.paragraph(&text);
+            }
+        }
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        match self.format {
+            DocFormat::Html => self.out += &format!("<p>{text}</p>\n"),
+            DocFormat::Markdown => self.out += &format!("{text}\n\n"),
+        }
+    }
+
+    fn list(&mut self, items: Vec<String>) {
+        match self.format {
+            DocFormat::Html => {
+                self.out += "<ul>\n";
+                for item in items {
+                    self.out += &format!("<li>{item}</li>\n");
+                }
+                self.out += "</ul>\n";
+            }
+            DocFormat::Markdown => {
+                for item in items {
+                    self.out += &format!("- {item}\n");
+                }
+                self.out += "\n";
+            }
+        }
+    }
+
+    /// Doc comment text, with `` `code` `` and links to functions and traits
+    fn inline(&self, text: &str, module: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while !rest.is_empty() {
+            if let Some((target, label, len)) = self.doc_link(rest, module) {
+                out += &self.link(&target, &label, rest[1..].starts_with('`'));
+                rest = &rest[len..];
+            } else if let Some(len) = rest[1..].find('`').filter(|_| rest.starts_with('`')) {
+                out += &self.code(&rest[1..len + 1]);
+                rest = &rest[len + 2..];
+            } else {
+                let first = rest.chars().next().map_or(1, char::len_utf8);
+                let len = rest[first..]
+                    .find(['[', '`'])
+                    .map_or(rest.len(), |i| i + first);
+                out += &self.text(&rest[..len]);
+                rest = &rest[len..];
+            }
+        }
+        out
+    }
+
+    /// A `[path]` or `` [`path`] `` at the start of `text` that names a
+    /// function or trait. Returns where it links to, the path, and the length
+    /// of the link in `text`.
+    fn doc_link(&self, text: &str, module: &str) -> Option<(String, String, usize)> {
+        let inner = text.strip_prefix('[')?;
+        let end = inner.find(']')?;
+        // Already a Markdown link
+        if inner[end + 1..].starts_with('(') {
+            return None;
+        }
+        let path = inner[..end].trim_matches('`');
+        let href = match self.signatures.resolve_function(path, module) {
+            Some((_, function)) => {
+                let name = path.rsplit("::").next().unwrap_or(path);
+                self.href(&function.module, Some(&format!("fn.{name}")))
+            }
+            None => {
+                let trait_ = self.signatures.resolve_trait(path, module)?;
+                self.href("", Some(&format!("trait.{trait_}")))
+            }
+        };
+        Some((href, path.to_string(), end + 2))
+    }
+
+    /// The relative URL of the page of a module, from the current page
+    fn href(&self, module: &str, anchor: Option<&str>) -> String {
+        let from: Vec<_> = self.module.split("::").filter(|s| !s.is_empty()).collect();
+        let to: Vec<_> = module.split("::").filter(|s| !s.is_empty()).collect();
+        let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+        let mut href = "../".repeat(from.len() - common);
+        for segment in &to[common..] {
+            href += &format!("{segment}/");
+        }
+        href += &format!("index.{}", self.format.extension());
+        if let Some(anchor) = anchor {
+            href += &format!("#{anchor}");
+        }
+        href
+    }
+
+    fn link(&self, href: &str, label: &str, code: bool) -> String {
+        let label = match code {
+            true => self.code(label),
+            false => self.text(label),
+        };
+        match self.format {
+            DocFormat::Html => format!("<a href=\"{href}\">{label}</a>"),
+            DocFormat::Markdown => format!("[{label}]({href})"),
+        }
+    }
+
+    fn code(&self, text: &str) -> String {
+        match self.format {
+            DocFormat::Html => format!("<code>{}</code>", escape(text)),
+            DocFormat::Markdown => format!("`{text}`"),
+        }
+    }
+
+    fn text(&self, text: &str) -> String {
+        match self.format {
+            DocFormat::Html => escape(text),
+            DocFormat::Markdown => text.to_string(),
+        }
+    }
+}
+
+/// How a type is written, with type parameters by name
+fn type_name(type_: Type, type_params: &[&str]) -> String {
+    match type_ {
+        Type::Generic(n) => type_params[n].to_string(),
+        Type::Int | Type::Bool | Type::Void | Type::Uint => type_.mangled_name().to_string(),
+        Type::Number | Type::Infer(_) | Type::Error => format!("{type_:?}"),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::*;
+
+    use crate::{
+        codepass::PassRunner, lower::LowerPass, make_signatures::MakeSignaturesPass,
+        parsepass::ParsePass,
+    };
+
+    use super::{document, DocFormat, Page};
+
+    const SOURCE: &str = r#"
+    /// Writes a character
+    extern "C" fn putchar(c: int) -> int;
+
+    trait Double {
+        /// Twice the value
+        fn double(self) -> Self;
+    }
+
+    impl Double for int {
+        fn double(self) -> int { self * 2 }
+    }
+
+    mod math {
+        mod trig {
+            fn sin(x: int) -> int { x }
+        }
+
+        /// The square of `x`, see also [`twice`] and [Double]
+        ///
+        /// Uses <multiplication>
+        pub fn square(x: int) -> int { x * x }
+
+        fn twice<T: Double>(x: T) -> T { double(x) }
+    }
+
+    /// Calls [`math::square`], not [missing]
+    fn main -> int { math::square(putchar(2)) }
+    "#;
+
+    fn pages(format: DocFormat) -> Result<Vec<Page>> {
+        let pass = PassRunner::<(), ()>::new(SOURCE)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .into_inner();
+        Ok(document("prog", &pass.signatures, format))
+    }
+
+    fn page<'a>(pages: &'a [Page], path: &str) -> &'a str {
+        &pages
+            .iter()
+            .find(|page| page.path == path)
+            .unwrap()
+            .contents
+    }
+
+    #[test]
+    fn a_page_per_module() -> Result<()> {
+        let paths: Vec<_> = pages(DocFormat::Html)?
+            .into_iter()
+            .map(|page| page.path)
+            .collect();
+        assert_eq!(
+            paths,
+            ["index.html", "math/index.html", "math/trig/index.html"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn markdown() -> Result<()> {
+        let pages = pages(DocFormat::Markdown)?;
+
+        let root = page(&pages, "index.md");
+        assert!(root.starts_with("# prog\n\n## Modules\n\n- [`math`](math/index.md)\n"));
+        assert!(root.contains("```liquid\nextern \"C\" fn putchar(c: int) -> int\n```"));
+        assert!(root.contains("```liquid\nfn double(self) -> Self\n```\n\nTwice the value"));
+        assert!(root.contains("Implemented for `int`"));
+        assert!(root.contains("Calls [`math::square`](math/index.md#fn.square), not [missing]"));
+
+        let math = page(&pages, "math/index.md");
+        assert!(math.starts_with("# [prog](../index.md)::math\n"));
+        assert!(math.contains("- [`trig`](trig/index.md)"));
+        assert!(math.contains("```liquid\npub fn square(x: int) -> int\n```"));
+        assert!(math.contains("```liquid\nfn twice<T: Double>(x: T) -> T\n```"));
+        assert!(math.contains(
+            "The square of `x`, see also [`twice`](index.md#fn.twice) and \
+             [Double](../index.md#trait.Double)\n\nUses <multiplication>\n\n"
+        ));
+
+        let trig = page(&pages, "math/trig/index.md");
+        assert!(trig.starts_with("# [prog](../../index.md)::[math](../index.md)::trig\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn html() -> Result<()> {
+        let pages = pages(DocFormat::Html)?;
+
+        let math = page(&pages, "math/index.html");
+        assert!(math.contains("<title>prog::math</title>"));
+        assert!(math.contains("<h3 id=\"fn.square\">square</h3>"));
+        assert!(math.contains(
+            "<pre><code>fn twice&lt;T: <a href=\"../index.html#trait.Double\">Double</a>&gt;(x: T) -&gt; T</code></pre>"
+        ));
+        assert!(math.contains("<p>Uses &lt;multiplication&gt;</p>"));
+        Ok(())
+    }
+}
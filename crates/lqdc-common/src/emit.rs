@@ -0,0 +1,203 @@
+//! Printing diagnostics, either for people or as JSON for tools
+
+use std::{fmt, str::FromStr};
+
+use miette::{Diagnostic, LabeledSpan, Report, Severity, SourceCode};
+use serde_json::{json, Value};
+
+/// How diagnostics are printed, set with `--error-format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// miette's report, with the source around each span
+    #[default]
+    Human,
+    /// One JSON object per line for each diagnostic
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown error format {s}, expected human or json")),
+        }
+    }
+}
+
+impl fmt::Display for ErrorFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Human => write!(f, "human"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl ErrorFormat {
+    /// Prints a diagnostic to stderr
+    pub fn emit(self, report: &Report) {
+        match self {
+            Self::Human => eprintln!("{:?}", report),
+            Self::Json => {
+                for value in to_json(&**report, None) {
+                    eprintln!("{value}");
+                }
+            }
+        }
+    }
+
+    /// Prints the error of a failed compilation and exits, unless it's left
+    /// for miette to print when returned from `main`
+    pub fn exit_on_error<T>(self, result: miette::Result<T>) -> miette::Result<T> {
+        match (result, self) {
+            (Err(error), Self::Json) => {
+                self.emit(&error);
+                std::process::exit(1)
+            }
+            (result, _) => result,
+        }
+    }
+}
+
+/// One JSON object for the diagnostic, after one for each of its related
+/// diagnostics
+pub fn to_json(diagnostic: &dyn Diagnostic, source: Option<&dyn SourceCode>) -> Vec<Value> {
+    let source = diagnostic.source_code().or(source);
+
+    let mut values = vec![];
+    for related in diagnostic.related().into_iter().flatten() {
+        values.extend(to_json(related, source));
+    }
+
+    // `Labelled` only adds a span to the error it wraps, so the rest is
+    // further down the chain
+    let mut chain = vec![diagnostic];
+    let mut current = diagnostic;
+    while let Some(inner) = current.diagnostic_source() {
+        chain.push(inner);
+        current = inner;
+    }
+
+    let message = chain
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .find(|message| !message.is_empty())
+        .unwrap_or_default();
+    let code = chain
+        .iter()
+        .find_map(|diagnostic| diagnostic.code())
+        .map(|code| code.to_string());
+    let help = chain
+        .iter()
+        .find_map(|diagnostic| diagnostic.help())
+        .map(|help| help.to_string());
+    let severity = match chain.iter().find_map(|diagnostic| diagnostic.severity()) {
+        Some(Severity::Advice) => "advice",
+        Some(Severity::Warning) => "warning",
+        Some(Severity::Error) | None => "error",
+    };
+    let source = chain
+        .iter()
+        .find_map(|diagnostic| diagnostic.source_code())
+        .or(source);
+    let spans = chain
+        .iter()
+        .flat_map(|diagnostic| diagnostic.labels().into_iter().flatten())
+        .enumerate()
+        .map(|(i, label)| span_to_json(&label, i == 0, source))
+        .collect::<Vec<_>>();
+
+    values.push(json!({
+        "code": code,
+        "severity": severity,
+        "message": message,
+        "spans": spans,
+        "help": help,
+    }));
+    values
+}
+
+/// The first span of a diagnostic is the primary one
+fn span_to_json(label: &LabeledSpan, primary: bool, source: Option<&dyn SourceCode>) -> Value {
+    let start = label.offset();
+    let end = start + label.len();
+    let (file, line, column) = position(source, start).unwrap_or_default();
+    let (_, end_line, end_column) = position(source, end).unwrap_or_default();
+
+    json!({
+        "file": file,
+        "start": start,
+        "end": end,
+        "line": line,
+        "column": column,
+        "end_line": end_line,
+        "end_column": end_column,
+        "label": label.label(),
+        "primary": primary,
+    })
+}
+
+/// The file name, if the source has one, and the 1-based line and column of a
+/// byte offset
+fn position(
+    source: Option<&dyn SourceCode>,
+    offset: usize,
+) -> Option<(Option<String>, Option<usize>, Option<usize>)> {
+    let contents = source?.read_span(&(offset, 0).into(), 0, 0).ok()?;
+    Some((
+        contents.name().map(str::to_string),
+        Some(contents.line() + 1),
+        Some(contents.column() + 1),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Report;
+
+    use crate::{Error, Errors, IntoLabelled};
+
+    use super::to_json;
+
+    #[test]
+    fn labelled_error() {
+        let input = "fn main -> int {\n    x\n}";
+        let report = Report::from(Error::VarDoesntExist("x".to_string()).labelled((21, 1).into()))
+            .with_source_code(input);
+        let values = to_json(&*report, None);
+
+        assert_eq!(values.len(), 1);
+        let value = &values[0];
+        assert_eq!(value["code"], "L0001");
+        assert_eq!(value["severity"], "error");
+        assert_eq!(value["message"], "Variable x does not exist");
+        assert!(value["help"].is_string());
+
+        let span = &value["spans"][0];
+        assert_eq!(span["start"], 21);
+        assert_eq!(span["end"], 22);
+        assert_eq!(span["line"], 2);
+        assert_eq!(span["column"], 5);
+        assert_eq!(span["primary"], true);
+    }
+
+    #[test]
+    fn one_object_per_error() {
+        let errors: Vec<Report> = vec![
+            Error::VoidValue.labelled((0, 1).into()).into(),
+            Error::InvalidInteger.labelled((2, 1).into()).into(),
+        ];
+        let report = Errors::check(errors).unwrap_err().with_source_code("a b");
+        let values = to_json(&*report, None);
+
+        let codes = values
+            .iter()
+            .map(|value| value["code"].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(codes, [Some("L0022"), Some("L0009"), None]);
+        assert_eq!(values[1]["spans"][0]["column"], 3);
+    }
+}
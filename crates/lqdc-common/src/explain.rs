@@ -0,0 +1,563 @@
+//! Long-form explanations of error codes, printed by `lqdc --explain`
+
+/// The explanation of an error code, like `L0023`
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "L0001",
+        "A variable was used that isn't in scope.
+
+    fn main -> int {
+        x + 1
+    }
+
+Variables are declared with `let`, and only exist after the `let` in the
+function that declares it. Function parameters are also variables.
+
+    fn main -> int {
+        let x = 1;
+        x + 1
+    }
+",
+    ),
+    (
+        "L0002",
+        "A function was called that doesn't exist.
+
+    mod math {
+        pub fn square(x: int) -> int {
+            x * x
+        }
+    }
+
+    fn main -> int {
+        square(2)
+    }
+
+Functions are looked up in the current module, then in the root module.
+Functions in other modules are called by their path:
+
+    fn main -> int {
+        math::square(2)
+    }
+",
+    ),
+    (
+        "L0003",
+        "The source doesn't follow the grammar of Liquid.
+
+    fn main -> int {
+        let x = ;
+        x
+    }
+
+The error points at the first token that couldn't be parsed, the mistake
+is often just before it, like a missing `;` on the previous line. Every
+statement that can't be parsed is reported, so fix the first one first.
+",
+    ),
+    (
+        "L0004",
+        "A function has a return type that doesn't exist.
+
+This error is no longer emitted, unknown return types are reported as
+L0005 like every other unknown type.
+",
+    ),
+    (
+        "L0005",
+        "A type was used that doesn't exist.
+
+    fn double(x: number) -> int {
+        x * 2
+    }
+
+The types are `int`, `uint`, `bool` and `void`, or a type parameter of
+the function, or `Self` in a trait.
+
+    fn double(x: int) -> int {
+        x * 2
+    }
+",
+    ),
+    (
+        "L0006",
+        "The compiler got into a state it shouldn't be able to.
+
+This is a bug in lqdc, not in the program being compiled. Please report it,
+along with the program that caused it.
+",
+    ),
+    (
+        "L0007",
+        "Something was used where it isn't allowed.
+
+    fn main -> int {
+        |x| x
+    }
+
+Some constructs only work in some places:
+
+- `return` can't be used in a closure, the closure returns its body
+- Closures have to be bound with `let` before they can be called
+- Generic functions and closures can't be exported with `pub` or
+  `#[no_mangle]`, since they don't have a single symbol
+- `#[no_mangle]` can't be used on the methods of an impl
+- Functions can't be defined inside other functions
+",
+    ),
+    (
+        "L0008",
+        "A function was called with the wrong number of arguments.
+
+    fn add(a: int, b: int) -> int {
+        a + b
+    }
+
+    fn main -> int {
+        add(1)
+    }
+
+There are no default arguments, every parameter needs a value.
+
+    fn main -> int {
+        add(1, 2)
+    }
+",
+    ),
+    (
+        "L0009",
+        "An integer literal isn't a valid integer.
+
+    fn main -> int {
+        99999999999999999999
+    }
+
+Integer literals have to fit in a signed 64 bit integer, so the largest is
+9223372036854775807, even for a `uint`. There are no floating point
+numbers.
+",
+    ),
+    (
+        "L0010",
+        "A type parameter can't be worked out from the call.
+
+    fn zero<T> -> int {
+        0
+    }
+
+    fn main -> int {
+        zero()
+    }
+
+Type parameters are inferred from the arguments of each call, and can't be
+given explicitly. So every type parameter has to be the type of at least
+one parameter.
+",
+    ),
+    (
+        "L0011",
+        "A type parameter is bounded by a trait that doesn't exist.
+
+    fn twice<T: Doubel>(x: T) -> T {
+        double(double(x))
+    }
+
+Check the spelling, or declare the trait:
+
+    trait Double {
+        fn double(self) -> Self;
+    }
+",
+    ),
+    (
+        "L0012",
+        "A type was used for a type parameter, but doesn't implement its bound.
+
+    trait Double {
+        fn double(self) -> Self;
+    }
+
+    fn twice<T: Double>(x: T) -> T {
+        double(double(x))
+    }
+
+    fn main -> bool {
+        twice(true)
+    }
+
+`twice` can only be called with types that implement `Double`. Implement
+it for the type:
+
+    impl Double for bool {
+        fn double(self) -> bool {
+            self
+        }
+    }
+",
+    ),
+    (
+        "L0013",
+        "A trait is implemented twice for the same type.
+
+    impl Double for int {
+        fn double(self) -> int {
+            self * 2
+        }
+    }
+
+    impl Double for int {
+        fn double(self) -> int {
+            self + self
+        }
+    }
+
+Calls to `double` with an `int` couldn't tell which to use. Remove one of
+the impls.
+",
+    ),
+    (
+        "L0014",
+        "An impl is missing a method of its trait.
+
+    trait Shape {
+        fn area(self) -> int;
+        fn sides(self) -> int;
+    }
+
+    impl Shape for int {
+        fn area(self) -> int {
+            self * self
+        }
+    }
+
+Every method of the trait has to be defined, here `sides` is missing.
+",
+    ),
+    (
+        "L0015",
+        "An impl defines a method that isn't in its trait.
+
+    trait Double {
+        fn double(self) -> Self;
+    }
+
+    impl Double for int {
+        fn double(self) -> int {
+            self * 2
+        }
+
+        fn triple(self) -> int {
+            self * 3
+        }
+    }
+
+Impls can only define the methods of their trait. Define other functions
+outside of the impl, or add the method to the trait.
+",
+    ),
+    (
+        "L0016",
+        "An imported module doesn't exist.
+
+    import math;
+
+`import math;` reads `math.lqd` from the directory of the importing file,
+and puts it in a module called `math`. `import vec;` in `math.lqd` then
+reads `math/vec.lqd`. Check that the file exists.
+",
+    ),
+    (
+        "L0017",
+        "Modules import each other.
+
+    // a.lqd
+    import b;
+
+    // b.lqd
+    import a;
+
+A module can't be imported while it's still being read. Move whatever both
+modules need into a third module, and import that from both.
+",
+    ),
+    (
+        "L0018",
+        "A function with a body is in an extern block.
+
+    extern \"C\" {
+        fn answer -> int {
+            42
+        }
+    }
+
+Extern blocks only declare functions defined somewhere else, like in C.
+To make a function callable from elsewhere, export it with `pub`:
+
+    pub fn answer -> int {
+        42
+    }
+",
+    ),
+    (
+        "L0019",
+        "An extern block or function uses an ABI that isn't supported.
+
+    extern \"stdcall\" fn puts -> int;
+
+The only supported ABI is \"C\", which is also what's used when none is
+given.
+
+    extern \"C\" fn puts -> int;
+",
+    ),
+    (
+        "L0020",
+        "An attribute doesn't exist.
+
+    #[inline]
+    fn main -> int {
+        1
+    }
+
+The attributes are:
+
+- `#[no_mangle]`, to emit a function under its own name
+- `#[allow(lint)]`, `#[warn(lint)]` and `#[deny(lint)]`, to set the level
+  of lints in a function or module
+",
+    ),
+    (
+        "L0021",
+        "A lint doesn't exist.
+
+    #[allow(unused_variable)]
+    fn main -> int {
+        1
+    }
+
+The lints are `unused_variables`, `dead_code`, `unreachable_code`,
+`always_true` and `shadowing`. On the command line, `all` sets every lint,
+and `-D warnings` makes every lint that warns an error.
+",
+    ),
+    (
+        "L0022",
+        "A call to a function that returns `void` was used as a value.
+
+    extern fn nothing -> void;
+
+    fn main -> int {
+        let x = nothing();
+        1
+    }
+
+A void function doesn't produce a value, so it can't be bound with `let`,
+passed as an argument, or used in an expression. Call it as a statement:
+
+    fn main -> int {
+        nothing();
+        1
+    }
+",
+    ),
+    (
+        "L0023",
+        "A value has a different type than where it's used needs.
+
+    fn main -> int {
+        if 1 {
+            2
+        }
+        3
+    }
+
+Here `if` needs a `bool`, but was given a number. Values are never
+converted from one type to another, an `int` can't be used as a `bool` or
+a `uint`. The exception is integer literals, which take the type they're
+used as.
+
+    fn main -> int {
+        let x = 1;
+        if x > 0 {
+            2
+        }
+        3
+    }
+",
+    ),
+    (
+        "L0024",
+        "A pass named with `-Z disable-pass` or `-Z enable-pass` doesn't exist.
+
+    lqdc main.lqd -o main -Z disable-pass=lints
+
+The passes, in the order they run, are `parse`, `lower`,
+`make_signatures`, `type_check`, `lint`, `closure_convert`,
+`monomorphize` and `codegen`. `-Z time-passes` lists the ones that ran.
+",
+    ),
+    (
+        "L0025",
+        "A pass was disabled that a later pass needs.
+
+    lqdc main.lqd -o main -Z disable-pass=type_check
+
+Each pass reads what the pass before it produced, `closure_convert` reads
+the type checked program for example. Only passes that don't change the
+program, like `lint`, can be disabled without disabling the passes after
+them too.
+",
+    ),
+    (
+        "L0026",
+        "A method is called by name, but more than one trait declares it.
+
+    trait Double {
+        fn double(self) -> Self;
+    }
+
+    trait Twice {
+        fn double(self) -> Self;
+    }
+
+    fn main -> int {
+        double(2)
+    }
+
+Calls through a type parameter use the traits it's bounded by, so this
+works as long as only one of them declares the method:
+
+    fn quadruple<T: Double>(x: T) -> T {
+        double(double(x))
+    }
+",
+    ),
+    (
+        "L0027",
+        "Two functions, traits or methods have the same path.
+
+    fn double(x: int) -> int {
+        x * 2
+    }
+
+    fn double(x: int) -> int {
+        x + x
+    }
+
+Each function and trait needs a path of its own, and each method a name
+of its own within its trait or impl. Items in different modules don't
+clash, since their paths differ:
+
+    mod math {
+        fn double(x: int) -> int {
+            x * 2
+        }
+    }
+
+    fn double(x: int) -> int {
+        math::double(x)
+    }
+",
+    ),
+    (
+        "L0028",
+        "An arithmetic or ordering operator is used on values it isn't defined
+for.
+
+    fn main -> bool {
+        true + false > false
+    }
+
+`+`, `-`, `*`, `/`, `<`, `<=`, `>` and `>=` only work on int and uint. Only
+`==` works on bools. Nothing says a type parameter is an integer, so these
+operators don't work on its values either:
+
+    fn double<T>(x: T) -> T {
+        x * 2
+    }
+",
+    ),
+    (
+        "L0029",
+        "Nothing says what type a closure parameter is.
+
+    fn main -> int {
+        let same = |a, b| a == b;
+        1
+    }
+
+A parameter without an annotation gets its type from how the body uses it,
+and from the arguments of calls to the closure. Here `==` works on any
+type, and the closure is never called. Annotating the parameter, or
+calling the closure, fixes it:
+
+    fn main -> bool {
+        let same = |a: int, b| a == b;
+        same(1, 2)
+    }
+",
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use miette::Diagnostic;
+
+    use crate::Error;
+
+    use super::{explain, EXPLANATIONS};
+
+    #[test]
+    fn every_error_is_explained() {
+        let errors = [
+            Error::VarDoesntExist(String::new()),
+            Error::FuncDoesntExist(String::new()),
+            Error::ParseError(String::new()),
+            Error::UnknownReturnType,
+            Error::UnknownType,
+            Error::InternalCompilerError(String::new()),
+            Error::NotAllowedHere(String::new(), String::new()),
+            Error::ExpectedNumArgs(0, 0),
+            Error::InvalidInteger,
+            Error::CannotInferTypeParam(String::new()),
+            Error::TraitDoesntExist(String::new()),
+            Error::UnsatisfiedBound(String::new(), String::new()),
+            Error::ConflictingImpl(String::new(), String::new()),
+            Error::MissingTraitMethod(String::new(), String::new()),
+            Error::NotATraitMethod(String::new(), String::new()),
+            Error::ModuleDoesntExist(String::new()),
+            Error::CircularImport(String::new()),
+            Error::ExternDefinition(String::new()),
+            Error::UnsupportedAbi(String::new()),
+            Error::UnknownAttribute(String::new()),
+            Error::UnknownLint(String::new()),
+            Error::VoidValue,
+            Error::TypeMismatch(String::new(), String::new(), (0..0).into()),
+            Error::UnknownPass(String::new()),
+            Error::PassInputMissing(String::new(), String::new(), String::new()),
+            Error::AmbiguousMethod(String::new(), String::new()),
+            Error::DuplicateDefinition(String::new()),
+            Error::OperatorNotDefined(String::new(), String::new()),
+            Error::CannotInferParamType(String::new()),
+        ];
+        assert_eq!(errors.len(), EXPLANATIONS.len());
+
+        for error in errors {
+            let code = error.code().unwrap().to_string();
+            assert!(explain(&code).is_some(), "{code} isn't explained");
+        }
+    }
+
+    #[test]
+    fn codes_are_case_insensitive() {
+        assert_eq!(explain("l0001"), explain("L0001"));
+        assert!(explain("L9999").is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use miette::SourceSpan;
+
+/// A byte range in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span covering both
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl From<Span> for SourceSpan {
+    fn from(span: Span) -> Self {
+        (span.start..span.end).into()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ident<'input> {
+    pub name: &'input str,
+    pub span: Span,
+}
+
+/// `name` or `module::name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path<'input> {
+    pub segments: Vec<Ident<'input>>,
+    pub span: Span,
+}
+
+impl Path<'_> {
+    pub fn joined(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| segment.name)
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item<'input> {
+    /// `#[name(args)]` attributes, outermost first
+    pub attributes: Vec<Attribute<'input>>,
+    pub kind: ItemKind<'input>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute<'input> {
+    pub name: Ident<'input>,
+    pub args: Vec<Ident<'input>>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemKind<'input> {
+    /// `import name;`
+    Import(Ident<'input>),
+    /// `mod name { items }`
+    Module {
+        name: Ident<'input>,
+        items: Vec<Item<'input>>,
+    },
+    /// `extern "abi" item` or `extern "abi" { items }`
+    Extern {
+        /// The ABI string, without quotes
+        abi: Option<Ident<'input>>,
+        items: Vec<Item<'input>>,
+    },
+    Trait {
+        name: Ident<'input>,
+        methods: Vec<FnSig<'input>>,
+    },
+    /// `impl Trait for type { methods }`
+    Impl {
+        trait_: Ident<'input>,
+        self_type: Ident<'input>,
+        methods: Vec<FnDef<'input>>,
+    },
+    FnDef(FnDef<'input>),
+    /// A function without a body
+    FnDecl(FnSig<'input>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnDef<'input> {
+    /// Declared with `pub fn`
+    pub public: bool,
+    pub sig: FnSig<'input>,
+    pub body: Vec<Expr<'input>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnSig<'input> {
+    pub name: Ident<'input>,
+    pub type_params: Vec<TypeParam<'input>>,
+    pub params: Vec<Param<'input>>,
+    pub ret: Ident<'input>,
+    pub span: Span,
+    /// From the `///` comments before it
+    pub doc: Option<String>,
+}
+
+/// `T` or `T: Bound + OtherBound`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeParam<'input> {
+    pub name: Ident<'input>,
+    pub bounds: Vec<Ident<'input>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Param<'input> {
+    /// `self`, of the type the enclosing impl is for
+    SelfParam(Span),
+    Named {
+        name: Ident<'input>,
+        type_: Ident<'input>,
+    },
+}
+
+/// Identifies an expression, unique across the whole program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr<'input> {
+    pub id: NodeId,
+    pub kind: ExprKind<'input>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind<'input> {
+    /// An integer literal, as written
+    Number(&'input str),
+    Bool(bool),
+    /// A variable, or `self`
+    Var(&'input str),
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr<'input>>,
+        rhs: Box<Expr<'input>>,
+    },
+    /// `let name = value`
+    Let {
+        name: Ident<'input>,
+        value: Box<Expr<'input>>,
+    },
+    Call {
+        path: Path<'input>,
+        args: Vec<Expr<'input>>,
+        /// The parentheses and everything between them
+        args_span: Span,
+    },
+    /// `if condition { body }`
+    If {
+        condition: Box<Expr<'input>>,
+        body: Vec<Expr<'input>>,
+    },
+    /// `return` or `return value`
+    Return(Option<Box<Expr<'input>>>),
+    /// `|params| body`
+    Closure {
+        params: Vec<ClosureParam<'input>>,
+        body: Box<Expr<'input>>,
+    },
+}
+
+/// A closure parameter, where the type is optional
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosureParam<'input> {
+    pub name: Ident<'input>,
+    pub type_: Option<Ident<'input>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Gt,
+    Gte,
+    Eq,
+    Lt,
+    Lte,
+}
+
+impl BinaryOp {
+    /// Comparisons result in a bool, everything else in the type of its operands
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Gt | BinaryOp::Gte | BinaryOp::Eq | BinaryOp::Lt | BinaryOp::Lte
+        )
+    }
+}
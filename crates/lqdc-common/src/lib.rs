@@ -1,7 +1,18 @@
+pub mod closure_convert;
 pub mod codepass;
+pub mod doc;
+pub mod emit;
+pub mod explain;
+pub mod hir;
 pub mod linkage;
+pub mod lint;
+pub mod lower;
 pub mod make_signatures;
+pub mod mangle;
+pub mod modules;
+pub mod monomorphize;
 pub mod parsepass;
+pub mod source_map;
 pub mod type_;
 pub mod type_check;
 
@@ -10,32 +21,204 @@ use std::collections::VecDeque;
 use miette::*;
 use thiserror::Error;
 
+/// Every error has a code, `lqdc --explain <code>` prints the long form from
+/// [`explain`]
 #[derive(Error, Diagnostic, Debug)]
 pub enum Error {
     #[error("Variable {} does not exist", .0)]
+    #[diagnostic(
+        code(L0001),
+        help("Variables have to be declared with `let` before they are used")
+    )]
     VarDoesntExist(String),
     #[error("Function {} does not exist", .0)]
+    #[diagnostic(
+        code(L0002),
+        help("Functions in other modules are called by their path, like `math::{}`", .0)
+    )]
     FuncDoesntExist(String),
     #[error("{}", .0)]
+    #[diagnostic(
+        code(L0003),
+        help("The error points at the first token that didn't fit")
+    )]
     ParseError(String),
     #[error("Unknown return type")]
+    #[diagnostic(
+        code(L0004),
+        help("Return types are int, uint, bool, void or a type parameter")
+    )]
     UnknownReturnType,
     #[error("Unknown type")]
+    #[diagnostic(
+        code(L0005),
+        help("Types are int, uint, bool, void or a type parameter")
+    )]
     UnknownType,
     #[error("Internal compiler error: {}", .0)]
+    #[diagnostic(
+        code(L0006),
+        help("This is a bug in lqdc, please report it with the program that caused it")
+    )]
     InternalCompilerError(String),
     #[error("{} not allowed in {}", .0, .1)]
+    #[diagnostic(code(L0007))]
     NotAllowedHere(String, String),
     #[error("Expected {} args, found {}", .0, .1)]
+    #[diagnostic(code(L0008), help("Check the parameters of the function being called"))]
     ExpectedNumArgs(usize, usize),
     #[error("Malformed integer")]
+    #[diagnostic(
+        code(L0009),
+        help("Integers are whole numbers that fit in 64 bits, there are no floats")
+    )]
     InvalidInteger,
+    #[error("Cannot infer type parameter {}", .0)]
+    #[diagnostic(
+        code(L0010),
+        help("Type parameters are inferred from the arguments, so {} has to be the type of one", .0)
+    )]
+    CannotInferTypeParam(String),
+    #[error("Trait {} does not exist", .0)]
+    #[diagnostic(code(L0011), help("Declare it with `trait {} {{ ... }}`", .0))]
+    TraitDoesntExist(String),
+    #[error("{} does not implement {}", .0, .1)]
+    #[diagnostic(code(L0012), help("Add `impl {} for {} {{ ... }}`", .1, .0))]
+    UnsatisfiedBound(String, String),
+    #[error("Conflicting implementations of {} for {}", .0, .1)]
+    #[diagnostic(code(L0013), help("A type can implement a trait only once"))]
+    ConflictingImpl(String, String),
+    #[error("Missing method {} of trait {}", .1, .0)]
+    #[diagnostic(code(L0014), help("An impl has to define every method of its trait"))]
+    MissingTraitMethod(String, String),
+    #[error("{} is not a method of trait {}", .1, .0)]
+    #[diagnostic(code(L0015), help("An impl can only define the methods of its trait"))]
+    NotATraitMethod(String, String),
+    #[error("Module {} does not exist", .0)]
+    #[diagnostic(
+        code(L0016),
+        help("`import {}` reads {}.lqd next to the importing file", .0, .0)
+    )]
+    ModuleDoesntExist(String),
+    #[error("Circular import of {}", .0)]
+    #[diagnostic(code(L0017), help("Move what both modules need into a third one"))]
+    CircularImport(String),
+    #[error("Function {} is defined in an extern block", .0)]
+    #[diagnostic(
+        code(L0018),
+        help(
+            "extern only declares functions defined elsewhere, export it with `pub fn {}` instead",
+            .0
+        )
+    )]
+    ExternDefinition(String),
+    #[error("Unsupported ABI {}", .0)]
+    #[diagnostic(code(L0019), help("The only supported ABI is \"C\""))]
+    UnsupportedAbi(String),
+    #[error("Unknown attribute {}", .0)]
+    #[diagnostic(
+        code(L0020),
+        help("The attributes are no_mangle, allow, warn and deny")
+    )]
+    UnknownAttribute(String),
+    #[error("Unknown lint {}", .0)]
+    #[diagnostic(
+        code(L0021),
+        help("The lints are unused_variables, dead_code, unreachable_code, always_true and shadowing")
+    )]
+    UnknownLint(String),
+    #[error("Expression has no value")]
+    #[diagnostic(
+        code(L0022),
+        help("Functions returning void can only be called as statements")
+    )]
+    VoidValue,
     #[error("Expected {}, found {}", .0, .1)]
+    #[diagnostic(
+        code(L0023),
+        help("Values are never converted implicitly, apart from integer literals")
+    )]
     TypeMismatch(
         String,
         String,
         #[label("This should be a {}", .0)] SourceSpan,
     ),
+    #[error("Unknown pass {}", .0)]
+    #[diagnostic(
+        code(L0024),
+        help("The passes are parse, lower, make_signatures, type_check, lint, closure_convert, monomorphize and codegen")
+    )]
+    UnknownPass(String),
+    #[error("Pass {} needs {}, but the passes before it produce {}", .0, .1, .2)]
+    #[diagnostic(code(L0025), help("A pass it depends on may have been disabled"))]
+    PassInputMissing(String, String, String),
+    #[error("Method {} is declared by more than one trait: {}", .0, .1)]
+    #[diagnostic(
+        code(L0026),
+        help("Bound a type parameter by the trait it should be called through")
+    )]
+    AmbiguousMethod(String, String),
+    #[error("{} is defined more than once", .0)]
+    #[diagnostic(
+        code(L0027),
+        help("Rename one of the definitions, or move it into another module")
+    )]
+    DuplicateDefinition(String),
+    #[error("Operator {} isn't defined for {}", .0, .1)]
+    #[diagnostic(
+        code(L0028),
+        help("Arithmetic and ordering are only defined for int and uint")
+    )]
+    OperatorNotDefined(String, String),
+    #[error("Cannot infer the type of parameter {}", .0)]
+    #[diagnostic(
+        code(L0029),
+        help("Annotate it, like `|{}: int| ...`, or call the closure", .0)
+    )]
+    CannotInferParamType(String),
+}
+
+/// Every error found by a pass that keeps going after the first
+#[derive(Error, Debug)]
+#[error("Aborting due to {} errors", .errors.len() + .hidden)]
+pub struct Errors {
+    errors: Vec<Report>,
+    /// Errors left out because of the error limit
+    hidden: usize,
+}
+
+impl Errors {
+    /// Fails with the errors if there are any. A single error is returned as
+    /// it is.
+    pub fn check(mut errors: Vec<Report>) -> Result<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Errors { errors, hidden: 0 }.into()),
+        }
+    }
+
+    /// Keeps only the first `limit` errors
+    pub fn limit(&mut self, limit: usize) {
+        if self.errors.len() > limit {
+            self.hidden += self.errors.len() - limit;
+            self.errors.truncate(limit);
+        }
+    }
+}
+
+impl Diagnostic for Errors {
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        (self.hidden > 0).then(|| {
+            Box::new(format!("{} more errors not shown", self.hidden)) as Box<dyn std::fmt::Display>
+        })
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(
+            self.errors.iter().map(|error| &**error as &dyn Diagnostic),
+        ))
+    }
 }
 
 #[derive(Error, Diagnostic, Debug)]
@@ -72,8 +255,15 @@ impl<E: Diagnostic + 'static + Sized> IntoLabelled for E {
 #[derive(PartialEq)]
 pub enum ScopeType {
     Extern,
+    NoMangle,
+    /// Inside an impl block, for the given type
+    Impl(type_::Type),
+    Module(String),
+    /// Under `#[allow]`, `#[warn]` or `#[deny]`
+    Lint(lint::Lint, lint::Level),
 }
 
+#[derive(Default)]
 pub struct Stack<T>(VecDeque<T>);
 
 impl<T> Stack<T> {
@@ -86,7 +276,7 @@ impl<T> Stack<T> {
     pub fn new() -> Self {
         Self(VecDeque::new())
     }
-    pub fn iter(&mut self) -> std::collections::vec_deque::Iter<T> {
+    pub fn iter(&mut self) -> std::collections::vec_deque::Iter<'_, T> {
         self.0.iter()
     }
 }
@@ -0,0 +1,598 @@
+//! Warnings about code that compiles, but is probably a mistake
+//!
+//! Each lint has a level, which can be changed for the whole program with
+//! `-A`, `-W` and `-D`, and for a function or module with `#[allow(...)]`,
+//! `#[warn(...)]` and `#[deny(...)]`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+};
+
+use miette::*;
+
+use crate::{
+    codepass::{CodePass, Is},
+    emit::ErrorFormat,
+    hir::{BinaryOp, Expr, ExprKind, Span},
+    linkage::Linkage,
+    make_signatures::Function,
+    mangle::mangle_impl,
+    source_map::SourceMap,
+    type_::Type,
+    type_check::{TypeCheckPass, Types},
+    Error, Errors,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A `let` binding that is never read
+    UnusedVariables,
+    /// A private function that is never called
+    DeadCode,
+    /// Code after a `return`
+    UnreachableCode,
+    /// A comparison that can't be false
+    AlwaysTrue,
+    /// A `let` binding with the same name as one already in scope
+    Shadowing,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::UnusedVariables,
+        Lint::DeadCode,
+        Lint::UnreachableCode,
+        Lint::AlwaysTrue,
+        Lint::Shadowing,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariables => "unused_variables",
+            Lint::DeadCode => "dead_code",
+            Lint::UnreachableCode => "unreachable_code",
+            Lint::AlwaysTrue => "always_true",
+            Lint::Shadowing => "shadowing",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn default_level(self) -> Level {
+        match self {
+            // Shadowing is often deliberate
+            Lint::Shadowing => Level::Allow,
+            _ => Level::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// The level of every lint for the whole program
+#[derive(Debug, Clone)]
+pub struct LintLevels(HashMap<Lint, Level>);
+
+impl Default for LintLevels {
+    fn default() -> Self {
+        Self(
+            Lint::ALL
+                .into_iter()
+                .map(|lint| (lint, lint.default_level()))
+                .collect(),
+        )
+    }
+}
+
+impl LintLevels {
+    /// Sets the level of a lint by name. `all` is every lint, and `warnings`
+    /// is every lint that is currently a warning.
+    pub fn set(&mut self, name: &str, level: Level) -> Result<(), Error> {
+        match name {
+            "all" => self.0.values_mut().for_each(|l| *l = level),
+            "warnings" => self
+                .0
+                .values_mut()
+                .filter(|l| **l == Level::Warn)
+                .for_each(|l| *l = level),
+            _ => {
+                let lint =
+                    Lint::from_name(name).ok_or_else(|| Error::UnknownLint(name.to_string()))?;
+                self.0.insert(lint, level);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, lint: Lint) -> Level {
+        self.0[&lint]
+    }
+}
+
+/// A lint that fired
+#[derive(thiserror::Error, Debug)]
+#[error("{message}")]
+pub struct Warning {
+    lint: Lint,
+    level: Level,
+    message: String,
+    label: SourceSpan,
+}
+
+impl Diagnostic for Warning {
+    fn severity(&self) -> Option<Severity> {
+        match self.level {
+            Level::Deny => Some(Severity::Error),
+            _ => Some(Severity::Warning),
+        }
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(format!(
+            "`#[allow({})]` silences this",
+            self.lint.name()
+        )))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(Box::new(std::iter::once(LabeledSpan::underline(
+            self.label,
+        ))))
+    }
+}
+
+/// The levels to lint with, and the warnings found
+#[derive(Default)]
+pub struct Lints {
+    pub levels: LintLevels,
+    pub warnings: Vec<Report>,
+    /// Names warnings by the file they are in, like `PassRunner::with_source_map`
+    pub source_map: Option<SourceMap>,
+}
+
+impl Lints {
+    pub fn new(levels: LintLevels) -> Self {
+        Self {
+            levels,
+            ..Default::default()
+        }
+    }
+
+    /// Prints the warnings. Denied lints are errors of `LintPass` instead.
+    pub fn report(&mut self, format: ErrorFormat) {
+        for warning in self.warnings.drain(..) {
+            format.emit(&warning);
+        }
+    }
+}
+
+/// Runs every lint over the type checked program. The lints only need to be
+/// borrowed for this pass, not for as long as the input.
+pub struct LintPass<'a>(PhantomData<&'a mut Lints>);
+
+impl<'input, 'a> CodePass<'input> for LintPass<'a> {
+    const NAME: &'static str = "lint";
+    type Prev = TypeCheckPass<'input>;
+    type Arg = &'a mut Lints;
+
+    fn check(
+        prev: Self::Prev,
+        input: &'input str,
+        lints: &mut impl Is<Self::Arg>,
+    ) -> Result<Self::Prev> {
+        let lints: &mut Lints = lints.is_mut();
+        let signatures = &prev.signatures;
+        let mut denied = vec![];
+
+        let mut functions = vec![];
+        for (path, function) in &signatures.functions {
+            if function.linkage != Linkage::External {
+                functions.push((path.clone(), function, false));
+            }
+        }
+        for ((trait_, self_type), impl_) in &signatures.impls {
+            for (name, method) in &impl_.methods {
+                functions.push((mangle_impl(trait_, *self_type, name), method, true));
+            }
+        }
+        functions.sort_by_key(|(_, function, _)| function.span.start);
+
+        // A function is used if it's reachable from one that's exported, which
+        // includes `main`. Methods are called through their trait, so aren't in
+        // `calls` under their symbol, and count as used too.
+        let mut callees = HashMap::<_, Vec<_>>::new();
+        for call in prev.calls.values() {
+            callees
+                .entry(call.caller.as_str())
+                .or_default()
+                .push(call.callee.as_str());
+        }
+        let mut queue = functions
+            .iter()
+            .filter(|(_, function, method)| {
+                *method || function.linkage != Linkage::Private || function.no_mangle
+            })
+            .map(|(symbol, _, _)| symbol.as_str())
+            .collect::<Vec<_>>();
+        let mut used = HashSet::new();
+        while let Some(symbol) = queue.pop() {
+            if used.insert(symbol) {
+                queue.extend(callees.get(symbol).into_iter().flatten());
+            }
+        }
+
+        for (symbol, function, _) in &functions {
+            let mut found = vec![];
+
+            if !used.contains(symbol.as_str()) {
+                found.push((
+                    Lint::DeadCode,
+                    format!("Function {symbol} is never used"),
+                    function.span,
+                ));
+            }
+
+            let mut linter = Linter {
+                symbol,
+                types: &prev.types,
+                bindings: function
+                    .args
+                    .iter()
+                    .map(|(name, _)| Binding {
+                        name,
+                        span: function.span,
+                        used: true,
+                    })
+                    .collect(),
+                found,
+            };
+            linter.body(&function.body);
+            linter.unused(0);
+
+            for (lint, message, span) in linter.found {
+                let level = level(lints, function, lint);
+                let warning = Warning {
+                    lint,
+                    level,
+                    message,
+                    label: span.into(),
+                };
+                let warning = match &lints.source_map {
+                    Some(source_map) => Report::new(warning).with_source_code(source_map.clone()),
+                    None => Report::new(warning).with_source_code(input.to_string()),
+                };
+                match level {
+                    Level::Allow => {}
+                    Level::Warn => lints.warnings.push(warning),
+                    Level::Deny => denied.push(warning),
+                }
+            }
+        }
+
+        Errors::check(denied)?;
+        Ok(prev)
+    }
+}
+
+/// The level of a lint in a function, the innermost attribute wins
+fn level(lints: &Lints, function: &Function, lint: Lint) -> Level {
+    function
+        .lints
+        .iter()
+        .rev()
+        .find(|(l, _)| *l == lint)
+        .map_or(lints.levels.get(lint), |(_, level)| *level)
+}
+
+struct Binding<'input> {
+    name: &'input str,
+    span: Span,
+    used: bool,
+}
+
+/// Walks the body of one function
+struct Linter<'input, 'a> {
+    symbol: &'a str,
+    types: &'a Types,
+    /// Variables in scope, innermost last
+    bindings: Vec<Binding<'input>>,
+    found: Vec<(Lint, String, Span)>,
+}
+
+impl<'input> Linter<'input, '_> {
+    fn body(&mut self, body: &[Expr<'input>]) {
+        for expr in body {
+            self.expr(expr);
+        }
+
+        let returns = body
+            .iter()
+            .position(|expr| matches!(expr.kind, ExprKind::Return(_)));
+        if let (Some(returns), Some(last)) = (returns, body.last()) {
+            if let Some(first) = body.get(returns + 1) {
+                self.found.push((
+                    Lint::UnreachableCode,
+                    "Unreachable code".to_string(),
+                    first.span.to(last.span),
+                ));
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr<'input>) {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::Bool(_) => {}
+            ExprKind::Var(name) => self.use_(name),
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                if self.always_true(*op, lhs, rhs) {
+                    self.found.push((
+                        Lint::AlwaysTrue,
+                        "Comparison is always true".to_string(),
+                        expr.span,
+                    ));
+                }
+            }
+            ExprKind::Let { name, value } => {
+                self.expr(value);
+                if self.bindings.iter().any(|b| b.name == name.name) {
+                    self.found.push((
+                        Lint::Shadowing,
+                        format!("{} shadows an earlier binding", name.name),
+                        name.span,
+                    ));
+                }
+                self.bindings.push(Binding {
+                    name: name.name,
+                    span: name.span,
+                    used: false,
+                });
+            }
+            ExprKind::Call { path, args, .. } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                // A closure bound to a variable
+                if let [name] = path.segments.as_slice() {
+                    self.use_(name.name);
+                }
+            }
+            ExprKind::If { condition, body } => {
+                self.expr(condition);
+                let outer = self.bindings.len();
+                self.body(body);
+                self.unused(outer);
+                self.bindings.truncate(outer);
+            }
+            ExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Closure { params, body } => {
+                let outer = self.bindings.len();
+                for param in params {
+                    self.bindings.push(Binding {
+                        name: param.name.name,
+                        span: param.name.span,
+                        used: true,
+                    });
+                }
+                self.expr(body);
+                self.unused(outer);
+                self.bindings.truncate(outer);
+            }
+        }
+    }
+
+    fn use_(&mut self, name: &str) {
+        if let Some(binding) = self.bindings.iter_mut().rev().find(|b| b.name == name) {
+            binding.used = true;
+        }
+    }
+
+    /// Reports the bindings from `from` on that were never used
+    fn unused(&mut self, from: usize) {
+        for binding in &self.bindings[from..] {
+            if !binding.used && !binding.name.starts_with('_') {
+                self.found.push((
+                    Lint::UnusedVariables,
+                    format!("Unused variable {}", binding.name),
+                    binding.span,
+                ));
+            }
+        }
+    }
+
+    fn always_true(&self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> bool {
+        use BinaryOp::*;
+
+        match (&lhs.kind, op, &rhs.kind) {
+            // `x == x`
+            (ExprKind::Var(a), Eq | Gte | Lte, ExprKind::Var(b)) => a == b,
+            // `1 < 2`
+            (ExprKind::Number(a), _, ExprKind::Number(b)) => {
+                match (a.parse::<i128>(), b.parse::<i128>()) {
+                    (Ok(a), Ok(b)) => match op {
+                        Gt => a > b,
+                        Gte => a >= b,
+                        Eq => a == b,
+                        Lt => a < b,
+                        Lte => a <= b,
+                        Add | Sub | Mul | Div => false,
+                    },
+                    _ => false,
+                }
+            }
+            // `x >= 0` for unsigned `x`
+            (_, Gte, ExprKind::Number("0")) => self.type_of(lhs) == Some(Type::Uint),
+            (ExprKind::Number("0"), Lte, _) => self.type_of(rhs) == Some(Type::Uint),
+            _ => false,
+        }
+    }
+
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        self.types.get(&(self.symbol.to_string(), expr.id)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::*;
+
+    use crate::{
+        codepass::PassRunner, lower::LowerPass, make_signatures::MakeSignaturesPass,
+        parsepass::ParsePass, type_check::TypeCheckPass,
+    };
+
+    use super::{Level, LintLevels, LintPass, Lints};
+
+    fn lint(input: &str, levels: LintLevels) -> Result<Lints> {
+        let mut lints = Lints::new(levels);
+        PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?
+            .set_arg(&mut lints)
+            .inject::<LintPass>()?;
+
+        Ok(lints)
+    }
+
+    fn messages(lints: &Lints) -> Vec<String> {
+        lints
+            .warnings
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn unused_variable_and_function() -> Result<()> {
+        let input = "
+        fn main -> int {
+            let unused = 1;
+            let _ignored = 2;
+            3
+        }
+
+        fn helper -> int {
+            4
+        }
+        ";
+        let lints = lint(input, LintLevels::default())?;
+
+        assert_eq!(
+            messages(&lints),
+            ["Unused variable unused", "Function helper is never used"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unused_recursion() -> Result<()> {
+        let input = "
+        fn main -> int {
+            used(1)
+        }
+
+        fn used(n: int) -> int {
+            if n > 0 {
+                return used(n - 1);
+            };
+            n
+        }
+
+        fn even(n: int) -> bool {
+            if n == 0 {
+                return true;
+            };
+            odd(n - 1)
+        }
+
+        fn odd(n: int) -> bool {
+            if n == 0 {
+                return false;
+            };
+            even(n - 1)
+        }
+        ";
+        let lints = lint(input, LintLevels::default())?;
+
+        assert_eq!(
+            messages(&lints),
+            ["Function even is never used", "Function odd is never used"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unreachable_and_always_true() -> Result<()> {
+        let input = "
+        fn main -> bool {
+            let x = 1;
+            return x == x;
+            false
+        }
+        ";
+        let lints = lint(input, LintLevels::default())?;
+
+        assert_eq!(
+            messages(&lints),
+            ["Comparison is always true", "Unreachable code"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn allow_attribute() -> Result<()> {
+        let input = "
+        #[allow(dead_code)]
+        fn helper -> int {
+            let x = 1;
+            2
+        }
+
+        fn main -> int {
+            3
+        }
+        ";
+        let lints = lint(input, LintLevels::default())?;
+
+        assert_eq!(messages(&lints), ["Unused variable x"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deny_shadowing() -> Result<()> {
+        let input = "
+        fn main -> int {
+            let x = 1;
+            let x = x + 1;
+            x
+        }
+        ";
+        let mut levels = LintLevels::default();
+        assert!(lint(input, levels.clone())?.warnings.is_empty());
+
+        levels.set("shadowing", Level::Deny)?;
+        assert!(lint(input, levels).is_err());
+
+        Ok(())
+    }
+}
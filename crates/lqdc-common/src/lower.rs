@@ -0,0 +1,358 @@
+use frontend::node::{Node, NodeValue};
+use miette::*;
+
+use crate::{
+    codepass::{CodePass, Is},
+    hir::{
+        Attribute, BinaryOp, ClosureParam, Expr, ExprKind, FnDef, FnSig, Ident, Item, ItemKind,
+        NodeId, Param, Path, Span, TypeParam,
+    },
+    parsepass::ParsePass,
+    Error, IntoLabelled,
+};
+
+/// Lowers the parse tree into the HIR
+pub struct LowerPass<'input> {
+    pub items: Vec<Item<'input>>,
+}
+
+impl<'input> CodePass<'input> for LowerPass<'input> {
+    const NAME: &'static str = "lower";
+    type Prev = ParsePass;
+    type Arg = ();
+
+    fn pass(prev: Self::Prev, input: &'input str, _: &mut impl Is<Self::Arg>) -> Result<Self> {
+        let items = Lowerer::new(input).lower_items(&prev.nodes)?;
+
+        Ok(Self { items })
+    }
+}
+
+fn span(node: &Node) -> Span {
+    Span::new(node.start, node.end)
+}
+
+/// The grammar only produces a node where it is expected, so this is a bug
+fn unexpected(node: &Node) -> Report {
+    Error::InternalCompilerError(format!("Unexpected {:?} node", node.node))
+        .labelled(span(node).into())
+        .into()
+}
+
+/// Lowers nodes of one source, numbering expressions as it goes
+pub struct Lowerer<'input> {
+    input: &'input str,
+    next_id: usize,
+}
+
+impl<'input> Lowerer<'input> {
+    pub fn new(input: &'input str) -> Self {
+        Self { input, next_id: 0 }
+    }
+
+    fn ident(&self, node: &Node) -> Ident<'input> {
+        Ident {
+            name: &self.input[node.start..node.end],
+            span: span(node),
+        }
+    }
+
+    fn expr(&mut self, kind: ExprKind<'input>, span: Span) -> Expr<'input> {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        Expr { id, kind, span }
+    }
+
+    pub fn lower_item(&mut self, node: &Node) -> Result<Item<'input>> {
+        let input = self.input;
+        let item_span = span(node);
+
+        // Attributes wrap the item they apply to, which is always the last child
+        let mut attributes = vec![];
+        let mut node = node;
+        while node.node == NodeValue::Attribute {
+            let item = &node.children[node.children.len() - 1];
+            let args = node.children[1..node.children.len() - 1]
+                .iter()
+                .filter(|arg| arg.node == NodeValue::Id)
+                .map(|arg| self.ident(arg))
+                .collect();
+            let text = input[node.start..item.start].trim_end();
+            attributes.push(Attribute {
+                name: self.ident(&node.children[0]),
+                args,
+                span: Span::new(node.start, node.start + text.len()),
+            });
+            node = item;
+        }
+
+        let kind = match node.node {
+            NodeValue::Import => ItemKind::Import(self.ident(&node.children[0])),
+            NodeValue::Module => ItemKind::Module {
+                name: self.ident(&node.children[0]),
+                items: self.lower_items(&node.children[1..])?,
+            },
+            NodeValue::Extern => {
+                let (abi, items) = match node.children.split_first() {
+                    Some((abi, items)) if abi.node == NodeValue::Abi => (Some(abi), items),
+                    _ => (None, node.children.as_slice()),
+                };
+                ItemKind::Extern {
+                    abi: abi.map(|abi| Ident {
+                        name: &input[abi.start + 1..abi.end - 1],
+                        span: span(abi),
+                    }),
+                    items: self.lower_items(items)?,
+                }
+            }
+            NodeValue::Trait => ItemKind::Trait {
+                name: self.ident(&node.children[0]),
+                methods: node.children[1..]
+                    .iter()
+                    .map(|method| self.lower_fn_decl(method))
+                    .collect::<Result<_>>()?,
+            },
+            NodeValue::Impl => ItemKind::Impl {
+                trait_: self.ident(&node.children[0]),
+                self_type: self.ident(&node.children[1]),
+                methods: node.children[2..]
+                    .iter()
+                    .map(|method| self.lower_fn_def(method, false))
+                    .collect::<Result<_>>()?,
+            },
+            NodeValue::Pub => ItemKind::FnDef(self.lower_fn_def(&node.children[0], true)?),
+            NodeValue::FnDef => ItemKind::FnDef(self.lower_fn_def(node, false)?),
+            NodeValue::FnDecl => ItemKind::FnDecl(self.lower_fn_decl(node)?),
+            _ => return Err(unexpected(node)),
+        };
+
+        Ok(Item {
+            attributes,
+            kind,
+            span: item_span,
+        })
+    }
+
+    pub fn lower_items(&mut self, nodes: &[Node]) -> Result<Vec<Item<'input>>> {
+        nodes.iter().map(|node| self.lower_item(node)).collect()
+    }
+
+    fn lower_fn_def(&mut self, node: &Node, public: bool) -> Result<FnDef<'input>> {
+        if node.node != NodeValue::FnDef {
+            return Err(unexpected(node));
+        }
+
+        let type_params = node.children[1]
+            .children
+            .iter()
+            .map(|param| TypeParam {
+                name: self.ident(&param.children[0]),
+                bounds: param.children[1..]
+                    .iter()
+                    .map(|bound| self.ident(bound))
+                    .collect(),
+            })
+            .collect();
+
+        Ok(FnDef {
+            public,
+            sig: FnSig {
+                name: self.ident(&node.children[0]),
+                type_params,
+                params: self.lower_params(&node.children[2])?,
+                ret: self.ident(&node.children[3]),
+                span: span(node),
+                doc: node.doc.clone(),
+            },
+            body: node.children[4..]
+                .iter()
+                .map(|expr| self.lower_expr(expr))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn lower_fn_decl(&self, node: &Node) -> Result<FnSig<'input>> {
+        if node.node != NodeValue::FnDecl {
+            return Err(unexpected(node));
+        }
+
+        Ok(FnSig {
+            name: self.ident(&node.children[0]),
+            type_params: vec![],
+            params: self.lower_params(&node.children[1])?,
+            ret: self.ident(&node.children[2]),
+            span: span(node),
+            doc: node.doc.clone(),
+        })
+    }
+
+    /// Reads the arguments of a function definition or declaration, which are
+    /// either `self` or a name followed by its type
+    fn lower_params(&self, node: &Node) -> Result<Vec<Param<'input>>> {
+        let mut params = vec![];
+        let mut children = node.children.iter();
+        while let Some(child) = children.next() {
+            match child.node {
+                NodeValue::SelfParam => params.push(Param::SelfParam(span(child))),
+                NodeValue::Id => {
+                    let type_ = children.next().ok_or_else(|| unexpected(child))?;
+                    params.push(Param::Named {
+                        name: self.ident(child),
+                        type_: self.ident(type_),
+                    });
+                }
+                _ => return Err(unexpected(child)),
+            }
+        }
+
+        Ok(params)
+    }
+
+    pub fn lower_expr(&mut self, node: &Node) -> Result<Expr<'input>> {
+        let input = self.input;
+        let kind = match node.node {
+            NodeValue::Binary => {
+                let [lhs, op, rhs] = node.children.as_slice() else {
+                    return Err(unexpected(node));
+                };
+                ExprKind::Binary {
+                    op: binary_op(op)?,
+                    lhs: Box::new(self.lower_expr(lhs)?),
+                    rhs: Box::new(self.lower_expr(rhs)?),
+                }
+            }
+            NodeValue::Number => ExprKind::Number(&input[node.start..node.end]),
+            NodeValue::True => ExprKind::Bool(true),
+            NodeValue::False => ExprKind::Bool(false),
+            NodeValue::Id => ExprKind::Var(&input[node.start..node.end]),
+            NodeValue::VarAssign => ExprKind::Let {
+                name: self.ident(&node.children[0]),
+                value: Box::new(self.lower_expr(&node.children[1])?),
+            },
+            NodeValue::FnCall => {
+                let path = &node.children[0];
+                let path = Path {
+                    segments: path.children.iter().map(|id| self.ident(id)).collect(),
+                    span: span(path),
+                };
+                let arg_set = &node.children[1];
+                let args = match arg_set.node {
+                    NodeValue::FnCallArgSet => arg_set
+                        .children
+                        .iter()
+                        .map(|arg| self.lower_expr(arg))
+                        .collect::<Result<_>>()?,
+                    _ => vec![],
+                };
+                ExprKind::Call {
+                    args_span: Span::new(path.span.end, node.end),
+                    path,
+                    args,
+                }
+            }
+            NodeValue::If => ExprKind::If {
+                condition: Box::new(self.lower_expr(&node.children[0])?),
+                body: node.children[1..]
+                    .iter()
+                    .map(|expr| self.lower_expr(expr))
+                    .collect::<Result<_>>()?,
+            },
+            NodeValue::Return => match node.children.first() {
+                Some(value) => ExprKind::Return(Some(Box::new(self.lower_expr(value)?))),
+                None => ExprKind::Return(None),
+            },
+            NodeValue::Closure => {
+                let params = match node.children[0].node {
+                    NodeValue::ClosureParams => node.children[0]
+                        .children
+                        .iter()
+                        .map(|param| ClosureParam {
+                            name: self.ident(&param.children[0]),
+                            type_: param.children.get(1).map(|type_| self.ident(type_)),
+                        })
+                        .collect(),
+                    _ => vec![],
+                };
+                ExprKind::Closure {
+                    params,
+                    body: Box::new(self.lower_expr(&node.children[node.children.len() - 1])?),
+                }
+            }
+            NodeValue::FnDef => bail!(Error::NotAllowedHere(
+                "Function definitions".to_string(),
+                "expressions".to_string()
+            )
+            .labelled(span(node).into())),
+            _ => return Err(unexpected(node)),
+        };
+
+        Ok(self.expr(kind, span(node)))
+    }
+}
+
+fn binary_op(node: &Node) -> Result<BinaryOp> {
+    Ok(match node.node {
+        NodeValue::Add => BinaryOp::Add,
+        NodeValue::Sub => BinaryOp::Sub,
+        NodeValue::Mul => BinaryOp::Mul,
+        NodeValue::Div => BinaryOp::Div,
+        NodeValue::GT => BinaryOp::Gt,
+        NodeValue::GTE => BinaryOp::Gte,
+        NodeValue::EQ => BinaryOp::Eq,
+        NodeValue::LT => BinaryOp::Lt,
+        NodeValue::LTE => BinaryOp::Lte,
+        _ => return Err(unexpected(node)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::*;
+
+    use crate::{
+        codepass::PassRunner,
+        hir::{BinaryOp, ExprKind, ItemKind},
+        parsepass::ParsePass,
+    };
+
+    use super::LowerPass;
+
+    #[test]
+    fn operators_are_left_associative() -> Result<()> {
+        let input = "fn main -> int { 1 - 2 + 3 * 4 }";
+        let items = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .into_inner()
+            .items;
+
+        let function = match &items[0].kind {
+            ItemKind::FnDef(function) => function,
+            _ => panic!("Expected a function"),
+        };
+        let (lhs, rhs) = match &function.body[0].kind {
+            ExprKind::Binary {
+                op: BinaryOp::Add,
+                lhs,
+                rhs,
+            } => (lhs, rhs),
+            kind => panic!("Expected an addition, found {:?}", kind),
+        };
+        assert!(matches!(
+            lhs.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Sub,
+                ..
+            }
+        ));
+        assert!(matches!(
+            rhs.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Mul,
+                ..
+            }
+        ));
+
+        Ok(())
+    }
+}
@@ -1,150 +1,669 @@
-use std::collections::HashMap;
-use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
 
+use crate::hir::{Expr, FnDef, FnSig, Item, ItemKind, Param, Span};
 use crate::linkage::Linkage;
+use crate::lint::{Level, Lint};
 use crate::{
     codepass::{CodePass, Is},
+    lower::LowerPass,
     type_::Type,
-    IntoLabelled, ScopeType, Stack,
+    Error, IntoLabelled, ScopeType, Stack,
 };
-use frontend::node::NodeValue;
-use lang_pt::ASTNode;
+use miette::*;
 
-use crate::parsepass::ParsePass;
+pub struct Function<'input> {
+    pub linkage: Linkage,
+    /// Names of the type parameters, `Type::Generic(n)` refers to the nth one
+    pub type_params: Vec<&'input str>,
+    /// Traits each type parameter must implement, keyed like
+    /// [`Signatures::traits`]
+    pub bounds: Vec<Vec<String>>,
+    pub args: Vec<(&'input str, Type)>,
+    pub ret_type: Type,
+    pub body: Vec<Expr<'input>>,
+    /// Path of the module the function is in, empty for the root module
+    pub module: String,
+    /// Emitted under its own name rather than a mangled one, see `crate::mangle`
+    pub no_mangle: bool,
+    /// Lint levels set with `#[allow]`, `#[warn]` and `#[deny]`, outermost first
+    pub lints: Vec<(Lint, Level)>,
+    /// The signature, from `fn` to the return type
+    pub span: Span,
+    /// From the `///` comments before it
+    pub doc: Option<String>,
+}
+
+impl Function<'_> {
+    pub fn is_generic(&self) -> bool {
+        !self.type_params.is_empty()
+    }
+}
+
+/// Method signatures of a trait. Each is a generic function whose only type
+/// parameter is `Self`, bounded by the trait.
+pub struct Trait<'input> {
+    pub methods: HashMap<&'input str, Function<'input>>,
+    pub span: Span,
+}
+
+pub struct Impl<'input> {
+    pub methods: HashMap<&'input str, Function<'input>>,
+    pub span: Span,
+}
+
+/// Everything declared at the top level of every module
+#[derive(Default)]
+pub struct Signatures<'input> {
+    /// Keyed by path, such as `math::sqrt`
+    pub functions: HashMap<String, Function<'input>>,
+    /// Keyed by path, like functions
+    pub traits: HashMap<String, Trait<'input>>,
+    /// Keyed by trait, then implementing type
+    pub impls: HashMap<(String, Type), Impl<'input>>,
+}
+
+impl<'input> Signatures<'input> {
+    /// Finds the trait declaring a method. Of the traits in `bounds`, those
+    /// of the caller's type parameters, only one may declare it, or only one
+    /// trait at all if none of them do.
+    pub fn trait_method(
+        &self,
+        name: &str,
+        bounds: &[String],
+    ) -> Result<Option<(&str, &Function<'input>)>, Error> {
+        let declaring = self
+            .traits
+            .iter()
+            .filter_map(|(trait_, t)| t.methods.get(name).map(|method| (trait_.as_str(), method)))
+            .collect::<Vec<_>>();
+        let bounded = declaring
+            .iter()
+            .filter(|(trait_, _)| bounds.iter().any(|bound| bound == trait_))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut candidates = if bounded.is_empty() {
+            declaring
+        } else {
+            bounded
+        };
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [found] => Ok(Some(*found)),
+            _ => {
+                candidates.sort_by_key(|(trait_, _)| *trait_);
+                let traits = candidates
+                    .iter()
+                    .map(|(trait_, _)| *trait_)
+                    .collect::<Vec<_>>();
+                Err(Error::AmbiguousMethod(name.to_string(), traits.join(", ")))
+            }
+        }
+    }
+
+    /// Resolves the path of a trait used in `module`, like [`Self::resolve_function`]
+    pub fn resolve_trait(&self, path: &str, module: &str) -> Option<String> {
+        resolve_trait(&self.traits, path, module)
+    }
+
+    /// Resolves a path used in `module`, relative to it first and then to the root
+    pub fn resolve_function(
+        &self,
+        path: &str,
+        module: &str,
+    ) -> Option<(String, &Function<'input>)> {
+        let relative = qualify(module, path);
+        [relative, path.to_string()]
+            .into_iter()
+            .find_map(|path| self.functions.get(&path).map(|function| (path, function)))
+    }
+}
+
+fn resolve_trait(traits: &HashMap<String, Trait>, path: &str, module: &str) -> Option<String> {
+    [qualify(module, path), path.to_string()]
+        .into_iter()
+        .find(|path| traits.contains_key(path))
+}
+
+/// Joins a module path and a name
+pub fn qualify(module: &str, name: &str) -> String {
+    if module.is_empty() {
+        name.to_string()
+    } else {
+        format!("{module}::{name}")
+    }
+}
 
 pub struct MakeSignaturesPass<'input> {
-    pub functions: HashMap<
-        &'input str,
-        (
-            Linkage,
-            Vec<(&'input str, Type)>,
-            Type,
-            Vec<ASTNode<NodeValue>>,
-        ),
-    >,
+    pub signatures: Signatures<'input>,
     scope: Stack<ScopeType>,
+    /// Every module declared with `mod`, and every module imported with `import`
+    modules: HashSet<String>,
+    imports: Vec<(String, Span)>,
+    /// Impls, with the module each is in, added once every trait is known
+    impls: Vec<(String, &'input str, Type, Impl<'input>)>,
 }
 
 impl<'input> CodePass<'input> for MakeSignaturesPass<'input> {
-    type Prev = ParsePass;
+    const NAME: &'static str = "make_signatures";
+    type Prev = LowerPass<'input>;
     type Arg = ();
 
     fn pass(
-        mut previous: Self::Prev,
-        input: &'input str,
+        previous: Self::Prev,
+        _: &'input str,
         _: &mut impl Is<Self::Arg>,
     ) -> miette::Result<Self> {
         let mut me = Self {
-            functions: HashMap::new(),
+            signatures: Signatures::default(),
             scope: Stack::new(),
+            modules: HashSet::new(),
+            imports: vec![],
+            impls: vec![],
         };
-        me.run(&mut previous.nodes, input)?;
+        me.run(&previous.items)?;
+
+        // The module loader puts the contents of imported files in `mod` blocks
+        for (import, span) in &me.imports {
+            ensure!(
+                me.modules.contains(import),
+                Error::ModuleDoesntExist(import.clone()).labelled((*span).into())
+            );
+        }
+
+        // Traits are resolved like functions, relative to the module they're
+        // used in. One that doesn't exist is reported by the type checker.
+        let traits = &me.signatures.traits;
+        let resolve = |path: &str, module: &str| {
+            resolve_trait(traits, path, module).unwrap_or_else(|| path.to_string())
+        };
+        let mut impls = HashMap::new();
+        for (module, trait_, type_, impl_) in std::mem::take(&mut me.impls) {
+            let trait_ = resolve(trait_, &module);
+            ensure!(
+                !impls.contains_key(&(trait_.clone(), type_)),
+                Error::ConflictingImpl(trait_, format!("{:?}", type_)).labelled(impl_.span.into())
+            );
+            impls.insert((trait_, type_), impl_);
+        }
+        let functions = me.signatures.functions.values_mut().chain(
+            impls
+                .values_mut()
+                .flat_map(|impl_| impl_.methods.values_mut()),
+        );
+        for function in functions {
+            for bounds in &mut function.bounds {
+                for bound in bounds {
+                    *bound = resolve(bound, &function.module);
+                }
+            }
+        }
+        me.signatures.impls = impls;
+
         Ok(me)
     }
 }
 impl<'input> MakeSignaturesPass<'input> {
-    fn run(
-        &mut self,
-        nodes: &mut Vec<ASTNode<NodeValue>>,
-        input: &'input str,
-    ) -> miette::Result<()> {
-        for node in nodes {
-            match node.node {
-                NodeValue::NULL => todo!(),
-                NodeValue::Id => todo!(),
-                NodeValue::Number => todo!(),
-                NodeValue::Add => todo!(),
-                NodeValue::Sub => todo!(),
-                NodeValue::Mul => todo!(),
-                NodeValue::Div => todo!(),
-                NodeValue::GT => todo!(),
-                NodeValue::GTE => todo!(),
-                NodeValue::EQ => todo!(),
-                NodeValue::LT => todo!(),
-                NodeValue::LTE => todo!(),
-                NodeValue::Product => todo!(),
-                NodeValue::Sum => todo!(),
-                NodeValue::Expr => todo!(),
-                NodeValue::Root => todo!(),
-                NodeValue::VarAssign => todo!(),
-                NodeValue::FnDef => {
-                    let id = &node.children[0];
-                    let id = &input[id.start..id.end];
-
-                    let linkage =
-                        if self.scope.iter().any(|e| e == &ScopeType::Extern) || id == "main" {
-                            Linkage::Public
-                        } else {
-                            Linkage::Private
-                        };
-
-                    let arg_nodes = &node.children[1];
-                    let mut arg_nodes_iter = arg_nodes.children.iter();
-                    let mut args = vec![];
-                    while let Some(arg_name) = arg_nodes_iter.next() {
-                        let type_node = arg_nodes_iter.next().unwrap();
-                        let arg_name = &input[arg_name.start..arg_name.end];
-                        let type_ = &input[type_node.start..type_node.end];
-                        let type_ = Type::from_str(type_)
-                            .map_err(|e| e.labelled((type_node.start..type_node.end).into()))?;
-                        args.push((arg_name, type_));
+    fn run(&mut self, items: &[Item<'input>]) -> miette::Result<()> {
+        for item in items {
+            let mut scopes = 0;
+            for attribute in &item.attributes {
+                let level = match attribute.name.name {
+                    "no_mangle" => {
+                        self.scope.push(ScopeType::NoMangle);
+                        scopes += 1;
+                        continue;
                     }
+                    "allow" => Level::Allow,
+                    "warn" => Level::Warn,
+                    "deny" => Level::Deny,
+                    name => bail!(Error::UnknownAttribute(name.to_string())
+                        .labelled(attribute.name.span.into())),
+                };
+                for arg in &attribute.args {
+                    let lint = Lint::from_name(arg.name).ok_or_else(|| {
+                        Error::UnknownLint(arg.name.to_string()).labelled(arg.span.into())
+                    })?;
+                    self.scope.push(ScopeType::Lint(lint, level));
+                    scopes += 1;
+                }
+            }
 
-                    let ret_type_node = &node.children[2];
-                    let ret_type = &input[ret_type_node.start..ret_type_node.end];
-                    let ret_type = Type::from_str(ret_type)
-                        .map_err(|e| e.labelled((ret_type_node.start..ret_type_node.end).into()))?;
+            self.read_item(item)?;
 
-                    let nodes = node.children[3..node.children.len()].to_vec();
+            for _ in 0..scopes {
+                self.scope.pop();
+            }
+        }
+
+        Ok(())
+    }
 
-                    self.functions.insert(id, (linkage, args, ret_type, nodes));
+    fn read_item(&mut self, item: &Item<'input>) -> miette::Result<()> {
+        match &item.kind {
+            ItemKind::FnDef(function) => {
+                let (id, function) = self.read_fn_def(function)?;
+                self.insert_function(qualify(&function.module, id), function)?;
+            }
+            ItemKind::Extern { abi, items } => {
+                if let Some(abi) = abi {
+                    ensure!(
+                        abi.name == "C",
+                        Error::UnsupportedAbi(abi.name.to_string()).labelled(abi.span.into())
+                    );
                 }
-                NodeValue::FnCall => todo!(),
-                NodeValue::FnDefArgSet => todo!(),
-                NodeValue::FnCallArgSet => todo!(),
-                NodeValue::Extern => {
-                    self.scope.push(ScopeType::Extern);
-                    self.run(&mut node.children, input)?;
-                    self.scope.pop();
+
+                self.scope.push(ScopeType::Extern);
+                self.run(items)?;
+                self.scope.pop();
+            }
+            ItemKind::FnDecl(sig) => {
+                let linkage = if self.scope.iter().any(|e| e == &ScopeType::Extern) {
+                    Linkage::External
+                } else {
+                    Linkage::Private
+                };
+
+                let module = self.module();
+                let (id, function) = read_fn_decl(sig, linkage, &[], None, module)?;
+                self.insert_function(qualify(&function.module, id), function)?;
+            }
+            ItemKind::Trait { name, methods } => {
+                let path = qualify(&self.module(), name.name);
+                ensure!(
+                    !self.signatures.traits.contains_key(&path),
+                    Error::DuplicateDefinition(path).labelled(name.span.into())
+                );
+
+                let mut trait_methods = HashMap::new();
+                for method in methods {
+                    let (method_name, mut method) =
+                        read_fn_decl(method, Linkage::Private, &["Self"], None, self.module())?;
+                    method.bounds = vec![vec![path.clone()]];
+                    ensure!(
+                        !trait_methods.contains_key(method_name),
+                        Error::DuplicateDefinition(method_name.to_string())
+                            .labelled(method.span.into())
+                    );
+                    trait_methods.insert(method_name, method);
                 }
-                NodeValue::FnDecl => {
-                    let id = &node.children[0];
-                    let id = &input[id.start..id.end];
-
-                    let linkage = if self.scope.iter().any(|e| e == &ScopeType::Extern) {
-                        Linkage::External
-                    } else {
-                        Linkage::Private
-                    };
-
-                    let arg_nodes = &node.children[1];
-                    let mut arg_nodes_iter = arg_nodes.children.iter();
-                    let mut args = vec![];
-                    while let Some(arg_name) = arg_nodes_iter.next() {
-                        let type_node = arg_nodes_iter.next().unwrap();
-                        let arg_name = &input[arg_name.start..arg_name.end];
-                        let type_ = &input[type_node.start..type_node.end];
-                        let type_ = Type::from_str(type_)
-                            .map_err(|e| e.labelled((type_node.start..type_node.end).into()))?;
-                        args.push((arg_name, type_));
-                    }
 
-                    let ret_type_node = &node.children[2];
-                    let ret_type = &input[ret_type_node.start..ret_type_node.end];
-                    let ret_type = Type::from_str(ret_type)
-                        .map_err(|e| e.labelled((ret_type_node.start..ret_type_node.end).into()))?;
+                self.signatures.traits.insert(
+                    path,
+                    Trait {
+                        methods: trait_methods,
+                        span: item.span,
+                    },
+                );
+            }
+            ItemKind::Impl {
+                trait_,
+                self_type,
+                methods,
+            } => {
+                let type_ = Type::resolve(self_type.name, &[])
+                    .map_err(|e| e.labelled(self_type.span.into()))?;
+
+                self.scope.push(ScopeType::Impl(type_));
+                let mut impl_methods = HashMap::new();
+                for method in methods {
+                    let (name, method) = self.read_fn_def(method)?;
+                    ensure!(
+                        !impl_methods.contains_key(name),
+                        Error::DuplicateDefinition(name.to_string()).labelled(method.span.into())
+                    );
+                    impl_methods.insert(name, method);
+                }
+                self.scope.pop();
+
+                let module = self.module();
+                self.impls.push((
+                    module,
+                    trait_.name,
+                    type_,
+                    Impl {
+                        methods: impl_methods,
+                        span: item.span,
+                    },
+                ));
+            }
+            ItemKind::Import(name) => {
+                let import = qualify(&self.module(), name.name);
+                self.imports.push((import, item.span));
+            }
+            ItemKind::Module { name, items } => {
+                let module = qualify(&self.module(), name.name);
+                self.modules.insert(module);
+
+                self.scope.push(ScopeType::Module(name.name.to_string()));
+                self.run(items)?;
+                self.scope.pop();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_fn_def(
+        &mut self,
+        function: &FnDef<'input>,
+    ) -> miette::Result<(&'input str, Function<'input>)> {
+        let sig = &function.sig;
+        let id = sig.name.name;
+
+        let self_type = self.scope.iter().find_map(|e| match e {
+            ScopeType::Impl(type_) => Some(*type_),
+            _ => None,
+        });
+
+        // Before `pub`, definitions in extern blocks were exported
+        ensure!(
+            !self.scope.iter().any(|e| e == &ScopeType::Extern),
+            Error::ExternDefinition(id.to_string()).labelled(sig.span.into())
+        );
 
-                    let nodes = node.children[3..node.children.len()].to_vec();
+        let module = self.module();
+        let linkage =
+            if function.public || (id == "main" && module.is_empty() && self_type.is_none()) {
+                Linkage::Public
+            } else {
+                Linkage::Private
+            };
 
-                    self.functions.insert(id, (linkage, args, ret_type, nodes));
+        let no_mangle = self.scope.iter().any(|e| e == &ScopeType::NoMangle);
+        // Impl methods are only ever called through the trait, which needs the mangled name
+        ensure!(
+            !no_mangle || self_type.is_none(),
+            Error::NotAllowedHere("#[no_mangle]".to_string(), "impl blocks".to_string())
+                .labelled(sig.span.into())
+        );
+
+        let type_params = sig
+            .type_params
+            .iter()
+            .map(|param| param.name.name)
+            .collect::<Vec<_>>();
+        let bounds = sig
+            .type_params
+            .iter()
+            .map(|param| {
+                param
+                    .bounds
+                    .iter()
+                    .map(|bound| bound.name.to_string())
+                    .collect()
+            })
+            .collect();
+        // Exported functions need a single symbol, which a generic function doesn't have
+        if let (Some(first), Some(last)) = (sig.type_params.first(), sig.type_params.last()) {
+            ensure!(
+                linkage == Linkage::Private && !no_mangle,
+                Error::NotAllowedHere(
+                    "Type parameters".to_string(),
+                    "exported functions".to_string()
+                )
+                .labelled(first.name.span.to(last.name.span).into())
+            );
+        }
+
+        let args = parse_args(&sig.params, &type_params, self_type)?;
+        let ret_type = resolve_type(sig.ret.name, &type_params, self_type)
+            .map_err(|e| e.labelled(sig.ret.span.into()))?;
+
+        Ok((
+            id,
+            Function {
+                linkage,
+                type_params,
+                bounds,
+                args,
+                ret_type,
+                body: function.body.clone(),
+                module,
+                no_mangle,
+                lints: self.lints(),
+                span: sig.span,
+                doc: sig.doc.clone(),
+            },
+        ))
+    }
+
+    /// Adds a function, unless another already has its path
+    fn insert_function(&mut self, path: String, function: Function<'input>) -> miette::Result<()> {
+        ensure!(
+            !self.signatures.functions.contains_key(&path),
+            Error::DuplicateDefinition(path).labelled(function.span.into())
+        );
+        self.signatures.functions.insert(path, function);
+        Ok(())
+    }
+
+    /// Lint levels set by attributes, outermost first
+    fn lints(&mut self) -> Vec<(Lint, Level)> {
+        self.scope
+            .iter()
+            .filter_map(|e| match e {
+                ScopeType::Lint(lint, level) => Some((*lint, *level)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Path of the module currently being read
+    fn module(&mut self) -> String {
+        self.scope
+            .iter()
+            .filter_map(|e| match e {
+                ScopeType::Module(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+}
+
+/// Reads a function declaration, one without a body
+fn read_fn_decl<'input>(
+    sig: &FnSig<'input>,
+    linkage: Linkage,
+    type_params: &[&'input str],
+    self_type: Option<Type>,
+    module: String,
+) -> miette::Result<(&'input str, Function<'input>)> {
+    let args = parse_args(&sig.params, type_params, self_type)?;
+    let ret_type = resolve_type(sig.ret.name, type_params, self_type)
+        .map_err(|e| e.labelled(sig.ret.span.into()))?;
+
+    Ok((
+        sig.name.name,
+        Function {
+            linkage,
+            type_params: type_params.to_vec(),
+            bounds: vec![vec![]; type_params.len()],
+            args,
+            ret_type,
+            body: vec![],
+            module,
+            no_mangle: false,
+            lints: vec![],
+            span: sig.span,
+            doc: sig.doc.clone(),
+        },
+    ))
+}
+
+/// Resolves a type name, where `Self` is the type of the enclosing impl
+fn resolve_type(s: &str, type_params: &[&str], self_type: Option<Type>) -> Result<Type, Error> {
+    match (s, self_type) {
+        ("Self", Some(self_type)) => Ok(self_type),
+        _ => Type::resolve(s, type_params),
+    }
+}
+
+/// Resolves the types of the arguments of a function definition or declaration
+fn parse_args<'input>(
+    params: &[Param<'input>],
+    type_params: &[&str],
+    self_type: Option<Type>,
+) -> miette::Result<Vec<(&'input str, Type)>> {
+    params
+        .iter()
+        .map(|param| match param {
+            Param::SelfParam(span) => resolve_type("Self", type_params, self_type)
+                .map(|type_| ("self", type_))
+                .map_err(|e| e.labelled((*span).into()).into()),
+            Param::Named { name, type_ } => resolve_type(type_.name, type_params, self_type)
+                .map(|resolved| (name.name, resolved))
+                .map_err(|e| e.labelled(type_.span.into()).into()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::*;
+
+    use crate::{
+        codepass::PassRunner, linkage::Linkage, lower::LowerPass, parsepass::ParsePass, type_::Type,
+    };
+
+    use super::MakeSignaturesPass;
+
+    #[test]
+    fn pub_fn_is_public() -> Result<()> {
+        let input = r#"
+        extern "C" fn putchar(c: int) -> int;
+
+        pub fn shout -> int {
+            putchar(65)
+        }
+
+        fn helper -> int {
+            0
+        }
+        "#;
+        let pass = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .into_inner();
+        let functions = &pass.signatures.functions;
+
+        assert!(functions["putchar"].linkage == Linkage::External);
+        assert!(functions["shout"].linkage == Linkage::Public);
+        assert!(functions["helper"].linkage == Linkage::Private);
+
+        Ok(())
+    }
+
+    #[test]
+    fn definition_in_extern_block() -> Result<()> {
+        let input = "
+        extern {
+            fn shout -> int {
+                65
+            }
+        }
+        ";
+        let result = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>();
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_abi() -> Result<()> {
+        let input = r#"
+        extern "stdcall" fn putchar(c: int) -> int;
+        "#;
+        let result = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>();
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_function() -> Result<()> {
+        let input = "
+        fn double(x: int) -> int {
+            x * 2
+        }
+
+        fn double(x: int) -> int {
+            x + x
+        }
+        ";
+        let error = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error.root_cause().to_string(),
+            "double is defined more than once"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn traits_keyed_by_path() -> Result<()> {
+        let input = "
+        trait Double {
+            fn double(self) -> Self;
+        }
+
+        mod math {
+            trait Double {
+                fn double(self) -> Self;
+            }
+
+            impl Double for int {
+                fn double(self) -> int {
+                    self * 2
                 }
-                NodeValue::BoolExpr => todo!(),
-                NodeValue::True => todo!(),
-                NodeValue::False => todo!(),
-                NodeValue::If => todo!(),
+            }
+
+            fn quadruple<T: Double>(x: T) -> T {
+                x
             }
         }
 
+        impl Double for int {
+            fn double(self) -> int {
+                self + self
+            }
+        }
+        ";
+        let pass = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .into_inner();
+        let signatures = &pass.signatures;
+
+        assert!(signatures.traits.contains_key("Double"));
+        assert!(signatures.traits.contains_key("math::Double"));
+        assert!(signatures
+            .impls
+            .contains_key(&("math::Double".to_string(), Type::Int)));
+        assert!(signatures
+            .impls
+            .contains_key(&("Double".to_string(), Type::Int)));
+        assert_eq!(
+            signatures.functions["math::quadruple"].bounds,
+            [["math::Double"]]
+        );
+
         Ok(())
     }
 }
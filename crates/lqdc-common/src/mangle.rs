@@ -0,0 +1,203 @@
+//! Symbol names for functions that aren't exported
+//!
+//! Exported and external functions keep their source name. Everything else is
+//! mangled, so functions with the same name in different modules, instances of
+//! generic functions and methods of different impls all get distinct symbols,
+//! none of which can clash with a C symbol. The scheme is
+//!
+//! ```text
+//! symbol   = "_L" item { closure }
+//! item     = "N" segment { segment } "E" [ generics ]   math::max<int>
+//!          | "X" segment { segment } segment segment "E"
+//!                                                        <int as ops::Add>::add
+//! generics = "G" segment { segment } "E"
+//! closure  = "C" number "_"                             {closure#0}
+//! segment  = number identifier                          4math
+//! ```
+//!
+//! so `math::max<int>` is `_LN4math3maxEG3intE`, and the first closure in
+//! `main` is `_LN4mainEC0_`.
+
+use crate::type_::Type;
+
+/// The symbol of the function at `path`, instantiated with `type_args`
+pub fn mangle(path: &str, type_args: &[Type]) -> String {
+    let mut symbol = format!("_LN{}E", segments(path.split("::")));
+    if !type_args.is_empty() {
+        let type_args = type_args.iter().map(|type_| type_.mangled_name());
+        symbol.push_str(&format!("G{}E", segments(type_args)));
+    }
+    symbol
+}
+
+/// The symbol of a method in an impl of the trait at `trait_`
+pub fn mangle_impl(trait_: &str, self_type: Type, method: &str) -> String {
+    format!(
+        "_LX{}E",
+        segments(trait_.split("::").chain([self_type.mangled_name(), method]))
+    )
+}
+
+/// The symbol of the nth closure in the function with the symbol `parent`
+pub fn mangle_closure(parent: &str, n: usize) -> String {
+    format!("{parent}C{n}_")
+}
+
+fn segments<'a>(segments: impl IntoIterator<Item = &'a str>) -> String {
+    segments
+        .into_iter()
+        .map(|segment| format!("{}{segment}", segment.len()))
+        .collect()
+}
+
+/// Turns a mangled symbol back into the path it was made from, or `None` if
+/// it isn't one
+pub fn demangle(symbol: &str) -> Option<String> {
+    let mut demangler = Demangler {
+        rest: symbol.strip_prefix("_L")?,
+    };
+
+    let mut demangled = if demangler.eat('N') {
+        let mut path = demangler.segments()?.join("::");
+        if demangler.eat('G') {
+            path.push_str(&format!("<{}>", demangler.segments()?.join(", ")));
+        }
+        path
+    } else if demangler.eat('X') {
+        match demangler.segments()?.as_slice() {
+            [trait_ @ .., self_type, method] if !trait_.is_empty() => {
+                format!("<{self_type} as {}>::{method}", trait_.join("::"))
+            }
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    while demangler.eat('C') {
+        let n = demangler.number()?;
+        if !demangler.eat('_') {
+            return None;
+        }
+        demangled.push_str(&format!("::{{closure#{n}}}"));
+    }
+
+    demangler.rest.is_empty().then_some(demangled)
+}
+
+/// Demangles every symbol in `text`, leaving everything else as it is
+pub fn demangle_all(text: &str) -> String {
+    let is_symbol_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("_L") {
+        let end = rest[start..]
+            .find(|c| !is_symbol_char(c))
+            .map_or(rest.len(), |len| start + len);
+        let word = &rest[start..end];
+        // Only whole words, not the end of some other identifier
+        let whole_word = !rest[..start].ends_with(is_symbol_char);
+
+        result.push_str(&rest[..start]);
+        match demangle(word).filter(|_| whole_word) {
+            Some(demangled) => result.push_str(&demangled),
+            None => result.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+
+    result
+}
+
+struct Demangler<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Demangler<'a> {
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let len = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let number = self.rest[..len].parse().ok()?;
+        self.rest = &self.rest[len..];
+        Some(number)
+    }
+
+    /// Reads segments up to and including the closing `E`
+    fn segments(&mut self) -> Option<Vec<&'a str>> {
+        let mut segments = vec![];
+        while !self.eat('E') {
+            let len = self.number()?;
+            segments.push(self.rest.get(..len)?);
+            self.rest = &self.rest[len..];
+        }
+        (!segments.is_empty()).then_some(segments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::type_::Type;
+
+    use super::{demangle, demangle_all, mangle, mangle_closure, mangle_impl};
+
+    #[test]
+    fn module_path() {
+        let symbol = mangle("math::helper", &[]);
+        assert_eq!(symbol, "_LN4math6helperE");
+        assert_eq!(demangle(&symbol).unwrap(), "math::helper");
+    }
+
+    #[test]
+    fn generic_instance() {
+        let symbol = mangle("max", &[Type::Int, Type::Bool]);
+        assert_eq!(symbol, "_LN3maxEG3int4boolE");
+        assert_eq!(demangle(&symbol).unwrap(), "max<int, bool>");
+    }
+
+    #[test]
+    fn impl_method_closure() {
+        let symbol = mangle_closure(&mangle_impl("Add", Type::Uint, "add"), 1);
+        assert_eq!(symbol, "_LX3Add4uint3addEC1_");
+        assert_eq!(
+            demangle(&symbol).unwrap(),
+            "<uint as Add>::add::{closure#1}"
+        );
+    }
+
+    #[test]
+    fn impl_of_module_trait() {
+        let symbol = mangle_impl("ops::Add", Type::Int, "add");
+        assert_eq!(symbol, "_LX3ops3Add3int3addE");
+        assert_eq!(demangle(&symbol).unwrap(), "<int as ops::Add>::add");
+    }
+
+    #[test]
+    fn not_mangled() {
+        assert_eq!(demangle("main"), None);
+        assert_eq!(demangle("_LN4mathE3"), None);
+        assert_eq!(demangle("_LN9mathE"), None);
+    }
+
+    #[test]
+    fn backtrace() {
+        let text = "at _LN4math6helperE+0x14\nat main+0x8\nat foo_LN4mainE";
+        assert_eq!(
+            demangle_all(text),
+            "at math::helper+0x14\nat main+0x8\nat foo_LN4mainE"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use frontend::{node::NodeValue, parse};
+use miette::*;
+
+use crate::{
+    source_map::{SourceFile, SourceMap},
+    Error, IntoLabelled,
+};
+
+/// Reads `root` and every file it imports into a single source.
+///
+/// Imports are resolved relative to the importing file, so `import math;` in
+/// `src/main.lqd` reads `src/math.lqd`, and `import vec;` in there reads
+/// `src/math/vec.lqd`. The contents of each imported file are appended in a
+/// `mod` block of the same name, which keeps every span valid in the combined
+/// source. The source map records where each file ended up in it.
+pub fn load(root: &Path) -> Result<SourceMap> {
+    load_source(root, read(root)?)
+}
+
+/// Like `load`, but with the contents of `root` given rather than read, such
+/// as an editor buffer that isn't saved
+pub fn load_source(root: &Path, source: String) -> Result<SourceMap> {
+    let dir = root.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = vec![];
+    let source = load_file(root, source, dir, &mut vec![], &mut files)?;
+    Ok(SourceMap::with_files(source, files))
+}
+
+fn read(path: &Path) -> Result<String> {
+    read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))
+}
+
+/// Adds the imports of a file to its source. The files read are added to
+/// `files`, with starts relative to the returned source.
+fn load_file(
+    path: &Path,
+    source: String,
+    dir: &Path,
+    loading: &mut Vec<PathBuf>,
+    files: &mut Vec<SourceFile>,
+) -> Result<String> {
+    let named_source = || NamedSource::new(path.display().to_string(), source.clone());
+
+    let nodes = parse(&source).map_err(|e| {
+        Report::new(Error::ParseError(e.message).labelled(e.span.into()))
+            .with_source_code(named_source())
+    })?;
+
+    loading.push(path.to_path_buf());
+    files.push(SourceFile::new(path.display().to_string(), 0, &source));
+    let mut combined = source.clone();
+    for node in nodes.iter().filter(|node| node.node == NodeValue::Import) {
+        let name = &node.children[0];
+        let name = &source[name.start..name.end];
+        let file = dir.join(format!("{name}.lqd"));
+
+        if loading.contains(&file) {
+            return Err(Report::new(
+                Error::CircularImport(name.to_string()).labelled((node.start..node.end).into()),
+            )
+            .with_source_code(named_source()));
+        }
+        if !file.exists() {
+            return Err(Report::new(
+                Error::ModuleDoesntExist(name.to_string()).labelled((node.start..node.end).into()),
+            )
+            .with_source_code(named_source()));
+        }
+
+        let imported = files.len();
+        let contents = load_file(&file, read(&file)?, &dir.join(name), loading, files)?;
+        combined.push_str(&format!("\nmod {name} {{\n"));
+        for file in &mut files[imported..] {
+            file.start += combined.len();
+        }
+        combined.push_str(&format!("{contents}\n}}\n"));
+    }
+    loading.pop();
+
+    Ok(combined)
+}
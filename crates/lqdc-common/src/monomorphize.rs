@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use crate::{
+    closure_convert::{ClosureConvertPass, LiftedClosure},
+    codepass::{CodePass, Is},
+    hir::NodeId,
+    linkage::Linkage,
+    make_signatures::Function,
+    mangle::{mangle, mangle_impl},
+    type_::Type,
+    type_check::Types,
+    Error,
+};
+
+pub struct MonomorphizePass<'input> {
+    /// Every function to emit, keyed by symbol name. Generic functions are
+    /// replaced by one instance per set of type arguments they are called with.
+    pub functions: HashMap<String, Function<'input>>,
+    pub closures: HashMap<NodeId, LiftedClosure<'input>>,
+    /// The instance each call resolves to, keyed by the symbol name of the
+    /// calling function and the node id of the call
+    pub call_targets: HashMap<(String, NodeId), String>,
+    /// The type of every expression, keyed by the symbol name of the instance
+    /// it is in
+    pub types: Types,
+}
+
+impl<'input> CodePass<'input> for MonomorphizePass<'input> {
+    const NAME: &'static str = "monomorphize";
+    type Prev = ClosureConvertPass<'input>;
+    type Arg = ();
+
+    fn pass(
+        mut prev: Self::Prev,
+        _: &'input str,
+        _: &mut impl Is<Self::Arg>,
+    ) -> miette::Result<Self> {
+        let signatures = &prev.signatures;
+
+        // Calls and types are recorded per node, so group them by the function
+        // they're in once, rather than scanning all of them for every instance
+        let mut calls = HashMap::<_, Vec<_>>::new();
+        for (id, call) in std::mem::take(&mut prev.calls) {
+            calls
+                .entry(call.caller.clone())
+                .or_default()
+                .push((id, call));
+        }
+        let mut function_types = HashMap::<_, Vec<_>>::new();
+        for ((function, id), type_) in &prev.types {
+            function_types
+                .entry(function.as_str())
+                .or_default()
+                .push((*id, type_));
+        }
+
+        let mut functions = HashMap::new();
+        let mut call_targets = HashMap::new();
+        let mut types = HashMap::new();
+        // Everything non-generic is emitted, generic functions only as far as
+        // they are reachable from there. Each entry is the symbol calls are
+        // recorded against, the symbol of the instance, the function and its
+        // type arguments.
+        let mut queue = vec![];
+        for (name, function) in &signatures.functions {
+            if !function.is_generic() {
+                queue.push((name.clone(), symbol(name, function, &[]), function, vec![]));
+            }
+        }
+        for ((trait_, self_type), impl_) in &signatures.impls {
+            for (name, method) in &impl_.methods {
+                let symbol = mangle_impl(trait_, *self_type, name);
+                queue.push((symbol.clone(), symbol, method, vec![]));
+            }
+        }
+
+        // Closures are never in generic functions, so each caller has one symbol
+        let mut caller_symbols = HashMap::new();
+        while let Some((caller, symbol, function, type_args)) = queue.pop() {
+            if functions.contains_key(&symbol) {
+                continue;
+            }
+            caller_symbols.insert(caller.clone(), symbol.clone());
+
+            for (id, type_) in function_types.get(caller.as_str()).into_iter().flatten() {
+                types.insert((symbol.clone(), *id), type_.substitute(&type_args));
+            }
+
+            for (span, call) in calls.get(&caller).into_iter().flatten() {
+                let callee_type_args = call
+                    .type_args
+                    .iter()
+                    .map(|type_| type_.substitute(&type_args))
+                    .collect::<Vec<Type>>();
+
+                let target = match signatures.functions.get(&call.callee) {
+                    Some(callee) => {
+                        let target = self::symbol(&call.callee, callee, &callee_type_args);
+                        queue.push((
+                            call.callee.clone(),
+                            target.clone(),
+                            callee,
+                            callee_type_args,
+                        ));
+                        target
+                    }
+                    // A trait method, which resolves to the impl for `Self`.
+                    // Impl methods aren't generic and are already queued.
+                    None => {
+                        let trait_ = call.trait_.as_ref().ok_or_else(|| {
+                            Error::InternalCompilerError(format!(
+                                "Call to {} was never resolved",
+                                call.callee
+                            ))
+                        })?;
+                        let self_type = callee_type_args.first().ok_or_else(|| {
+                            Error::InternalCompilerError(format!(
+                                "Call to trait method {} has no type for Self",
+                                call.callee
+                            ))
+                        })?;
+                        mangle_impl(trait_, *self_type, &call.callee)
+                    }
+                };
+                call_targets.insert((symbol.clone(), *span), target);
+            }
+
+            functions.insert(
+                symbol,
+                Function {
+                    linkage: function.linkage,
+                    type_params: vec![],
+                    bounds: vec![],
+                    args: function
+                        .args
+                        .iter()
+                        .map(|(arg, type_)| (*arg, type_.substitute(&type_args)))
+                        .collect(),
+                    ret_type: function.ret_type.substitute(&type_args),
+                    body: function.body.clone(),
+                    module: function.module.clone(),
+                    no_mangle: function.no_mangle,
+                    lints: function.lints.clone(),
+                    span: function.span,
+                    doc: function.doc.clone(),
+                },
+            );
+        }
+
+        for closure in prev.closures.values_mut() {
+            if let Some(symbol) = caller_symbols.get(&closure.function) {
+                closure.function = symbol.clone();
+            }
+        }
+
+        Ok(Self {
+            functions,
+            closures: prev.closures,
+            call_targets,
+            types,
+        })
+    }
+}
+
+/// The symbol name of an instance of the function at `path`. External
+/// functions, `#[no_mangle]` functions and those exported from the root module
+/// keep their own name, everything else is mangled.
+fn symbol(path: &str, function: &Function, type_args: &[Type]) -> String {
+    let exported = function.linkage == Linkage::Public && function.module.is_empty();
+    if function.linkage == Linkage::External || function.no_mangle || exported {
+        path.rsplit("::").next().unwrap_or(path).to_string()
+    } else {
+        mangle(path, type_args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::*;
+
+    use crate::{
+        closure_convert::ClosureConvertPass, codepass::PassRunner, lower::LowerPass,
+        make_signatures::MakeSignaturesPass, parsepass::ParsePass, type_check::TypeCheckPass,
+    };
+
+    use super::MonomorphizePass;
+
+    #[test]
+    fn one_instance_per_type() -> Result<()> {
+        let input = "
+        fn main -> bool {
+            first(1, 2);
+            first(3, 4);
+            first(true, false)
+        }
+
+        fn first<T>(a: T, b: T) -> T {
+            a
+        }
+        ";
+        let pass = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?
+            .run::<ClosureConvertPass>()?
+            .run::<MonomorphizePass>()?
+            .into_inner();
+        let mut symbols = pass.functions.keys().cloned().collect::<Vec<_>>();
+        symbols.sort();
+
+        assert_eq!(symbols, ["_LN5firstEG3intE", "_LN5firstEG4boolE", "main"]);
+
+        Ok(())
+    }
+}
@@ -1,29 +1,83 @@
 use crate::{
     codepass::{CodePass, Is},
-    Error, IntoLabelled,
+    Error, Errors, IntoLabelled,
 };
-use frontend::{node::NodeValue, parser};
-use lang_pt::ASTNode;
+use frontend::{parse_all, Node};
+use miette::bail;
 
 pub struct ParsePass {
-    pub(crate) nodes: Vec<ASTNode<NodeValue>>,
+    pub(crate) nodes: Vec<Node>,
 }
 impl<'input> CodePass<'input> for ParsePass {
+    const NAME: &'static str = "parse";
     type Prev = ();
 
     type Arg = ();
 
     fn pass(_: Self::Prev, input: &'input str, _: &mut impl Is<Self::Arg>) -> miette::Result<Self> {
-        Ok(Self {
-            nodes: parser()
-                .parse(input.as_bytes())
-                .map_err(|e| Error::ParseError(e.message).labelled(e.pointer.into()))?,
-        })
+        let errors = match parse_all(input) {
+            Ok(nodes) => return Ok(Self { nodes }),
+            Err(errors) => errors,
+        };
+
+        Errors::check(
+            errors
+                .into_iter()
+                .map(|e| Error::ParseError(e.message).labelled(e.span.into()).into())
+                .collect(),
+        )?;
+        bail!(Error::InternalCompilerError(
+            "Parsing failed without an error".to_string()
+        ))
     }
 }
 
-impl Into<Vec<ASTNode<NodeValue>>> for ParsePass {
-    fn into(self) -> Vec<ASTNode<NodeValue>> {
-        self.nodes
+impl From<ParsePass> for Vec<Node> {
+    fn from(pass: ParsePass) -> Self {
+        pass.nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codepass::PassRunner;
+
+    use super::ParsePass;
+
+    #[test]
+    fn reports_every_bad_statement() {
+        let input = "
+        fn main -> int {
+            let a = ;
+            let b = 2;
+            let c = * 3;
+            b
+        }
+        ";
+        let error = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()
+            .err()
+            .unwrap();
+
+        assert_eq!(error.related().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let parens = format!(
+            "fn main -> int {{ {}1{} }}",
+            "(".repeat(10_000),
+            ")".repeat(10_000)
+        );
+        let chain = format!("fn main -> int {{ 1{} }}", "+1".repeat(100_000));
+        for input in [parens, chain] {
+            let error = PassRunner::<(), ()>::new(&input)
+                .run::<ParsePass>()
+                .err()
+                .unwrap();
+
+            let code = error.diagnostic_source().and_then(|error| error.code());
+            assert_eq!(code.unwrap().to_string(), "L0003");
+        }
     }
 }
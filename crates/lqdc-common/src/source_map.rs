@@ -0,0 +1,209 @@
+//! Which file each part of the combined source came from
+//!
+//! The module loader puts every imported file into a single source, so every
+//! span is a byte range in that source. The source map turns those back into
+//! a file and a line and column in it.
+
+use std::fmt;
+
+use miette::{MietteError, MietteSpanContents, SourceCode, SourceSpan, SpanContents};
+
+/// Index of a file in a `SourceMap`, the root file is always 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub usize);
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// The path, as given on the command line or resolved from an import
+    pub name: String,
+    /// Where the contents of the file start in the combined source
+    pub start: usize,
+    pub len: usize,
+    /// Offset of the start of each line, relative to `start`
+    lines: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: String, start: usize, contents: &str) -> Self {
+        let lines = std::iter::once(0)
+            .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            name,
+            start,
+            len: contents.len(),
+            lines,
+        }
+    }
+
+    /// 0-based line and column of an offset relative to the start of the file
+    fn line_column(&self, offset: usize) -> (usize, usize) {
+        let line = self.lines.partition_point(|&start| start <= offset) - 1;
+        (line, offset - self.lines[line])
+    }
+}
+
+/// A position in a file, displayed as `src/math.lqd:12:5`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub file: FileId,
+    pub name: &'a str,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in bytes
+    pub column: usize,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.name, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// Every file, as given to the parser
+    pub source: String,
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// A map of a source that is a single file
+    pub fn new(name: impl Into<String>, source: impl Into<String>) -> Self {
+        let source = source.into();
+        Self {
+            files: vec![SourceFile::new(name.into(), 0, &source)],
+            source,
+        }
+    }
+
+    /// A map of a combined source, with `files` in the order they were read
+    pub fn with_files(source: String, files: Vec<SourceFile>) -> Self {
+        Self { source, files }
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| (FileId(i), file))
+    }
+
+    /// The file an offset is in, and the offset relative to the start of it.
+    /// The `mod` blocks the loader wraps imported files in aren't part of any
+    /// file, so they count as the end of the file before them.
+    pub fn lookup(&self, offset: usize) -> Option<(FileId, usize)> {
+        let (id, file) = self
+            .files()
+            .filter(|(_, file)| file.start <= offset)
+            .max_by_key(|(_, file)| file.start)?;
+        Some((id, (offset - file.start).min(file.len)))
+    }
+
+    pub fn location(&self, offset: usize) -> Option<Location<'_>> {
+        let (id, offset) = self.lookup(offset)?;
+        let file = self.file(id);
+        let (line, column) = file.line_column(offset);
+        Some(Location {
+            file: id,
+            name: &file.name,
+            line: line + 1,
+            column: column + 1,
+        })
+    }
+}
+
+impl SourceCode for SourceMap {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let (id, start) = self.lookup(span.offset()).ok_or(MietteError::OutOfBounds)?;
+        let (end_id, _) = self
+            .lookup(span.offset() + span.len())
+            .ok_or(MietteError::OutOfBounds)?;
+        // Snippets can't show two files at once
+        if id != end_id {
+            return Err(MietteError::OutOfBounds);
+        }
+
+        let file = self.file(id);
+        let text = &self.source[file.start..file.start + file.len];
+        let len = span.len().min(file.len - start);
+        let contents = text.read_span(
+            &(start, len).into(),
+            context_lines_before,
+            context_lines_after,
+        )?;
+
+        // Spans stay in the combined source, lines are counted in the file
+        Ok(Box::new(MietteSpanContents::new_named(
+            file.name.clone(),
+            contents.data(),
+            (file.start + contents.span().offset(), contents.span().len()).into(),
+            contents.line(),
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileId, SourceFile, SourceMap};
+
+    fn map() -> SourceMap {
+        let root = "import math;\nfn main -> int {\n    math::one()\n}\n";
+        let math = "pub fn one -> int {\n    1\n}";
+        let source = format!("{root}\nmod math {{\n{math}\n}}\n");
+        let math_start = source.find("pub fn").unwrap();
+        SourceMap::with_files(
+            source,
+            vec![
+                SourceFile::new("src/main.lqd".to_string(), 0, root),
+                SourceFile::new("src/math.lqd".to_string(), math_start, math),
+            ],
+        )
+    }
+
+    #[test]
+    fn location_in_each_file() {
+        let map = map();
+
+        let call = map.source.find("math::one").unwrap();
+        assert_eq!(map.location(call).unwrap().to_string(), "src/main.lqd:3:5");
+
+        let one = map.source.find("1\n}\n").unwrap();
+        let location = map.location(one).unwrap();
+        assert_eq!(location.file, FileId(1));
+        assert_eq!(location.to_string(), "src/math.lqd:2:5");
+    }
+
+    #[test]
+    fn wrapper_counts_as_end_of_file() {
+        let map = map();
+
+        let (file, offset) = map.lookup(map.source.len() - 1).unwrap();
+        assert_eq!(file, FileId(1));
+        assert_eq!(offset, map.file(FileId(1)).len);
+    }
+
+    #[test]
+    fn snippet_is_named_and_numbered_by_file() {
+        use miette::SourceCode;
+
+        let map = map();
+        let one = map.source.find("1\n}\n").unwrap();
+        let contents = map.read_span(&(one, 1).into(), 0, 0).unwrap();
+
+        assert_eq!(contents.name(), Some("src/math.lqd"));
+        assert_eq!(contents.line(), 1);
+        assert_eq!(contents.span().offset(), one);
+    }
+}
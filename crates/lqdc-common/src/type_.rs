@@ -4,7 +4,7 @@ use miette::*;
 
 use crate::Error;
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum Type {
     // Specifics
     Int,
@@ -14,12 +14,30 @@ pub enum Type {
 
     // Inferables
     Number,
+
+    /// The nth type parameter of the enclosing function
+    Generic(usize),
+
+    /// The type of an unannotated closure parameter, while it's inferred. None
+    /// are left once type checking is done.
+    Infer(usize),
+
+    /// Something that failed to type check. It coerces to and from anything,
+    /// so the error isn't reported again wherever it is used.
+    Error,
 }
 
 impl Type {
     pub fn coerce(self, to: Type) -> Result<Type> {
         match self {
-            Type::Int | Type::Bool | Type::Void | Type::Uint => {
+            _ if to == Type::Error => Ok(self),
+            Type::Error => Ok(to),
+            Type::Int
+            | Type::Bool
+            | Type::Void
+            | Type::Uint
+            | Type::Generic(_)
+            | Type::Infer(_) => {
                 if self == to {
                     Ok(to)
                 } else {
@@ -27,11 +45,40 @@ impl Type {
                 }
             }
             Type::Number => match to {
-                Type::Int | Type::Uint | Type::Number => Ok(to),
-                Type::Bool | Type::Void => Err(miette!("Cannot coerce {:?} to {:?}", self, to)),
+                Type::Int | Type::Uint | Type::Number | Type::Error => Ok(to),
+                Type::Bool | Type::Void | Type::Generic(_) | Type::Infer(_) => {
+                    Err(miette!("Cannot coerce {:?} to {:?}", self, to))
+                }
             },
         }
     }
+
+    /// Replaces type parameters with the matching type argument
+    pub fn substitute(self, type_args: &[Type]) -> Type {
+        match self {
+            Type::Generic(n) => type_args[n],
+            _ => self,
+        }
+    }
+
+    /// Resolves a type name, which may be one of the enclosing function's type parameters
+    pub fn resolve(s: &str, type_params: &[&str]) -> Result<Self, Error> {
+        match type_params.iter().position(|param| *param == s) {
+            Some(n) => Ok(Type::Generic(n)),
+            None => Type::from_str(s),
+        }
+    }
+
+    /// The name used for this type in mangled symbols
+    pub fn mangled_name(self) -> &'static str {
+        match self {
+            Type::Int => "int",
+            Type::Bool => "bool",
+            Type::Void => "void",
+            Type::Uint => "uint",
+            Type::Number | Type::Generic(_) | Type::Infer(_) | Type::Error => unreachable!(),
+        }
+    }
 }
 
 impl FromStr for Type {
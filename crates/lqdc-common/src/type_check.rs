@@ -1,224 +1,896 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 
-use lang_pt::ASTNode;
 use miette::*;
 
 use crate::{
     codepass::{CodePass, Is},
+    hir::{BinaryOp, ClosureParam, Expr, ExprKind, Ident, NodeId, Span},
     linkage::Linkage,
-    make_signatures::MakeSignaturesPass,
+    make_signatures::{Function, Impl, MakeSignaturesPass, Signatures},
+    mangle::mangle_impl,
     type_::Type,
-    Error, IntoLabelled,
+    Error, Errors, IntoLabelled,
 };
-use frontend::node::NodeValue;
 
-pub struct TypeCheck<'input, 'a> {
-    input: &'input str,
-    // builder: &'input ModuleBuilder,
-    prev: &'a MakeSignaturesPass<'input>,
-    vars: HashMap<&'input str, Type>,
+/// A closure bound with `let`, as seen by the type checker
+pub(crate) struct Closure<'input> {
+    /// Symbol of the function the closure was defined in
+    pub function: String,
+    /// The closure expression
+    pub id: NodeId,
+    pub span: Span,
+    /// Parameters, with their annotated or inferred type
+    pub params: Vec<(Ident<'input>, Type)>,
+    pub ret_type: Type,
+    pub body: Expr<'input>,
+    /// Variables from the enclosing scope used in the body
+    pub captures: Vec<(&'input str, Type)>,
+}
+
+/// A call to a function, as seen by the type checker
+pub(crate) struct Call {
+    /// Symbol of the function the call is made from
+    pub caller: String,
+    /// Path of the function, or the name of a trait method
+    pub callee: String,
+    /// The trait declaring the callee, if it's a trait method
+    pub trait_: Option<String>,
+    /// Inferred type arguments, which may refer to the caller's own type parameters
+    pub type_args: Vec<Type>,
 }
-impl<'input, 'a> CodePass<'input> for TypeCheck<'input, 'a> {
+
+/// The type of every expression, keyed by the symbol name of the function it
+/// is in and its node id. Types in generic functions may refer to the
+/// function's type parameters.
+pub type Types = HashMap<(String, NodeId), Type>;
+
+pub struct TypeCheckPass<'input> {
+    pub signatures: Signatures<'input>,
+    pub types: Types,
+    pub(crate) closures: Vec<Closure<'input>>,
+    pub(crate) calls: HashMap<NodeId, Call>,
+}
+
+impl<'input> TypeCheckPass<'input> {
+    /// The variables the closure expression `id` captures, whether or not it
+    /// is ever called
+    pub fn captures(&self, id: NodeId) -> Option<&[(&'input str, Type)]> {
+        self.closures
+            .iter()
+            .find(|closure| closure.id == id)
+            .map(|closure| closure.captures.as_slice())
+    }
+}
+
+impl<'input> CodePass<'input> for TypeCheckPass<'input> {
+    const NAME: &'static str = "type_check";
     type Prev = MakeSignaturesPass<'input>;
     type Arg = ();
 
-    fn check(prev: Self::Prev, input: &str, _: &impl Is<Self::Arg>) -> Result<Self::Prev> {
-        let mut me = TypeCheck {
-            input,
-            // builder: builder.is(),
-            prev: &prev,
+    fn pass(prev: Self::Prev, _: &'input str, _: &mut impl Is<Self::Arg>) -> Result<Self> {
+        Self::check(prev, None)
+    }
+}
+
+impl<'input> TypeCheckPass<'input> {
+    /// Checks like the pass, except that the function `symbol` returns
+    /// whatever its last expression is. Its declared return type is replaced
+    /// with that, and its `return`s have to agree.
+    pub fn check_inferring_return(prev: MakeSignaturesPass<'input>, symbol: &str) -> Result<Self> {
+        Self::check(prev, Some(symbol))
+    }
+
+    fn check(mut prev: MakeSignaturesPass<'input>, infer: Option<&str>) -> Result<Self> {
+        let mut checker = TypeCheck::new(&prev.signatures);
+        checker.infer = infer.map(str::to_string);
+        checker.check_functions()?;
+        let TypeCheck {
+            types,
+            closures,
+            calls,
+            inferred,
+            ..
+        } = checker;
+
+        if let (Some(symbol), Some(ret_type)) = (infer, inferred) {
+            if let Some(function) = prev.signatures.functions.get_mut(symbol) {
+                function.ret_type = ret_type;
+            }
+        }
+
+        Ok(Self {
+            signatures: prev.signatures,
+            types,
+            closures,
+            calls,
+        })
+    }
+}
+
+pub(crate) struct TypeCheck<'input, 'a> {
+    signatures: &'a Signatures<'input>,
+    vars: HashMap<&'input str, Type>,
+    /// Symbol of the function being checked
+    function: String,
+    /// Module of the function being checked, which calls are resolved relative to
+    module: String,
+    /// Bounds on the type parameters of the function being checked
+    bounds: Vec<Vec<String>>,
+    /// What `return` returns, `None` inside closures where it isn't allowed
+    ret_type: Option<Type>,
+    /// The function whose return type is inferred from its body
+    infer: Option<String>,
+    /// The `return`s in it, checked once the return type is known
+    returns: Option<Vec<(Type, Option<Expr<'input>>, Span)>>,
+    /// Its return type, once checked
+    inferred: Option<Type>,
+    /// Closures bound in the current scope, as indexes into `closures`
+    closure_vars: HashMap<&'input str, usize>,
+    closures: Vec<Closure<'input>>,
+    /// What each `Type::Infer(n)` is inferred to be so far, which may be
+    /// another one
+    inferences: Vec<Option<Type>>,
+    /// Calls to functions, keyed by the node id of the call
+    calls: HashMap<NodeId, Call>,
+    types: Types,
+    /// Errors found so far, checking carries on past them
+    errors: Vec<Report>,
+}
+
+impl<'input, 'a> TypeCheck<'input, 'a> {
+    pub(crate) fn new(signatures: &'a Signatures<'input>) -> Self {
+        TypeCheck {
+            signatures,
             vars: HashMap::new(),
-        };
-        for function in &prev.functions {
-            if function.1 .0 == Linkage::External {
-                continue;
+            function: String::new(),
+            module: String::new(),
+            bounds: vec![],
+            ret_type: None,
+            infer: None,
+            returns: None,
+            inferred: None,
+            closure_vars: HashMap::new(),
+            closures: vec![],
+            inferences: vec![],
+            calls: HashMap::new(),
+            types: HashMap::new(),
+            errors: vec![],
+        }
+    }
+
+    fn check_functions(&mut self) -> Result<()> {
+        let signatures = self.signatures;
+        // In source order, so the errors are as well
+        let mut functions = signatures
+            .functions
+            .iter()
+            .filter(|(_, function)| function.linkage != Linkage::External)
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|(_, function)| function.span.start);
+        for (name, function) in functions {
+            let result = self.check_function(name.to_string(), function);
+            self.report(result);
+        }
+
+        let mut impls = signatures.impls.iter().collect::<Vec<_>>();
+        impls.sort_by_key(|(_, impl_)| impl_.span.start);
+        for ((trait_, self_type), impl_) in impls {
+            let result = self.check_impl(trait_, *self_type, impl_);
+            self.report(result);
+        }
+
+        // Closure parameters are only used in the function they're defined
+        // in, so everything there is to know about them is known by now
+        for closure in &mut self.closures {
+            for (param, type_) in &mut closure.params {
+                if let Type::Infer(_) = resolve(&self.inferences, *type_) {
+                    self.errors.push(
+                        Error::CannotInferParamType(param.name.to_string())
+                            .labelled(param.span.into())
+                            .into(),
+                    );
+                }
+                *type_ = finish(&self.inferences, *type_);
+            }
+            closure.ret_type = finish(&self.inferences, closure.ret_type);
+            for (_, type_) in &mut closure.captures {
+                *type_ = finish(&self.inferences, *type_);
+            }
+        }
+        for call in self.calls.values_mut() {
+            for type_ in &mut call.type_args {
+                *type_ = finish(&self.inferences, *type_);
             }
-            me.vars = HashMap::new();
-            for (name, type_) in &function.1 .1 {
-                me.vars.insert(name, *type_);
+        }
+        for type_ in self.types.values_mut() {
+            *type_ = finish(&self.inferences, *type_);
+        }
+
+        Errors::check(std::mem::take(&mut self.errors))
+    }
+
+    fn resolve(&self, type_: Type) -> Type {
+        resolve(&self.inferences, type_)
+    }
+
+    /// Checks that `from` coerces to `to`, inferring closure parameter types
+    /// on the way. A literal that a parameter was inferred to be can still be
+    /// narrowed to a specific integer type.
+    fn unify(&mut self, from: Type, to: Type) -> Result<Type> {
+        let (from_var, from_type) = self.root(from);
+        let (to_var, to_type) = self.root(to);
+        match (from_type, to_type) {
+            (Type::Infer(a), Type::Infer(b)) if a == b => {}
+            (Type::Infer(a), _) => self.inferences[a] = Some(to_type),
+            (_, Type::Infer(b)) => self.inferences[b] = Some(from_type),
+            _ => {
+                let coerced = match from_type.coerce(to_type) {
+                    Ok(coerced) => coerced,
+                    // Arguments narrow a parameter inferred to be a literal
+                    Err(_) if to_var.is_some() => to_type.coerce(from_type)?,
+                    Err(error) => return Err(error),
+                };
+                for (var, type_) in [(from_var, from_type), (to_var, to_type)] {
+                    if let Some(var) = var.filter(|_| type_ != coerced) {
+                        self.inferences[var] = Some(coerced);
+                    }
+                }
+                if from_var.is_none() && to_var.is_none() {
+                    return Ok(coerced);
+                }
             }
-            let mut result = Type::Void;
-            for node in &function.1 .3 {
-                result = me.check_node(node)?;
+        }
+        // Keep the variable, so literals given this type follow it
+        Ok(if to_var.is_some() { to } else { from })
+    }
+
+    /// The last inference variable `type_` leads to, and what it's inferred
+    /// to be, which is the variable itself if nothing is known yet
+    fn root(&self, mut type_: Type) -> (Option<usize>, Type) {
+        let mut var = None;
+        while let Type::Infer(n) = type_ {
+            var = Some(n);
+            match self.inferences[n] {
+                Some(inferred) => type_ = inferred,
+                None => break,
             }
-            let result_coerced = result.coerce(function.1 .2);
+        }
+        (var, type_)
+    }
+
+    /// Checks that an impl has exactly the methods of its trait, with the
+    /// right signatures, and checks the methods themselves
+    fn check_impl(&mut self, trait_: &str, self_type: Type, impl_: &Impl<'input>) -> Result<()> {
+        let signatures = self.signatures;
+        let span: SourceSpan = impl_.span.into();
+        let declared = &signatures
+            .traits
+            .get(trait_)
+            .ok_or_else(|| Error::TraitDoesntExist(trait_.to_string()).labelled(span))?
+            .methods;
+
+        for (name, expected) in declared {
+            let method = impl_.methods.get(name).ok_or_else(|| {
+                Error::MissingTraitMethod(trait_.to_string(), name.to_string()).labelled(span)
+            })?;
+            let expected_args = expected
+                .args
+                .iter()
+                .map(|(_, type_)| type_.substitute(&[self_type]))
+                .collect::<Vec<_>>();
+            let args = method
+                .args
+                .iter()
+                .map(|(_, type_)| *type_)
+                .collect::<Vec<_>>();
             ensure!(
-                result_coerced.is_ok(),
-                crate::Error::TypeMismatch(
-                    format!("{:?}", function.1 .2),
-                    format!("{:?}", result),
-                    (function.1 .3.last().unwrap().start..function.1 .3.last().unwrap().end).into()
+                args == expected_args,
+                Error::TypeMismatch(format!("{:?}", expected_args), format!("{:?}", args), span)
+            );
+            let expected_ret = expected.ret_type.substitute(&[self_type]);
+            ensure!(
+                method.ret_type == expected_ret,
+                Error::TypeMismatch(
+                    format!("{:?}", expected_ret),
+                    format!("{:?}", method.ret_type),
+                    span
                 )
             );
         }
 
-        Ok(prev)
-    }
-}
+        let mut methods = impl_.methods.iter().collect::<Vec<_>>();
+        methods.sort_by_key(|(_, method)| method.span.start);
+        for (name, method) in methods {
+            ensure!(
+                declared.contains_key(name),
+                Error::NotATraitMethod(trait_.to_string(), name.to_string()).labelled(span)
+            );
+            let result = self.check_function(mangle_impl(trait_, self_type, name), method);
+            self.report(result);
+        }
 
-impl TypeCheck<'_, '_> {
-    fn check_node(&mut self, node: &ASTNode<NodeValue>) -> Result<Type> {
-        match node.node {
-            NodeValue::NULL => todo!(),
-            NodeValue::Id => {
-                let id = &self.input[node.start..node.end];
-                Ok(self
-                    .vars
-                    .get(id)
-                    .ok_or_else(|| {
-                        Error::VarDoesntExist(id.to_string())
-                            .labelled((node.start..node.end).into())
-                    })
-                    .cloned()?)
-            }
-            NodeValue::Number => Ok(Type::Number),
-            NodeValue::Add => todo!(),
-            NodeValue::Sub => todo!(),
-            NodeValue::Mul => todo!(),
-            NodeValue::Div => todo!(),
-            NodeValue::GT => todo!(),
-            NodeValue::GTE => todo!(),
-            NodeValue::EQ => todo!(),
-            NodeValue::LT => todo!(),
-            NodeValue::LTE => todo!(),
-            NodeValue::Product => {
-                if node.children.len() == 1 {
-                    self.check_node(&node.children[0])
-                } else {
-                    let mut iter = node.children.iter();
+        Ok(())
+    }
 
-                    let lhs = self.check_node(iter.next().unwrap())?;
-                    while let Some(_op) = iter.next() {
-                        let rhs_node = iter.next().unwrap();
-                        let rhs = self.check_node(rhs_node)?.coerce(lhs)?;
+    /// Keeps an error to report once everything is checked
+    fn report(&mut self, result: Result<()>) {
+        if let Err(error) = result {
+            self.errors.push(error);
+        }
+    }
 
-                        ensure!(
-                            lhs == rhs,
-                            Error::TypeMismatch(
-                                format!("{:?}", lhs),
-                                format!("{:?}", rhs),
-                                (rhs_node.start..rhs_node.end).into()
-                            )
-                        );
-                    }
+    fn check_function(&mut self, symbol: String, function: &Function<'input>) -> Result<()> {
+        let infer = self.infer.as_ref() == Some(&symbol);
+        self.function = symbol;
+        self.module = function.module.clone();
+        self.bounds = function.bounds.clone();
+        if infer {
+            self.ret_type = None;
+            self.returns = Some(vec![]);
+        } else {
+            self.ret_type = Some(function.ret_type);
+        }
+        self.vars = HashMap::new();
+        self.closure_vars = HashMap::new();
+        for (name, type_) in &function.args {
+            self.vars.insert(name, *type_);
+        }
+        let mut result = Type::Void;
+        for expr in &function.body {
+            result = self.check_expr(expr);
+        }
 
-                    Ok(lhs)
+        let mut ret_type = function.ret_type;
+        if let Some(returns) = self.returns.take() {
+            ret_type = match self.resolve(result) {
+                Type::Number => Type::Int,
+                type_ => type_,
+            };
+            self.inferred = Some(ret_type);
+            for (value_type, value, span) in returns {
+                if let Some(value) = &value {
+                    self.settle(value, ret_type);
                 }
+                ensure!(
+                    self.unify(value_type, ret_type).is_ok(),
+                    Error::TypeMismatch(
+                        format!("{:?}", ret_type),
+                        format!("{:?}", self.resolve(value_type)),
+                        span.into()
+                    )
+                );
             }
-            NodeValue::Sum => {
-                if node.children.len() == 1 {
-                    self.check_node(&node.children[0])
-                } else {
-                    let mut iter = node.children.iter();
+        }
 
-                    let lhs = self.check_node(iter.next().unwrap())?;
-                    while let Some(_op) = iter.next() {
-                        let rhs_node = iter.next().unwrap();
-                        let rhs = self.check_node(rhs_node)?.coerce(lhs)?;
+        if let Some(last) = function.body.last() {
+            self.settle(last, ret_type);
+        }
+        let result_coerced = self.unify(result, ret_type);
+        let span = function.body.last().map_or(function.span, |expr| expr.span);
+        ensure!(
+            result_coerced.is_ok(),
+            crate::Error::TypeMismatch(
+                format!("{:?}", ret_type),
+                format!("{:?}", self.resolve(result)),
+                span.into()
+            )
+        );
 
-                        ensure!(
-                            lhs == rhs,
-                            Error::TypeMismatch(
-                                format!("{:?}", lhs),
-                                format!("{:?}", rhs),
-                                (rhs_node.start..rhs_node.end).into()
-                            )
-                        );
-                    }
+        Ok(())
+    }
 
-                    Ok(lhs)
+    /// Checks that the type arguments of a call satisfy the callee's bounds
+    fn check_bounds(
+        &self,
+        function: &Function<'input>,
+        type_args: &[Type],
+        call: Span,
+    ) -> Result<()> {
+        for (type_arg, bounds) in type_args.iter().zip(&function.bounds) {
+            for bound in bounds {
+                let satisfied = match type_arg {
+                    // Bounds on the caller's own type parameters are all that is known about them
+                    Type::Generic(n) => self.bounds[*n].contains(bound),
+                    // Already reported
+                    Type::Error => true,
+                    _ => self
+                        .signatures
+                        .impls
+                        .contains_key(&(bound.clone(), *type_arg)),
+                };
+                ensure!(
+                    satisfied,
+                    Error::UnsatisfiedBound(format!("{:?}", type_arg), bound.to_string())
+                        .labelled(call.into())
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Works out the type arguments of a call to a generic function from the
+    /// types of its arguments
+    fn infer_type_args(
+        &self,
+        function: &Function<'input>,
+        args: &[(Type, Span)],
+        call: Span,
+    ) -> Result<Vec<Type>> {
+        let mut type_args: Vec<Option<Type>> = vec![None; function.type_params.len()];
+        for ((_, expected), arg) in function.args.iter().zip(args) {
+            let n = match expected {
+                Type::Generic(n) => *n,
+                _ => continue,
+            };
+            let arg_type = self.resolve(arg.0);
+            type_args[n] = match type_args[n] {
+                None => Some(arg_type),
+                // A literal only says the type is some integer, so a later argument can narrow it
+                Some(Type::Number) if Type::Number.coerce(arg_type).is_ok() => Some(arg_type),
+                Some(inferred) => {
+                    ensure!(
+                        arg_type.coerce(inferred).is_ok(),
+                        Error::TypeMismatch(
+                            format!("{:?}", inferred),
+                            format!("{:?}", arg_type),
+                            arg.1.into()
+                        )
+                    );
+                    Some(inferred)
                 }
+            };
+        }
+
+        type_args
+            .into_iter()
+            .zip(&function.type_params)
+            .map(|(type_, name)| match type_ {
+                Some(Type::Number) => Ok(Type::Int),
+                // A closure parameter whose type isn't known yet says nothing
+                Some(type_) if !matches!(type_, Type::Infer(_)) => Ok(type_),
+                _ => Err(Error::CannotInferTypeParam(name.to_string())
+                    .labelled(call.into())
+                    .into()),
+            })
+            .collect()
+    }
+
+    /// Records a closure bound with `let`, working out what it captures from
+    /// the current scope, and checks its body. Unannotated parameters are
+    /// inferred from the body and from calls, by the end of the function.
+    fn bind_closure(
+        &mut self,
+        closure: &Expr<'input>,
+        closure_params: &[ClosureParam<'input>],
+        body: &Expr<'input>,
+    ) -> Result<usize> {
+        let mut params = vec![];
+        for param in closure_params {
+            let type_ = match param.type_ {
+                Some(type_) => {
+                    Type::from_str(type_.name).map_err(|e| e.labelled(type_.span.into()))?
+                }
+                None => {
+                    self.inferences.push(None);
+                    Type::Infer(self.inferences.len() - 1)
+                }
+            };
+            params.push((param.name, type_));
+        }
+
+        let names = params
+            .iter()
+            .map(|(param, _)| param.name)
+            .collect::<Vec<_>>();
+        let mut captures: Vec<(&'input str, Type)> = vec![];
+        for id in free_variables(body, &names) {
+            if captures.iter().any(|(name, _)| *name == id) {
+                continue;
+            }
+            match self.vars.get(id) {
+                // The environment needs a concrete type, and literals default to int
+                Some(Type::Number) => captures.push((id, Type::Int)),
+                Some(type_) => captures.push((id, *type_)),
+                None => {}
             }
-            NodeValue::Expr => self.check_node(&node.children[0]),
-            NodeValue::Root => todo!(),
-            NodeValue::VarAssign => {
-                let identifier = &node.children[0];
-                let identifier = &self.input[identifier.start..identifier.end];
-                let expr = &node.children[1];
-                let expr_type = self.check_node(expr)?;
+        }
+
+        let mut vars: HashMap<&'input str, Type> = captures.iter().cloned().collect();
+        for (param, type_) in &params {
+            vars.insert(param.name, *type_);
+        }
+        let outer_vars = std::mem::replace(&mut self.vars, vars);
+        let outer_closure_vars = std::mem::take(&mut self.closure_vars);
+        let outer_ret_type = self.ret_type.take();
+        let outer_returns = self.returns.take();
+
+        let result = self.check_expr(body);
+        self.settle(body, Type::Int);
 
-                self.vars.insert(identifier, expr_type);
+        self.vars = outer_vars;
+        self.closure_vars = outer_closure_vars;
+        self.ret_type = outer_ret_type;
+        self.returns = outer_returns;
+
+        self.closures.push(Closure {
+            function: self.function.clone(),
+            id: closure.id,
+            span: closure.span,
+            params,
+            ret_type: match result {
+                Type::Number => Type::Int,
+                type_ => type_,
+            },
+            body: body.clone(),
+            captures,
+        });
+
+        Ok(self.closures.len() - 1)
+    }
+
+    /// Checks a call to a closure, which may say what type its unannotated
+    /// parameters are
+    fn call_closure(
+        &mut self,
+        index: usize,
+        arg_exprs: &[Expr<'input>],
+        args: Vec<(Type, Span)>,
+        args_span: Span,
+    ) -> Result<Type> {
+        let expected = self.closures[index].params.len();
+        ensure!(
+            expected == args.len(),
+            Error::ExpectedNumArgs(expected, args.len()).labelled(args_span.into())
+        );
+
+        let param_types = self.closures[index]
+            .params
+            .iter()
+            .map(|(_, type_)| *type_)
+            .collect::<Vec<_>>();
+        for ((expected, arg), expr) in param_types.into_iter().zip(&args).zip(arg_exprs) {
+            ensure!(
+                self.unify(arg.0, expected).is_ok(),
+                Error::TypeMismatch(
+                    format!("{:?}", self.resolve(expected)),
+                    format!("{:?}", self.resolve(arg.0)),
+                    arg.1.into()
+                )
+            );
+            self.settle(expr, expected);
+        }
+
+        Ok(self.closures[index].ret_type)
+    }
+}
 
-                Ok(expr_type)
+/// Every variable used in `expr`, in order of appearance, except the
+/// closure's own parameters
+fn free_variables<'input>(expr: &Expr<'input>, params: &[&'input str]) -> Vec<&'input str> {
+    let mut found = vec![];
+    match &expr.kind {
+        ExprKind::Var(id) => {
+            if !params.contains(id) {
+                found.push(*id);
             }
-            NodeValue::FnDef => todo!(),
-            NodeValue::FnCall => {
-                let id = &node.children[0];
-                let id = &self.input[id.start..id.end];
+        }
+        ExprKind::Number(_) | ExprKind::Bool(_) => {}
+        ExprKind::Binary { lhs, rhs, .. } => {
+            found.extend(free_variables(lhs, params));
+            found.extend(free_variables(rhs, params));
+        }
+        ExprKind::Let { value, .. } => found.extend(free_variables(value, params)),
+        ExprKind::Return(value) => {
+            if let Some(value) = value {
+                found.extend(free_variables(value, params));
+            }
+        }
+        ExprKind::Call { args, .. } => {
+            for arg in args {
+                found.extend(free_variables(arg, params));
+            }
+        }
+        ExprKind::If { condition, body } => {
+            found.extend(free_variables(condition, params));
+            for expr in body {
+                found.extend(free_variables(expr, params));
+            }
+        }
+        ExprKind::Closure {
+            params: inner,
+            body,
+        } => {
+            let mut params = params.to_vec();
+            params.extend(inner.iter().map(|param| param.name.name));
+            found.extend(free_variables(body, &params));
+        }
+    }
+    found
+}
 
-                let mut args = vec![];
-                let arg_set = &node.children[1];
-                match arg_set.node {
-                    NodeValue::FnCallArgSet => {
-                        // dbg!(&arg_set.children);
-                        for arg in &arg_set.children {
-                            args.push((self.check_node(arg)?, arg.start, arg.end))
-                        }
-                    }
-                    NodeValue::NULL => {}
-                    _ => unreachable!(),
-                }
+/// What `type_` is inferred to be so far
+fn resolve(inferences: &[Option<Type>], mut type_: Type) -> Type {
+    while let Type::Infer(n) = type_ {
+        match inferences[n] {
+            Some(inferred) => type_ = inferred,
+            None => break,
+        }
+    }
+    type_
+}
 
-                let (_, expected_args, ret_type, _) = self
-                    .prev
-                    .functions
-                    .get(id)
-                    .ok_or_else(|| miette!("Unknown function"))?;
+/// The type `type_` ends up as. Literals that nothing gave a type to are
+/// ints, and parameters that couldn't be inferred are already reported.
+fn finish(inferences: &[Option<Type>], type_: Type) -> Type {
+    match resolve(inferences, type_) {
+        Type::Number => Type::Int,
+        Type::Infer(_) => Type::Error,
+        type_ => type_,
+    }
+}
 
-                ensure!(
-                    expected_args.len() == args.len(),
-                    Error::ExpectedNumArgs(expected_args.len(), args.len())
-                        .labelled((arg_set.start..arg_set.end).into())
-                );
+impl<'input> TypeCheck<'input, '_> {
+    /// Checks an expression and records its type. Errors are kept for later,
+    /// and the expression gets the error type.
+    fn check_expr(&mut self, expr: &Expr<'input>) -> Type {
+        let type_ = match self.check_expr_kind(expr) {
+            Ok(type_) => type_,
+            Err(error) => {
+                self.errors.push(error);
+                Type::Error
+            }
+        };
+        self.types.insert((self.function.clone(), expr.id), type_);
+        type_
+    }
 
-                for (expected, arg) in expected_args.iter().zip(args) {
-                    ensure!(
-                        expected.1 == arg.0,
+    /// Checks an expression whose value is used, which a call to a void
+    /// function doesn't have
+    fn check_operand(&mut self, expr: &Expr<'input>) -> Type {
+        let type_ = self.check_expr(expr);
+        match self.resolve(type_) {
+            Type::Void => {
+                self.errors
+                    .push(Error::VoidValue.labelled(expr.span.into()).into());
+                Type::Error
+            }
+            _ => type_,
+        }
+    }
+
+    /// Gives a literal, or arithmetic on literals, the type it is used as
+    fn settle(&mut self, expr: &Expr<'input>, type_: Type) {
+        let key = (self.function.clone(), expr.id);
+        if self.types.get(&key) != Some(&Type::Number) || type_ == Type::Number {
+            return;
+        }
+        self.types.insert(key, type_);
+        if let ExprKind::Binary { op, lhs, rhs } = &expr.kind {
+            if !op.is_comparison() {
+                self.settle(lhs, type_);
+                self.settle(rhs, type_);
+            }
+        }
+    }
+
+    fn check_expr_kind(&mut self, expr: &Expr<'input>) -> Result<Type> {
+        match &expr.kind {
+            ExprKind::Var(id) => Ok(self
+                .vars
+                .get(id)
+                .ok_or_else(|| Error::VarDoesntExist(id.to_string()).labelled(expr.span.into()))
+                .cloned()?),
+            ExprKind::Number(_) => Ok(Type::Number),
+            ExprKind::Bool(_) => Ok(Type::Bool),
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs_type = self.check_operand(lhs);
+                let rhs_type = self.check_operand(rhs);
+
+                // Either side may be a literal, which takes the type of the other
+                let type_ = match self.unify(lhs_type, rhs_type) {
+                    Ok(type_) => type_,
+                    Err(_) => self.unify(rhs_type, lhs_type).map_err(|_| {
                         Error::TypeMismatch(
-                            format!("{:?}", expected.1),
-                            format!("{:?}", arg.0),
-                            (arg.1..arg.2).into()
+                            format!("{:?}", self.resolve(lhs_type)),
+                            format!("{:?}", self.resolve(rhs_type)),
+                            rhs.span.into(),
                         )
+                    })?,
+                };
+
+                // Only integers have the other operators, so a parameter
+                // nothing else is known about is one
+                if *op != BinaryOp::Eq {
+                    if let Type::Infer(_) = self.resolve(type_) {
+                        self.unify(type_, Type::Number)?;
+                    }
+                }
+
+                self.settle(lhs, type_);
+                self.settle(rhs, type_);
+
+                // Nothing says a type parameter is an integer
+                if *op != BinaryOp::Eq
+                    && matches!(
+                        self.resolve(type_),
+                        Type::Bool | Type::Void | Type::Generic(_)
+                    )
+                {
+                    bail!(Error::OperatorNotDefined(
+                        format!("{:?}", op),
+                        format!("{:?}", self.resolve(type_))
                     )
+                    .labelled(expr.span.into()));
                 }
 
-                Ok(*ret_type)
-            }
-            NodeValue::FnDefArgSet => todo!(),
-            NodeValue::FnCallArgSet => todo!(),
-            NodeValue::Extern => todo!(),
-            NodeValue::FnDecl => todo!(),
-            NodeValue::BoolExpr => {
-                if node.children.len() == 1 {
-                    self.check_node(&node.children[0])
+                if op.is_comparison() {
+                    Ok(Type::Bool)
                 } else {
-                    let mut iter = node.children.iter();
+                    Ok(type_)
+                }
+            }
+            ExprKind::Let { name, value } => {
+                if let ExprKind::Closure { params, body } = &value.kind {
+                    let index = self.bind_closure(value, params, body)?;
+                    self.vars.remove(name.name);
+                    self.closure_vars.insert(name.name, index);
+                    return Ok(Type::Void);
+                }
+
+                let value_type = self.check_operand(value);
+
+                self.closure_vars.remove(name.name);
+                self.vars.insert(name.name, value_type);
 
-                    let lhs = self.check_node(iter.next().unwrap())?;
-                    while let Some(_op) = iter.next() {
-                        let rhs_node = iter.next().unwrap();
-                        let rhs = self.check_node(rhs_node)?.coerce(lhs)?;
+                Ok(value_type)
+            }
+            ExprKind::Call {
+                path,
+                args: arg_exprs,
+                args_span,
+            } => {
+                let id = path.joined();
+
+                let mut args = vec![];
+                for arg in arg_exprs {
+                    args.push((self.check_operand(arg), arg.span));
+                }
+
+                if let Some(index) = self.closure_vars.get(id.as_str()).copied() {
+                    return self.call_closure(index, arg_exprs, args, *args_span);
+                }
+
+                let signatures = self.signatures;
+                let mut trait_ = None;
+                let (callee, function) = match signatures.resolve_function(&id, &self.module) {
+                    Some(found) => found,
+                    None => {
+                        let bounds = self.bounds.concat();
+                        let (declaring, method) = signatures
+                            .trait_method(&id, &bounds)
+                            .map_err(|error| error.labelled(path.span.into()))?
+                            .ok_or_else(|| {
+                                Error::FuncDoesntExist(id.clone()).labelled(path.span.into())
+                            })?;
+                        trait_ = Some(declaring.to_string());
+                        (id.clone(), method)
+                    }
+                };
+
+                ensure!(
+                    function.args.len() == args.len(),
+                    Error::ExpectedNumArgs(function.args.len(), args.len())
+                        .labelled((*args_span).into())
+                );
+
+                let type_args = if function.is_generic() {
+                    let type_args = self.infer_type_args(function, &args, expr.span)?;
+                    self.check_bounds(function, &type_args, expr.span)?;
+                    type_args
+                } else {
+                    vec![]
+                };
 
-                        ensure!(
-                            lhs == rhs,
+                for ((expected, arg), arg_expr) in function.args.iter().zip(args).zip(arg_exprs) {
+                    let expected = expected.1.substitute(&type_args);
+                    // A literal takes the type of the parameter. The call
+                    // still has the return type if an argument is wrong.
+                    if self.unify(arg.0, expected).is_err() {
+                        self.errors.push(
                             Error::TypeMismatch(
-                                format!("{:?}", lhs),
-                                format!("{:?}", rhs),
-                                (rhs_node.start..rhs_node.end).into()
+                                format!("{:?}", expected),
+                                format!("{:?}", self.resolve(arg.0)),
+                                arg.1.into(),
                             )
+                            .into(),
                         );
                     }
+                    self.settle(arg_expr, expected);
+                }
 
-                    Ok(Type::Bool)
+                let ret_type = function.ret_type.substitute(&type_args);
+                self.calls.insert(
+                    expr.id,
+                    Call {
+                        caller: self.function.clone(),
+                        callee,
+                        trait_,
+                        type_args,
+                    },
+                );
+
+                Ok(ret_type)
+            }
+            ExprKind::If { condition, body } => {
+                let condition_type = self.check_operand(condition);
+                if self.unify(condition_type, Type::Bool).is_err() {
+                    // The body can still be checked
+                    self.errors.push(
+                        Error::TypeMismatch(
+                            format!("{:?}", Type::Bool),
+                            format!("{:?}", self.resolve(condition_type)),
+                            condition.span.into(),
+                        )
+                        .into(),
+                    );
+                }
+
+                // Bindings in the body end with it
+                let outer_vars = self.vars.clone();
+                let outer_closure_vars = self.closure_vars.clone();
+                for expr in body {
+                    self.check_expr(expr);
                 }
+                self.vars = outer_vars;
+                self.closure_vars = outer_closure_vars;
+
+                // Without an `else` there's no value when the condition is false
+                Ok(Type::Void)
             }
-            NodeValue::True => Ok(Type::Bool),
-            NodeValue::False => Ok(Type::Bool),
-            NodeValue::If => {
-                let final_expr = &node.children[node.children.len() - 1];
+            ExprKind::Return(value) => {
+                if self.returns.is_some() {
+                    // Checked against the return type once it is inferred
+                    let value_type = match value {
+                        Some(value) => self.check_operand(value),
+                        None => Type::Void,
+                    };
+                    let span = value.as_ref().map_or(expr.span, |value| value.span);
+                    if let Some(returns) = &mut self.returns {
+                        returns.push((value_type, value.as_deref().cloned(), span));
+                    }
+                    return Ok(value_type);
+                }
+                let ret_type = self.ret_type.ok_or_else(|| {
+                    Error::NotAllowedHere("`return`".to_string(), "closures".to_string())
+                        .labelled(expr.span.into())
+                })?;
+                let (value_type, span) = match value {
+                    Some(value) => {
+                        let value_type = self.check_operand(value);
+                        self.settle(value, ret_type);
+                        (value_type, value.span)
+                    }
+                    None => (Type::Void, expr.span),
+                };
+                ensure!(
+                    self.unify(value_type, ret_type).is_ok(),
+                    Error::TypeMismatch(
+                        format!("{:?}", ret_type),
+                        format!("{:?}", self.resolve(value_type)),
+                        span.into()
+                    )
+                );
 
-                self.check_node(final_expr)
+                // Whatever uses the value of a `return` is never reached
+                Ok(ret_type)
             }
+            ExprKind::Closure { .. } => bail!(Error::NotAllowedHere(
+                "Closures".to_string(),
+                "this position, bind it with `let` first".to_string()
+            )
+            .labelled(expr.span.into())),
         }
     }
 }
@@ -227,9 +899,12 @@ impl TypeCheck<'_, '_> {
 mod tests {
     use miette::*;
 
-    use crate::{codepass::PassRunner, make_signatures::MakeSignaturesPass, parsepass::ParsePass};
+    use crate::{
+        codepass::PassRunner, lower::LowerPass, make_signatures::MakeSignaturesPass,
+        parsepass::ParsePass, type_::Type,
+    };
 
-    use super::TypeCheck;
+    use super::TypeCheckPass;
 
     #[test]
     fn bool_in_int_function_call() -> Result<()> {
@@ -244,8 +919,9 @@ mod tests {
         ";
         let result = PassRunner::<(), ()>::new(input)
             .run::<ParsePass>()?
+            .run::<LowerPass>()?
             .run::<MakeSignaturesPass>()?
-            .inject::<TypeCheck>();
+            .run::<TypeCheckPass>();
 
         assert!(result.is_err());
 
@@ -265,8 +941,9 @@ mod tests {
         ";
         let result = PassRunner::<(), ()>::new(input)
             .run::<ParsePass>()?
+            .run::<LowerPass>()?
             .run::<MakeSignaturesPass>()?
-            .inject::<TypeCheck>();
+            .run::<TypeCheckPass>();
 
         assert!(result.is_err());
 
@@ -288,11 +965,405 @@ mod tests {
         ";
         let result = PassRunner::<(), ()>::new(input)
             .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>();
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn closure_captures_variable() -> Result<()> {
+        let input = "
+        fn main -> int {
+            let offset = 5;
+            let add = |x| x + offset;
+            add(1)
+        }
+        ";
+        PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn closure_param_inferred_from_body() -> Result<()> {
+        let input = "
+        fn main -> bool {
+            let not = |x| x == false;
+            not(true);
+            not(1)
+        }
+        ";
+        let result = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>();
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn closure_checked_where_defined() -> Result<()> {
+        let check = |input: &'static str| {
+            PassRunner::<(), ()>::new(input)
+                .run::<ParsePass>()?
+                .run::<LowerPass>()?
+                .run::<MakeSignaturesPass>()?
+                .run::<TypeCheckPass>()
+        };
+
+        // Neither is ever called
+        for (input, expected) in [
+            ("fn main -> int { let f = |x| x + true; 1 }", "L0028"),
+            ("fn main -> int { let same = |a| a == a; 1 }", "L0029"),
+        ] {
+            let error = check(input).err().unwrap();
+            let code = error.diagnostic_source().and_then(|error| error.code());
+            assert_eq!(code.unwrap().to_string(), expected, "{input}");
+        }
+
+        // `x` is some integer from the body, and the call narrows it
+        let input = "
+        fn inc(n: uint) -> uint {
+            let add = |x| x + 1;
+            add(n)
+        }
+        ";
+        let types = check(input)?.into_inner().types;
+        let mut types = types.into_values().collect::<Vec<_>>();
+        types.sort_by_key(|type_| format!("{:?}", type_));
+        types.dedup();
+        assert_eq!(types, [Type::Uint, Type::Void]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn generic_args_must_agree() -> Result<()> {
+        let input = "
+        fn main -> int {
+            max(1, true)
+        }
+
+        fn max<T>(a: T, b: T) -> T {
+            a
+        }
+        ";
+        let result = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
             .run::<MakeSignaturesPass>()?
-            .inject::<TypeCheck>();
+            .run::<TypeCheckPass>();
 
         assert!(result.is_err());
 
         Ok(())
     }
+
+    #[test]
+    fn generic_return_type_inferred() -> Result<()> {
+        let input = "
+        fn main -> bool {
+            first(true, false)
+        }
+
+        fn first<T>(a: T, b: T) -> T {
+            a
+        }
+        ";
+        PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn trait_bound_satisfied_by_impl() -> Result<()> {
+        let input = "
+        trait Double {
+            fn double(self) -> Self;
+        }
+
+        impl Double for int {
+            fn double(self) -> int {
+                self * 2
+            }
+        }
+
+        fn twice<T: Double>(x: T) -> T {
+            double(double(x))
+        }
+
+        fn main -> int {
+            twice(4)
+        }
+        ";
+        PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn trait_bound_without_impl() -> Result<()> {
+        let input = "
+        trait Double {
+            fn double(self) -> Self;
+        }
+
+        impl Double for int {
+            fn double(self) -> int {
+                self * 2
+            }
+        }
+
+        fn twice<T: Double>(x: T) -> T {
+            double(double(x))
+        }
+
+        fn main -> bool {
+            twice(true)
+        }
+        ";
+        let result = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>();
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn trait_method_resolved_through_bound() -> Result<()> {
+        let traits = "
+        trait Double {
+            fn double(self) -> Self;
+        }
+
+        trait Twice {
+            fn double(self) -> Self;
+        }
+
+        impl Double for int {
+            fn double(self) -> int {
+                self * 2
+            }
+        }
+
+        impl Twice for int {
+            fn double(self) -> int {
+                self + self
+            }
+        }
+        ";
+        let check = |main: &str| {
+            let input = format!("{traits}{main}");
+            PassRunner::<(), ()>::new(&input)
+                .run::<ParsePass>()?
+                .run::<LowerPass>()?
+                .run::<MakeSignaturesPass>()?
+                .run::<TypeCheckPass>()
+                .map(|_| ())
+        };
+
+        check(
+            "
+            fn quadruple<T: Double>(x: T) -> T {
+                double(double(x))
+            }
+
+            fn main -> int {
+                quadruple(1)
+            }
+            ",
+        )?;
+        let error = check("fn main -> int { double(1) }").unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Method double is declared by more than one trait: Double, Twice"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn module_function_called_by_path() -> Result<()> {
+        let input = "
+        mod math {
+            fn double(x: int) -> int {
+                x * 2
+            }
+
+            fn quadruple(x: int) -> int {
+                double(double(x))
+            }
+        }
+
+        fn main -> int {
+            math::quadruple(1)
+        }
+        ";
+        PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn module_function_needs_path() -> Result<()> {
+        let input = "
+        mod math {
+            fn double(x: int) -> int {
+                x * 2
+            }
+        }
+
+        fn main -> int {
+            double(1)
+        }
+        ";
+        let result = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>();
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn literals_take_the_type_they_are_used_as() -> Result<()> {
+        let input = "
+        fn main -> bool {
+            half(4) > 1
+        }
+
+        fn half(x: uint) -> uint {
+            x / 2
+        }
+        ";
+        let types = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?
+            .into_inner()
+            .types;
+
+        let of = |function: &str| {
+            let mut types = types
+                .iter()
+                .filter(|((name, _), _)| name == function)
+                .map(|(_, type_)| *type_)
+                .collect::<Vec<_>>();
+            types.sort_by_key(|type_| format!("{:?}", type_));
+            types.dedup();
+            types
+        };
+        assert_eq!(of("half"), [Type::Uint]);
+        assert_eq!(of("main"), [Type::Bool, Type::Uint]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_every_error() -> Result<()> {
+        let input = "
+        fn main -> int {
+            let a = missing;
+            other_function(true, 1);
+            a + 1
+        }
+
+        fn other_function(x: int, y: bool) -> int {
+            x
+        }
+        ";
+        let error = PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()
+            .err()
+            .unwrap();
+
+        assert_eq!(error.related().unwrap().count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn if_without_else_is_void() -> Result<()> {
+        let check = |input: &str| {
+            PassRunner::<(), ()>::new(input)
+                .run::<ParsePass>()?
+                .run::<LowerPass>()?
+                .run::<MakeSignaturesPass>()?
+                .run::<TypeCheckPass>()
+                .map(|_| ())
+        };
+
+        assert!(check("fn main -> int { if true { 5 } }").is_err());
+        // Bindings in the body don't outlive it
+        assert!(check("fn main -> int { if true { let x = 5; }; x }").is_err());
+        check(
+            "
+            fn main -> int {
+                let x = 1;
+                if true {
+                    let x = true;
+                };
+                x
+            }
+            ",
+        )
+    }
+
+    #[test]
+    fn operators_need_integers() -> Result<()> {
+        let check = |input: &str| {
+            PassRunner::<(), ()>::new(input)
+                .run::<ParsePass>()?
+                .run::<LowerPass>()?
+                .run::<MakeSignaturesPass>()?
+                .run::<TypeCheckPass>()
+                .map(|_| ())
+        };
+
+        for input in [
+            "fn main -> bool { true + false }",
+            "fn main -> bool { true > false }",
+            "fn times<T>(a: T, b: T) -> T { a * b }",
+        ] {
+            let error = check(input).unwrap_err();
+            let code = error.diagnostic_source().and_then(|error| error.code());
+            assert_eq!(code.unwrap().to_string(), "L0028", "{input}");
+        }
+        check("fn main -> bool { true == false }")?;
+        check("fn same<T>(a: T, b: T) -> bool { a == b }")
+    }
 }
@@ -0,0 +1,26 @@
+use std::fs::read_to_string;
+
+use criterion::*;
+use lqdc_common::{
+    lint::{Level, LintLevels},
+    source_map::SourceMap,
+};
+use lqdc_driver::{CompileOptions, Session};
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let input = read_to_string("benches/benchmark.lqd").unwrap();
+    let source_map = SourceMap::new("benchmark.lqd", input);
+    // Warnings would be printed on every iteration
+    let mut lint_levels = LintLevels::default();
+    lint_levels.set("all", Level::Allow).unwrap();
+    let session = Session::new(CompileOptions {
+        lint_levels,
+        ..Default::default()
+    });
+    c.bench_function("full_parse_and_compile", |b| {
+        b.iter(|| session.build_module(&source_map, "benchmark").unwrap())
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use miette::*;
+
+use lqdc_common::emit::ErrorFormat;
+use lqdc_driver::{CompileOptions, Session, Target};
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let error_format = cli.error_format;
+    error_format.exit_on_error(run(cli))
+}
+
+fn run(cli: Cli) -> Result<()> {
+    Session::new(CompileOptions {
+        inputs: vec![cli.input],
+        target: cli.target,
+        output: Some(cli.output),
+        error_format: cli.error_format,
+        ..Default::default()
+    })
+    .compile()?;
+
+    Ok(())
+}
+
+#[derive(Parser)]
+struct Cli {
+    input: PathBuf,
+    /// x64, riscv64 or urcl
+    #[clap(short, long)]
+    target: Target,
+    #[clap(short, long)]
+    output: PathBuf,
+    /// How to print errors, human or json
+    #[clap(long, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+}
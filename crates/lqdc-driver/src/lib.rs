@@ -0,0 +1,563 @@
+//! Compiling Liquid, the same way for every frontend
+//!
+//! `lqdc`, `lqdc-codegem` and `lqdc-urcl` only turn their arguments into
+//! `CompileOptions`, a `Session` runs the passes and writes the output.
+
+use std::{
+    fmt, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use lqdc_codegem::{
+    codegem::{
+        arch::{
+            rv64::{RvInstruction, RvSelector},
+            urcl::{UrclInstruction, UrclSelector},
+            x64::{X64Instruction, X64Selector},
+        },
+        ir::{Module, ModuleBuilder},
+        regalloc::RegAlloc,
+    },
+    codegen::CodegenPass,
+    CodegemError,
+};
+use lqdc_common::{
+    closure_convert::ClosureConvertPass,
+    codepass::{PassManager, Stage},
+    doc::{self, DocFormat},
+    emit::{to_json, ErrorFormat},
+    lint::{LintLevels, LintPass, Lints},
+    lower::LowerPass,
+    make_signatures::MakeSignaturesPass,
+    modules,
+    monomorphize::MonomorphizePass,
+    parsepass::ParsePass,
+    source_map::SourceMap,
+    type_check::TypeCheckPass,
+    Error, IntoLabelled,
+};
+use lqdc_interp::Interpreter;
+use miette::*;
+use serde_json::Value;
+
+/// The architecture to generate assembly for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    #[default]
+    X64,
+    Riscv64,
+    Urcl,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x64" => Ok(Self::X64),
+            "riscv64" => Ok(Self::Riscv64),
+            "urcl" => Ok(Self::Urcl),
+            _ => Err(format!("Unknown target {s}, expected x64, riscv64 or urcl")),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::X64 => write!(f, "x64"),
+            Self::Riscv64 => write!(f, "riscv64"),
+            Self::Urcl => write!(f, "urcl"),
+        }
+    }
+}
+
+/// A kind of file written for each input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// Assembly for the target, `name.s`
+    Assembly,
+    /// Codegem IR, `name.codegem`
+    Codegem,
+}
+
+impl Emit {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Assembly => "s",
+            Self::Codegem => "codegem",
+        }
+    }
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asm" => Ok(Self::Assembly),
+            "codegem" => Ok(Self::Codegem),
+            _ => Err(format!("Unknown emit kind {s}, expected asm or codegem")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// Root files, each compiled into its own module with its imports
+    pub inputs: Vec<PathBuf>,
+    pub target: Target,
+    pub emit: Vec<Emit>,
+    /// Codegem doesn't optimize yet, so this only reaches the assembler and
+    /// linker, through `-O`
+    pub opt_level: u8,
+    /// Where emitted files are written, named after their input
+    pub out_dir: PathBuf,
+    /// Where the assembly is written instead, with a single input
+    pub output: Option<PathBuf>,
+    /// Stop after checking the program, without generating code
+    pub check: bool,
+    /// The most errors to report at once, `None` for no limit
+    pub error_limit: Option<usize>,
+    pub error_format: ErrorFormat,
+    pub lint_levels: LintLevels,
+    /// Print how long each pass took
+    pub time_passes: bool,
+    /// Passes to enable or disable by name, in order
+    pub passes: Vec<(String, bool)>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            inputs: vec![],
+            target: Target::default(),
+            emit: vec![Emit::Assembly],
+            opt_level: 0,
+            out_dir: PathBuf::from("."),
+            output: None,
+            check: false,
+            error_limit: Some(20),
+            error_format: ErrorFormat::default(),
+            lint_levels: LintLevels::default(),
+            time_passes: false,
+            passes: vec![],
+        }
+    }
+}
+
+/// The files written for an input
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub name: String,
+    pub files: Vec<(Emit, PathBuf)>,
+}
+
+impl Output {
+    pub fn file(&self, emit: Emit) -> Option<&Path> {
+        self.files
+            .iter()
+            .find(|(kind, _)| *kind == emit)
+            .map(|(_, path)| path.as_path())
+    }
+}
+
+pub struct Session {
+    options: CompileOptions,
+}
+
+impl Session {
+    pub fn new(options: CompileOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    /// Compiles every input, stopping at the first that fails
+    pub fn compile(&self) -> Result<Vec<Output>> {
+        if self.options.output.is_some() && self.options.inputs.len() > 1 {
+            bail!("An output path can only be given with a single input");
+        }
+
+        self.options
+            .inputs
+            .iter()
+            .map(|input| self.compile_file(input))
+            .collect()
+    }
+
+    /// Compiles a root file and its imports, and writes every kind of file
+    /// in `emit`. Nothing is written with `check`.
+    pub fn compile_file(&self, input: &Path) -> Result<Output> {
+        let name = module_name(input)?;
+        let source_map = modules::load(input)?;
+        let mut output = Output {
+            name: name.clone(),
+            files: vec![],
+        };
+        let Some(module) = self.build_module(&source_map, &name)? else {
+            return Ok(output);
+        };
+
+        // Lowering to vcode takes the module, so the IR is printed first
+        let ir = self
+            .options
+            .emit
+            .contains(&Emit::Codegem)
+            .then(|| format!("{module}"));
+        let asm = if self.options.emit.contains(&Emit::Assembly) {
+            Some(assembly(module, self.options.target)?)
+        } else {
+            None
+        };
+
+        for (emit, contents) in [(Emit::Codegem, ir), (Emit::Assembly, asm)] {
+            let Some(contents) = contents else { continue };
+            let path = match (&self.options.output, emit) {
+                (Some(path), Emit::Assembly) => path.clone(),
+                _ => self
+                    .options
+                    .out_dir
+                    .join(format!("{name}.{}", emit.extension())),
+            };
+            fs::write(&path, contents)
+                .into_diagnostic()
+                .map_err(|e| e.wrap_err(format!("Failed to write {}", path.display())))?;
+            output.files.push((emit, path));
+        }
+
+        Ok(output)
+    }
+
+    /// Runs every pass over a loaded program, and prints its warnings. There
+    /// is no module with `check`, which stops before codegen.
+    pub fn build_module(&self, source_map: &SourceMap, name: &str) -> Result<Option<Module>> {
+        let mut lints = Lints::new(self.options.lint_levels.clone());
+        lints.source_map = Some(source_map.clone());
+        let result = self.run_passes(source_map, name, &mut lints);
+        lints.report(self.options.error_format);
+        result
+    }
+
+    /// Runs a root file and its imports with the interpreter instead of
+    /// compiling them, returning the exit code
+    pub fn interpret_file(&self, input: &Path, output: impl Write + Send) -> Result<i32> {
+        let source_map = modules::load(input)?;
+        self.interpret(&source_map, output)
+    }
+
+    /// Checks a loaded program like compiling it does, and prints its
+    /// warnings, then runs it from `main`. `putchar` writes to `output`.
+    pub fn interpret(&self, source_map: &SourceMap, output: impl Write + Send) -> Result<i32> {
+        let options = &self.options;
+        let mut lints = Lints::new(options.lint_levels.clone());
+        lints.source_map = Some(source_map.clone());
+        let stage = self.run_stages(source_map, &mut lints, None);
+        lints.report(options.error_format);
+        let stage = stage?;
+        let Stage::Monomorphized(program) = stage else {
+            // A pass it needs was disabled with `-Z disable-pass`
+            bail!(
+                "Interpreting needs a monomorphized program, but got {}",
+                stage.name()
+            );
+        };
+
+        let mut interpreter = Interpreter::new(&program).with_output(output);
+        interpreter
+            .run_main()
+            .map_err(|error| error.with_source_code(source_map.clone()))
+    }
+
+    /// Runs every pass, leaving the warnings in `lints`
+    fn run_passes(
+        &self,
+        source_map: &SourceMap,
+        name: &str,
+        lints: &mut Lints,
+    ) -> Result<Option<Module>> {
+        if self.options.check {
+            self.run_stages(source_map, lints, None)?;
+            return Ok(None);
+        }
+        let mut builder = ModuleBuilder::default().with_name(name);
+        self.run_stages(source_map, lints, Some(&mut builder))?;
+        let module = builder.build().map_err(CodegemError::ModuleCreationError)?;
+        Ok(Some(module))
+    }
+
+    /// Runs the passes up to monomorphizing, then codegen into `builder` if
+    /// there is one, returning the last stage
+    fn run_stages<'input>(
+        &self,
+        source_map: &'input SourceMap,
+        lints: &mut Lints,
+        builder: Option<&mut ModuleBuilder>,
+    ) -> Result<Stage<'input>> {
+        let options = &self.options;
+        let mut passes = PassManager::new(&source_map.source)
+            .with_source_map(source_map)
+            .with_error_limit(options.error_limit)
+            .with_time_passes(options.time_passes)
+            .add::<ParsePass>()
+            .add::<LowerPass>()
+            .add::<MakeSignaturesPass>()
+            .add::<TypeCheckPass>()
+            .add_check::<LintPass, _>(lints)
+            .add::<ClosureConvertPass>()
+            .add::<MonomorphizePass>();
+        if let Some(builder) = builder {
+            passes = passes.add_with_arg::<CodegenPass, _>(builder);
+        }
+        for (name, enabled) in &options.passes {
+            passes.set_enabled(name, *enabled)?;
+        }
+        passes.run()
+    }
+}
+
+/// What compiling a source in memory produced
+#[derive(Debug, Clone, Default)]
+pub struct Compilation {
+    /// Assembly for the target, unless compilation failed or stopped at `check`
+    pub assembly: Option<String>,
+    /// Codegem IR, when there is assembly
+    pub ir: Option<String>,
+    /// Every warning and error, as the objects `--error-format=json` prints
+    pub diagnostics: Vec<Value>,
+}
+
+impl Compilation {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic["severity"] == "error")
+    }
+}
+
+/// Compiles a single source without touching the file system, for embedding
+/// the compiler. Diagnostics are returned instead of printed.
+///
+/// The module is called `main`, and is `main.lqd` in diagnostics. There are
+/// no files to import, so other modules have to be `mod` blocks in the
+/// source. `inputs`, `emit` and the output paths of `options` are ignored.
+pub fn compile_source(source: &str, options: &CompileOptions) -> Compilation {
+    let session = Session::new(options.clone());
+    let source_map = SourceMap::new("main.lqd", source);
+    let mut lints = Lints::new(options.lint_levels.clone());
+    lints.source_map = Some(source_map.clone());
+
+    let result = session
+        .run_passes(&source_map, "main", &mut lints)
+        .and_then(|module| {
+            let Some(module) = module else {
+                return Ok((None, None));
+            };
+            let ir = format!("{module}");
+            Ok((Some(assembly(module, options.target)?), Some(ir)))
+        });
+
+    let mut compilation = Compilation::default();
+    for warning in &lints.warnings {
+        compilation.diagnostics.extend(to_json(&**warning, None));
+    }
+    match result {
+        Ok((assembly, ir)) => {
+            compilation.assembly = assembly;
+            compilation.ir = ir;
+        }
+        Err(error) => compilation
+            .diagnostics
+            .extend(to_json(&**error, Some(&source_map))),
+    }
+    compilation
+}
+
+/// The name of the module compiled from a root file, its file name without
+/// the extension
+pub fn module_name(input: &Path) -> Result<String> {
+    input
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| miette!("Invalid input file name {}", input.display()))
+}
+
+/// Formats a source file in place. With `check` the file is only read.
+/// Returns whether the file isn't formatted.
+pub fn format_file(path: &Path, check: bool) -> Result<bool> {
+    let source = fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    let formatted = frontend::format_source(&source).map_err(|e| {
+        Report::new(Error::ParseError(e.message).labelled(e.span.into()))
+            .with_source_code(NamedSource::new(path.display().to_string(), source.clone()))
+    })?;
+
+    let changed = formatted != source;
+    if changed && !check {
+        fs::write(path, formatted)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(changed)
+}
+
+/// Documents a root file and its imports, writing a page for each module in
+/// every format into `out_dir`. Returns the paths written.
+pub fn document(input: &Path, out_dir: &Path, formats: &[DocFormat]) -> Result<Vec<PathBuf>> {
+    let name = module_name(input)?;
+    let source_map = modules::load(input)?;
+    let stage = PassManager::new(&source_map.source)
+        .with_source_map(&source_map)
+        .add::<ParsePass>()
+        .add::<LowerPass>()
+        .add::<MakeSignaturesPass>()
+        .run()?;
+    let Stage::Signatures(pass) = stage else {
+        bail!(Error::InternalCompilerError(format!(
+            "Documenting needs signatures, but got {}",
+            stage.name()
+        )));
+    };
+
+    let mut written = vec![];
+    for format in formats {
+        for page in doc::document(&name, &pass.signatures, *format) {
+            let path = out_dir.join(&page.path);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+            }
+            fs::write(&path, page.contents)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+/// Lowers a module to assembly for the target
+pub fn assembly(module: Module, target: Target) -> Result<String> {
+    let mut buf = Vec::new();
+    match target {
+        Target::X64 => {
+            let mut vcode = module.lower_to_vcode::<X64Instruction, X64Selector>();
+            vcode.allocate_regs::<RegAlloc>();
+            vcode.emit_assembly(&mut buf).into_diagnostic()?;
+        }
+        Target::Riscv64 => {
+            let mut vcode = module.lower_to_vcode::<RvInstruction, RvSelector>();
+            vcode.allocate_regs::<RegAlloc>();
+            vcode.emit_assembly(&mut buf).into_diagnostic()?;
+        }
+        Target::Urcl => {
+            let mut vcode = module.lower_to_vcode::<UrclInstruction, UrclSelector>();
+            vcode.allocate_regs::<RegAlloc>();
+            vcode.emit_assembly(&mut buf).into_diagnostic()?;
+        }
+    }
+
+    let assembly = String::from_utf8(buf).into_diagnostic()?;
+    Ok(match target {
+        // Codegem generates assembly with percentage signs, but clang and gcc
+        // don't support them
+        Target::X64 => assembly.replace('%', ""),
+        _ => assembly,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use lqdc_common::source_map::SourceMap;
+
+    use super::{compile_source, module_name, CompileOptions, Emit, Session, Target};
+
+    #[test]
+    fn target_names() {
+        for target in [Target::X64, Target::Riscv64, Target::Urcl] {
+            assert_eq!(target.to_string().parse(), Ok(target));
+        }
+        assert!("arm".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn emit_names() {
+        assert_eq!("asm".parse(), Ok(Emit::Assembly));
+        assert_eq!("codegem".parse(), Ok(Emit::Codegem));
+    }
+
+    #[test]
+    fn module_name_is_file_stem() {
+        assert_eq!(module_name(Path::new("src/main.lqd")).unwrap(), "main");
+    }
+
+    #[test]
+    fn compiles_in_memory() {
+        let compilation = compile_source("fn main -> int {\n    1\n}", &CompileOptions::default());
+
+        assert!(compilation.diagnostics.is_empty());
+        assert!(compilation.assembly.unwrap().contains("main"));
+        assert!(compilation.ir.unwrap().contains("main"));
+    }
+
+    #[test]
+    fn errors_are_data() {
+        let compilation = compile_source("fn main -> int {\n    x\n}", &CompileOptions::default());
+
+        assert!(compilation.has_errors());
+        assert!(compilation.assembly.is_none());
+        let error = compilation
+            .diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic["code"] == "L0001")
+            .unwrap();
+        assert_eq!(error["spans"][0]["file"], "main.lqd");
+        assert_eq!(error["spans"][0]["line"], 2);
+    }
+
+    #[test]
+    fn warnings_are_data() {
+        let compilation = compile_source(
+            "fn main -> int {\n    let x = 1;\n    2\n}",
+            &CompileOptions::default(),
+        );
+
+        assert!(!compilation.has_errors());
+        assert!(compilation.assembly.is_some());
+        assert_eq!(compilation.diagnostics.len(), 1);
+        assert_eq!(compilation.diagnostics[0]["severity"], "warning");
+    }
+
+    #[test]
+    fn interprets() {
+        let source = "extern \"C\" fn putchar(c: int) -> int;\n\nfn main -> int {\n    putchar(33);\n    2\n}";
+        let mut output = vec![];
+        let code = Session::new(CompileOptions::default())
+            .interpret(&SourceMap::new("main.lqd", source), &mut output)
+            .unwrap();
+
+        assert_eq!(code, 2);
+        assert_eq!(output, b"!");
+    }
+
+    #[test]
+    fn check_only() {
+        let options = CompileOptions {
+            check: true,
+            ..Default::default()
+        };
+        let compilation = compile_source("fn main -> int {\n    1\n}", &options);
+
+        assert!(!compilation.has_errors());
+        assert!(compilation.assembly.is_none());
+    }
+}
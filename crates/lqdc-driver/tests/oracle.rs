@@ -0,0 +1,84 @@
+//! Runs every program in `tests/programs` with the interpreter and compiled
+//! for x64, and checks both print the same and exit with the same code
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use lqdc_common::source_map::SourceMap;
+use lqdc_driver::{compile_source, CompileOptions, Session};
+
+/// What a program printed, and its exit code
+#[derive(Debug, PartialEq, Eq)]
+struct Run {
+    output: Vec<u8>,
+    code: i32,
+}
+
+fn interpret(name: &str, source: &str) -> Run {
+    let mut output = vec![];
+    let code = Session::new(CompileOptions::default())
+        .interpret(&SourceMap::new(name, source), &mut output)
+        .unwrap_or_else(|error| panic!("{name}: {error:?}"));
+    // Exit codes are a byte, like the compiled program's
+    Run {
+        output,
+        code: code & 0xff,
+    }
+}
+
+fn compile_and_run(name: &str, source: &str, dir: &Path) -> Run {
+    let compilation = compile_source(source, &CompileOptions::default());
+    let assembly = compilation
+        .assembly
+        .unwrap_or_else(|| panic!("{name}: {:#?}", compilation.diagnostics));
+    let stem = name.trim_end_matches(".lqd");
+    let assembly_path = dir.join(format!("{stem}.s"));
+    let executable = dir.join(stem);
+    fs::write(&assembly_path, assembly).unwrap();
+
+    let status = Command::new("clang")
+        .arg(&assembly_path)
+        .arg("-o")
+        .arg(&executable)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{name}: failed to link");
+
+    let result = Command::new(&executable).output().unwrap();
+    Run {
+        output: result.stdout,
+        code: result.status.code().unwrap(),
+    }
+}
+
+#[test]
+fn interpreter_matches_x64() {
+    // Checked when the test runs, so CI with clang always runs it
+    if Command::new("clang").arg("--version").output().is_err() {
+        eprintln!("clang isn't on PATH, skipping the x64 oracle");
+        return;
+    }
+    let dir = env::temp_dir().join(format!("lqdc-oracle-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let programs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut paths: Vec<PathBuf> = fs::read_dir(programs)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let source = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            interpret(name, &source),
+            compile_and_run(name, &source, &dir),
+            "{name}"
+        );
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
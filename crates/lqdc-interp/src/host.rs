@@ -0,0 +1,75 @@
+//! The functions `extern` declarations can call in the interpreter, which
+//! stand in for the C library
+//!
+//! They can be declared with any parameter and return types, like in C,
+//! and get the bits of their arguments.
+
+use std::io::Write;
+
+use lqdc_common::{hir::Span, type_::Type, IntoLabelled};
+
+use crate::{fail, RuntimeError, Unwind, Value};
+
+/// Every host function, by the name it is declared with
+pub const FUNCTIONS: [&str; 2] = ["putchar", "exit"];
+
+/// Calls the host function `name`. `span` is the call, for errors.
+pub(crate) fn call(
+    name: &str,
+    args: &[Value],
+    ret_type: Type,
+    output: &mut dyn Write,
+    span: Span,
+) -> Result<Value, Unwind> {
+    match (name, args) {
+        ("putchar", [c]) => {
+            output
+                .write_all(&[c.bits() as u8])
+                .map_err(|error| fail(RuntimeError::Output(error).labelled(span.into())))?;
+            Ok(Value::from_bits(ret_type, c.bits() & 0xff))
+        }
+        ("exit", [code]) => Err(Unwind::Exit(code.bits() as i32)),
+        _ => Err(fail(
+            RuntimeError::ExternCall(name.to_string()).labelled(span.into()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lqdc_common::{hir::Span, type_::Type};
+
+    use super::call;
+    use crate::{Unwind, Value};
+
+    #[test]
+    fn putchar_writes_a_byte() {
+        let mut output = vec![];
+        let span = Span::new(0, 0);
+        let value = call(
+            "putchar",
+            &[Value::Int(0x141)],
+            Type::Int,
+            &mut output,
+            span,
+        );
+        assert!(matches!(value, Ok(Value::Int(0x41))));
+        assert_eq!(output, b"A");
+
+        let value = call("putchar", &[Value::Uint(10)], Type::Void, &mut output, span);
+        assert!(matches!(value, Ok(Value::Void)));
+        assert_eq!(output, b"A\n");
+    }
+
+    #[test]
+    fn exit_unwinds() {
+        let exit = call(
+            "exit",
+            &[Value::Int(3)],
+            Type::Void,
+            &mut vec![],
+            Span::new(0, 0),
+        );
+        assert!(matches!(exit, Err(Unwind::Exit(3))));
+    }
+}
@@ -0,0 +1,656 @@
+//! A tree-walking interpreter, running checked programs without an assembler
+//! or linker
+//!
+//! It walks the output of [`MonomorphizePass`], so calls, closures and
+//! literals resolve exactly as they do in codegen. `extern` functions are
+//! looked up in [`host`], so programs can be run and their results compared
+//! with the compiled ones.
+
+pub mod host;
+pub mod repl;
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, stdout, Write},
+};
+
+use miette::*;
+use thiserror::Error;
+
+use lqdc_common::{
+    hir::{BinaryOp, Expr, ExprKind, NodeId, Span},
+    linkage::Linkage,
+    make_signatures::Function,
+    monomorphize::MonomorphizePass,
+    type_::Type,
+    Error, IntoLabelled,
+};
+
+/// The most calls deep a program can go before it is stopped
+pub const MAX_DEPTH: usize = 10_000;
+/// Stack of the thread programs run on, with room for `MAX_DEPTH` calls
+const STACK_SIZE: usize = 256 << 20;
+
+/// Errors that only happen when a program runs
+#[derive(Error, Diagnostic, Debug)]
+pub enum RuntimeError {
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Cannot call extern function {}", .0)]
+    #[diagnostic(help("The interpreter can only call putchar and exit, with one argument each"))]
+    ExternCall(String),
+    #[error("Exited with code {}", .0)]
+    Exit(i32),
+    #[error("Failed to write output")]
+    Output(#[source] io::Error),
+    #[error("Stack overflow, more than {} calls deep", MAX_DEPTH)]
+    StackOverflow,
+}
+
+/// A value of one of the language's types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Uint(u64),
+    Bool(bool),
+    Void,
+}
+
+impl Value {
+    pub fn type_(self) -> Type {
+        match self {
+            Value::Int(_) => Type::Int,
+            Value::Uint(_) => Type::Uint,
+            Value::Bool(_) => Type::Bool,
+            Value::Void => Type::Void,
+        }
+    }
+
+    /// The bits a C function would be passed, or return as an exit code
+    pub fn bits(self) -> i64 {
+        match self {
+            Value::Int(value) => value,
+            Value::Uint(value) => value as i64,
+            Value::Bool(value) => value as i64,
+            Value::Void => 0,
+        }
+    }
+
+    /// A value of a type, from the bits a C function returned
+    pub fn from_bits(type_: Type, bits: i64) -> Self {
+        match type_ {
+            Type::Uint => Value::Uint(bits as u64),
+            Type::Bool => Value::Bool(bits & 0xff != 0),
+            Type::Void => Value::Void,
+            _ => Value::Int(bits),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Uint(value) => write!(f, "{value}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Void => write!(f, "()"),
+        }
+    }
+}
+
+/// Why evaluation stopped before the end of a body
+pub(crate) enum Unwind {
+    Return(Value),
+    /// `exit` was called
+    Exit(i32),
+    Error(Report),
+}
+
+impl From<Report> for Unwind {
+    fn from(error: Report) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+pub(crate) fn fail(error: impl Diagnostic + Send + Sync + 'static) -> Unwind {
+    Unwind::Error(Report::new(error))
+}
+
+/// The variables a closure captured, and their values
+type Env<'input> = Vec<(&'input str, Value)>;
+
+/// The variables of a function while it runs
+struct Frame<'a, 'input> {
+    /// Symbol of the function calls resolve in, the enclosing one for closures
+    symbol: &'a str,
+    vars: HashMap<&'input str, Value>,
+    /// Closures bound with `let`, by the node id of the closure and the
+    /// variables it captured there
+    closures: HashMap<&'input str, (NodeId, Env<'input>)>,
+}
+
+/// The variables of a function as they were when it finished
+#[derive(Debug, Default)]
+pub struct Vars {
+    pub values: HashMap<String, Value>,
+    /// The variables each closure bound with `let` captured
+    pub closures: HashMap<String, Vec<(String, Value)>>,
+}
+
+pub struct Interpreter<'a, 'input> {
+    program: &'a MonomorphizePass<'input>,
+    depth: usize,
+    /// Where `putchar` writes, stdout unless set with `with_output`
+    output: Box<dyn Write + Send + 'a>,
+}
+
+impl<'a, 'input> Interpreter<'a, 'input> {
+    pub fn new(program: &'a MonomorphizePass<'input>) -> Self {
+        Self {
+            program,
+            depth: 0,
+            output: Box::new(stdout()),
+        }
+    }
+
+    pub fn with_output(self, output: impl Write + Send + 'a) -> Self {
+        Self {
+            output: Box::new(output),
+            ..self
+        }
+    }
+
+    /// Runs the program from `main`, returning the exit code, which is what
+    /// `main` returns unless `exit` is called
+    pub fn run_main(&mut self) -> Result<i32> {
+        match self.on_thread(|interpreter| interpreter.run("main", vec![])) {
+            Ok((value, _)) => Ok(value.bits() as i32),
+            Err(Unwind::Exit(code)) => Ok(code),
+            Err(Unwind::Error(error)) => Err(error),
+            Err(Unwind::Return(_)) => unreachable!(),
+        }
+    }
+
+    /// Calls the function with the symbol name `symbol`
+    pub fn call(&mut self, symbol: &str, args: Vec<Value>) -> Result<Value> {
+        self.call_with_vars(symbol, args).map(|(value, _)| value)
+    }
+
+    /// Calls a function like [`Interpreter::call`], also returning its
+    /// variables as they were when it finished
+    pub fn call_with_vars(&mut self, symbol: &str, args: Vec<Value>) -> Result<(Value, Vars)> {
+        match self.on_thread(|interpreter| interpreter.run(symbol, args)) {
+            Ok(result) => Ok(result),
+            Err(Unwind::Exit(code)) => Err(RuntimeError::Exit(code).into()),
+            Err(Unwind::Error(error)) => Err(error),
+            Err(Unwind::Return(_)) => unreachable!(),
+        }
+    }
+
+    /// Runs on a thread of its own, since every call is a few calls of
+    /// `eval`, more than the main thread has room for. The output is flushed
+    /// after.
+    fn on_thread<T: Send>(
+        &mut self,
+        run: impl FnOnce(&mut Self) -> Result<T, Unwind> + Send,
+    ) -> Result<T, Unwind> {
+        let result = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn_scoped(scope, || run(self))
+                .into_diagnostic()?
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        });
+        self.output
+            .flush()
+            .map_err(|error| fail(RuntimeError::Output(error)))?;
+        result
+    }
+
+    /// Calls a function, `return` and all
+    fn run(&mut self, symbol: &str, args: Vec<Value>) -> Result<(Value, Vars), Unwind> {
+        let (symbol, function) = self
+            .program
+            .functions
+            .get_key_value(symbol)
+            .ok_or_else(|| fail(Error::FuncDoesntExist(symbol.to_string())))?;
+        let mut frame = self.frame(symbol, function, args, function.span)?;
+        let value = match self.body(&mut frame, &function.body) {
+            Ok(value) | Err(Unwind::Return(value)) => value,
+            Err(unwind) => return Err(unwind),
+        };
+        let vars = Vars {
+            values: frame
+                .vars
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            closures: frame
+                .closures
+                .into_iter()
+                .map(|(name, (_, env))| {
+                    let env = env
+                        .into_iter()
+                        .map(|(name, value)| (name.to_string(), value))
+                        .collect();
+                    (name.to_string(), env)
+                })
+                .collect(),
+        };
+        Ok((value, vars))
+    }
+
+    fn frame(
+        &self,
+        symbol: &'a str,
+        function: &'a Function<'input>,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Frame<'a, 'input>> {
+        ensure!(
+            function.args.len() == args.len(),
+            Error::ExpectedNumArgs(function.args.len(), args.len()).labelled(span.into())
+        );
+        Ok(Frame {
+            symbol,
+            vars: function
+                .args
+                .iter()
+                .map(|(name, _)| *name)
+                .zip(args)
+                .collect(),
+            closures: HashMap::new(),
+        })
+    }
+
+    /// Evaluates each expression in turn, the value is that of the last
+    fn body(
+        &mut self,
+        frame: &mut Frame<'a, 'input>,
+        exprs: &'a [Expr<'input>],
+    ) -> Result<Value, Unwind> {
+        let mut value = Value::Void;
+        for expr in exprs {
+            value = self.eval(frame, expr)?;
+        }
+        Ok(value)
+    }
+
+    fn eval(
+        &mut self,
+        frame: &mut Frame<'a, 'input>,
+        expr: &'a Expr<'input>,
+    ) -> Result<Value, Unwind> {
+        match &expr.kind {
+            ExprKind::Number(number) => {
+                let number = number
+                    .parse::<i64>()
+                    .map_err(|_| fail(Error::InvalidInteger.labelled(expr.span.into())))?;
+                let type_ = self.program.types.get(&(frame.symbol.to_string(), expr.id));
+                Ok(match type_ {
+                    Some(Type::Uint) => Value::Uint(number as u64),
+                    _ => Value::Int(number),
+                })
+            }
+            ExprKind::Bool(value) => Ok(Value::Bool(*value)),
+            ExprKind::Var(name) => frame.vars.get(name).copied().ok_or_else(|| {
+                fail(Error::VarDoesntExist(name.to_string()).labelled(expr.span.into()))
+            }),
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.eval(frame, lhs)?;
+                let rhs = self.eval(frame, rhs)?;
+                binary(*op, lhs, rhs, expr.span)
+            }
+            ExprKind::Let { name, value } => {
+                if let ExprKind::Closure { .. } = value.kind {
+                    // Closures only exist as their lifted function, calls are
+                    // resolved through the binding. Captures are copied here,
+                    // so later bindings don't change what the closure sees.
+                    let closure = self.program.closures.get(&value.id).ok_or_else(|| {
+                        fail(Error::InternalCompilerError(format!(
+                            "Closure {} was never lifted",
+                            name.name
+                        )))
+                    })?;
+                    let mut env = vec![];
+                    for (capture, _) in &closure.env.captures {
+                        let value = frame.vars.get(capture).ok_or_else(|| {
+                            fail(
+                                Error::VarDoesntExist(capture.to_string())
+                                    .labelled(expr.span.into()),
+                            )
+                        })?;
+                        env.push((*capture, *value));
+                    }
+                    frame.closures.insert(name.name, (value.id, env));
+                    return Ok(Value::Void);
+                }
+                frame.closures.remove(name.name);
+                let value = self.eval(frame, value)?;
+                frame.vars.insert(name.name, value);
+                Ok(value)
+            }
+            ExprKind::Call {
+                path,
+                args: arg_exprs,
+                args_span,
+            } => {
+                let mut args = vec![];
+                for arg in arg_exprs {
+                    args.push(self.eval(frame, arg)?);
+                }
+
+                let id = path.joined();
+                if let Some((closure_id, env)) = frame.closures.get(id.as_str()) {
+                    let closure = self.program.closures.get(closure_id).ok_or_else(|| {
+                        fail(Error::InternalCompilerError(format!(
+                            "Closure {id} was never lifted"
+                        )))
+                    })?;
+                    let mut env = env.iter().map(|(_, value)| *value).collect::<Vec<_>>();
+                    env.extend(args);
+                    let mut closure_frame = Frame {
+                        symbol: &closure.function,
+                        vars: closure
+                            .args()
+                            .into_iter()
+                            .map(|(name, _)| name)
+                            .zip(env)
+                            .collect(),
+                        closures: HashMap::new(),
+                    };
+                    return self.enter(expr.span, |interpreter| {
+                        interpreter.eval(&mut closure_frame, &closure.body)
+                    });
+                }
+
+                let target = self
+                    .program
+                    .call_targets
+                    .get(&(frame.symbol.to_string(), expr.id))
+                    .unwrap_or(&id);
+                let (symbol, function) = self
+                    .program
+                    .functions
+                    .get_key_value(target.as_str())
+                    .ok_or_else(|| {
+                        fail(Error::FuncDoesntExist(id.clone()).labelled(path.span.into()))
+                    })?;
+                if function.linkage == Linkage::External {
+                    let ret_type = function.ret_type;
+                    return host::call(symbol, &args, ret_type, &mut self.output, path.span);
+                }
+                let mut callee = self.frame(symbol, function, args, *args_span)?;
+                self.enter(expr.span, |interpreter| {
+                    interpreter.body(&mut callee, &function.body)
+                })
+            }
+            ExprKind::If { condition, body } => {
+                if self.eval(frame, condition)? == Value::Bool(true) {
+                    // Bindings in the body end with it
+                    let vars = frame.vars.clone();
+                    let closures = frame.closures.clone();
+                    let result = self.body(frame, body);
+                    frame.vars = vars;
+                    frame.closures = closures;
+                    result?;
+                }
+                Ok(Value::Void)
+            }
+            ExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(frame, value)?,
+                    None => Value::Void,
+                };
+                Err(Unwind::Return(value))
+            }
+            ExprKind::Closure { .. } => Err(fail(
+                // The type checker only allows closures bound with `let`
+                Error::InternalCompilerError("Closure outside of a let binding".to_string())
+                    .labelled(expr.span.into()),
+            )),
+        }
+    }
+
+    /// Runs the body of a call a level deeper, where `return` stops
+    fn enter(
+        &mut self,
+        span: Span,
+        body: impl FnOnce(&mut Self) -> Result<Value, Unwind>,
+    ) -> Result<Value, Unwind> {
+        if self.depth == MAX_DEPTH {
+            return Err(fail(RuntimeError::StackOverflow.labelled(span.into())));
+        }
+        self.depth += 1;
+        let result = body(self);
+        self.depth -= 1;
+        match result {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Arithmetic wraps, like it does in compiled code
+fn binary(op: BinaryOp, lhs: Value, rhs: Value, span: Span) -> Result<Value, Unwind> {
+    let ordering = match (lhs, rhs) {
+        (Value::Int(lhs), Value::Int(rhs)) => {
+            let value = match op {
+                BinaryOp::Add => lhs.wrapping_add(rhs),
+                BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                BinaryOp::Div if rhs == 0 => {
+                    return Err(fail(RuntimeError::DivisionByZero.labelled(span.into())))
+                }
+                BinaryOp::Div => lhs.wrapping_div(rhs),
+                _ => return Ok(Value::Bool(compare(op, lhs.cmp(&rhs)))),
+            };
+            return Ok(Value::Int(value));
+        }
+        (Value::Uint(lhs), Value::Uint(rhs)) => {
+            let value = match op {
+                BinaryOp::Add => lhs.wrapping_add(rhs),
+                BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                BinaryOp::Div if rhs == 0 => {
+                    return Err(fail(RuntimeError::DivisionByZero.labelled(span.into())))
+                }
+                BinaryOp::Div => lhs / rhs,
+                _ => return Ok(Value::Bool(compare(op, lhs.cmp(&rhs)))),
+            };
+            return Ok(Value::Uint(value));
+        }
+        (Value::Bool(lhs), Value::Bool(rhs)) if op == BinaryOp::Eq => lhs.cmp(&rhs),
+        // The type checker only allows arithmetic and ordering on integers
+        _ => {
+            return Err(fail(
+                Error::InternalCompilerError(format!("Operator {op:?} on {lhs:?} and {rhs:?}"))
+                    .labelled(span.into()),
+            ))
+        }
+    };
+    Ok(Value::Bool(compare(op, ordering)))
+}
+
+fn compare(op: BinaryOp, ordering: std::cmp::Ordering) -> bool {
+    match op {
+        BinaryOp::Gt => ordering.is_gt(),
+        BinaryOp::Gte => ordering.is_ge(),
+        BinaryOp::Eq => ordering.is_eq(),
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::Lte => ordering.is_le(),
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lqdc_common::{
+        closure_convert::ClosureConvertPass, codepass::PassRunner, lower::LowerPass,
+        make_signatures::MakeSignaturesPass, monomorphize::MonomorphizePass, parsepass::ParsePass,
+        type_check::TypeCheckPass,
+    };
+    use miette::*;
+
+    use super::{Interpreter, Value};
+
+    fn program(input: &str) -> Result<MonomorphizePass<'_>> {
+        Ok(PassRunner::<(), ()>::new(input)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .run::<TypeCheckPass>()?
+            .run::<ClosureConvertPass>()?
+            .run::<MonomorphizePass>()?
+            .into_inner())
+    }
+
+    fn run(input: &str) -> Result<Value> {
+        Interpreter::new(&program(input)?).call("main", vec![])
+    }
+
+    #[test]
+    fn arithmetic() -> Result<()> {
+        assert_eq!(run("fn main -> int { 1 + 2 * 3 - 8 / 2 }")?, Value::Int(3));
+        assert_eq!(run("fn main -> uint { 0 - 1 }")?, Value::Uint(u64::MAX));
+        assert_eq!(run("fn main -> bool { 2 > 1 }")?, Value::Bool(true));
+        Ok(())
+    }
+
+    #[test]
+    fn calls_and_return() -> Result<()> {
+        let input = "
+        fn main -> int {
+            let x = 5;
+            fact(x)
+        }
+
+        fn fact(n: int) -> int {
+            if n < 2 {
+                return 1;
+            };
+            n * fact(n - 1)
+        }
+        ";
+        assert_eq!(run(input)?, Value::Int(120));
+        Ok(())
+    }
+
+    #[test]
+    fn generic_and_closure() -> Result<()> {
+        let input = "
+        fn main -> bool {
+            let offset = 1;
+            let add = |x| x + offset;
+            first(add(2) > 2, false)
+        }
+
+        fn first<T>(a: T, b: T) -> T {
+            a
+        }
+        ";
+        assert_eq!(run(input)?, Value::Bool(true));
+        Ok(())
+    }
+
+    #[test]
+    fn if_body_is_scoped() -> Result<()> {
+        let input = "
+        fn main -> int {
+            let x = 1;
+            if x > 0 {
+                let x = 2;
+            };
+            x
+        }
+        ";
+        assert_eq!(run(input)?, Value::Int(1));
+        Ok(())
+    }
+
+    #[test]
+    fn closure_captures_at_definition() -> Result<()> {
+        let input = "
+        fn main -> int {
+            let offset = 1;
+            let add = |x| x + offset;
+            let offset = 10;
+            let offset = true;
+            add(1)
+        }
+        ";
+        assert_eq!(run(input)?, Value::Int(2));
+        Ok(())
+    }
+
+    #[test]
+    fn trait_method() -> Result<()> {
+        let input = "
+        trait Double {
+            fn double(self) -> Self;
+        }
+
+        impl Double for int {
+            fn double(self) -> int {
+                self * 2
+            }
+        }
+
+        fn main -> int {
+            twice(3)
+        }
+
+        fn twice<T: Double>(x: T) -> T {
+            double(x)
+        }
+        ";
+        assert_eq!(run(input)?, Value::Int(6));
+        Ok(())
+    }
+
+    #[test]
+    fn host_functions() -> Result<()> {
+        let input = "
+        extern \"C\" {
+            fn putchar(c: int) -> int;
+            fn exit(code: int) -> void;
+        }
+
+        fn main -> int {
+            putchar(72);
+            putchar(105);
+            exit(3);
+            0
+        }
+        ";
+        let program = program(input)?;
+        let mut output = vec![];
+        let code = Interpreter::new(&program)
+            .with_output(&mut output)
+            .run_main()?;
+        assert_eq!(code, 3);
+        assert_eq!(output, b"Hi");
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let error = run("fn main -> int { let zero = 0; 1 / zero }").unwrap_err();
+        assert_eq!(error.root_cause().to_string(), "Division by zero");
+
+        let error = run("fn main -> int { main() }").unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Stack overflow, more than 10000 calls deep"
+        );
+
+        let error =
+            run("extern \"C\" fn getchar -> int;\nfn main -> int { getchar() }").unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Cannot call extern function getchar"
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! The state of an `lqdc repl` session
+//!
+//! Each line is checked and run as the body of a function, `__repl`, in a
+//! program with every item entered so far. Variables bound by earlier lines
+//! are passed to it as arguments. Closures can't be passed, so the `let`s
+//! binding them are repeated at the start of the body, after binding what
+//! they captured to the values it had when they were first bound.
+
+use std::collections::{BTreeMap, HashMap};
+
+use frontend::token::{lex, Token};
+use miette::*;
+
+use lqdc_common::{
+    closure_convert::ClosureConvertPass,
+    codepass::{CodePass, PassRunner},
+    hir::ExprKind,
+    lower::LowerPass,
+    make_signatures::MakeSignaturesPass,
+    monomorphize::MonomorphizePass,
+    parsepass::ParsePass,
+    source_map::{SourceFile, SourceMap},
+    type_check::TypeCheckPass,
+    Error,
+};
+
+use crate::{Interpreter, Value};
+
+/// The function each line is run in
+const FUNCTION: &str = "__repl";
+/// What errors name the file, every line is part of it
+const FILE: &str = "repl";
+
+#[derive(Default)]
+pub struct Repl {
+    /// Functions and other items entered so far
+    items: Vec<String>,
+    vars: BTreeMap<String, Value>,
+    /// Closures bound so far
+    closures: Vec<Closure>,
+}
+
+struct Closure {
+    name: String,
+    /// The `let` binding the closure
+    binding: String,
+    /// The variables it captured, and their values when it was bound
+    env: Vec<(String, Value)>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks and runs an item, expression or `let` binding, returning what
+    /// to print. Nothing changes if it fails.
+    pub fn eval(&mut self, input: &str) -> Result<Option<String>> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let item = is_item(input);
+
+        let mut source = String::new();
+        let mut files = vec![];
+        let mut add = |source: &mut String, text: &str, end: &str| {
+            files.push(SourceFile::new(FILE.to_string(), source.len(), text));
+            source.push_str(text);
+            source.push_str(end);
+        };
+        for text in self.items.iter().map(String::as_str) {
+            add(&mut source, text, "\n");
+        }
+        if item {
+            add(&mut source, input, "\n");
+        }
+        // Parameters are prefixed so captures and variables don't shadow one
+        // another until they are bound at the start of the body
+        let mut params = vec![];
+        let mut preamble = 0;
+        for (name, value) in &self.vars {
+            params.push((format!("__{name}"), *value));
+        }
+        for (i, closure) in self.closures.iter().enumerate() {
+            for (capture, value) in &closure.env {
+                params.push((format!("__{i}_{capture}"), *value));
+            }
+        }
+        // The return type is inferred when it is checked
+        source.push_str(&format!(
+            "pub fn {FUNCTION}({}) -> void {{\n",
+            params
+                .iter()
+                .map(|(name, value)| format!("{name}: {}", value.type_().mangled_name()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        for (i, closure) in self.closures.iter().enumerate() {
+            for (capture, _) in &closure.env {
+                source.push_str(&format!("let {capture} = __{i}_{capture};\n"));
+                preamble += 1;
+            }
+            add(&mut source, &closure.binding, ";\n");
+            preamble += 1;
+        }
+        for name in self.vars.keys() {
+            source.push_str(&format!("let {name} = __{name};\n"));
+            preamble += 1;
+        }
+        if !item {
+            add(&mut source, input, "\n");
+        }
+        source.push_str("}\n");
+
+        let map = SourceMap::with_files(source, files);
+        let source = map.source.as_str();
+        let signatures = PassRunner::<(), ()>::new(source)
+            .with_source_map(&map)
+            .run::<ParsePass>()?
+            .run::<LowerPass>()?
+            .run::<MakeSignaturesPass>()?
+            .into_inner();
+        let body = signatures
+            .signatures
+            .functions
+            .get(FUNCTION)
+            .ok_or_else(|| Error::InternalCompilerError(format!("{FUNCTION} is missing")))?
+            .body
+            .clone();
+
+        let mut captures = HashMap::new();
+        // A line has the type of its last expression
+        let program = TypeCheckPass::check_inferring_return(signatures, FUNCTION)
+            .and_then(|checked| {
+                for expr in &body {
+                    if let ExprKind::Let { value, .. } = &expr.kind {
+                        if let Some(names) = checked.captures(value.id) {
+                            captures.insert(value.id, names.to_vec());
+                        }
+                    }
+                }
+                ClosureConvertPass::pass(checked, source, &mut ())
+            })
+            .and_then(|converted| MonomorphizePass::pass(converted, source, &mut ()))
+            .map_err(|error| error.with_source_code(map.clone()))?;
+        if item {
+            self.items.push(input.to_string());
+            return Ok(None);
+        }
+
+        let args = params.into_iter().map(|(_, value)| value).collect();
+        let (value, vars) = Interpreter::new(&program)
+            .call_with_vars(FUNCTION, args)
+            .map_err(|error| error.with_source_code(map.clone()))?;
+
+        // The repeated bindings come first
+        let line = &body[preamble..];
+        for expr in line {
+            if let ExprKind::Let { name, value } = &expr.kind {
+                let name = name.name.to_string();
+                self.closures.retain(|closure| closure.name != name);
+                if let ExprKind::Closure { body, .. } = &value.kind {
+                    self.vars.remove(&name);
+                    let env = vars.closures.get(&name).into_iter().flatten();
+                    let captures = captures.get(&value.id).into_iter().flatten();
+                    // Later lines may not call it, so the parameters keep the
+                    // types they were inferred to have here
+                    let lifted = program.closures.get(&value.id).ok_or_else(|| {
+                        Error::InternalCompilerError(format!("Closure {name} was never lifted"))
+                    })?;
+                    let params = lifted
+                        .params
+                        .iter()
+                        .map(|(param, type_)| format!("{param}: {}", type_.mangled_name()))
+                        .collect::<Vec<_>>();
+                    self.closures.push(Closure {
+                        binding: format!(
+                            "let {name} = |{}| {}",
+                            params.join(", "),
+                            &source[body.span.start..body.span.end]
+                        ),
+                        env: env
+                            .filter(|(var, _)| captures.clone().any(|(capture, _)| capture == var))
+                            .cloned()
+                            .collect(),
+                        name,
+                    });
+                } else if let Some(value) = vars.values.get(&name) {
+                    self.vars.insert(name, *value);
+                }
+            }
+        }
+
+        let output = match line.last().map(|expr| &expr.kind) {
+            // Nothing is bound if the line returned before the `let`
+            Some(ExprKind::Let { name, value }) => match value.kind {
+                ExprKind::Closure { .. } => None,
+                _ => self.vars.get(name.name).map(|value| {
+                    format!("{}: {} = {value}", name.name, value.type_().mangled_name())
+                }),
+            },
+            _ if value == Value::Void => None,
+            _ => Some(format!("{value}: {}", value.type_().mangled_name())),
+        };
+        Ok(output)
+    }
+}
+
+/// Whether `input` is a whole line, or needs more to close its brackets or
+/// comments
+pub fn is_complete(input: &str) -> bool {
+    let lexemes = match lex(input) {
+        Ok(lexemes) => lexemes,
+        Err(error) => return error.message != "Unterminated comment",
+    };
+    let mut depth = 0;
+    for lexeme in lexemes {
+        match lexeme.token {
+            Token::OpenBrace | Token::OpenParen | Token::OpenBracket => depth += 1,
+            Token::CloseBrace | Token::CloseParen | Token::CloseBracket => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+/// Items are added to the program, everything else is run
+fn is_item(input: &str) -> bool {
+    let first = lex(input).ok().and_then(|lexemes| {
+        lexemes
+            .into_iter()
+            .find(|lexeme| lexeme.token.is_structural())
+    });
+    matches!(
+        first.map(|lexeme| lexeme.token),
+        Some(
+            Token::Fn
+                | Token::Pub
+                | Token::Extern
+                | Token::Mod
+                | Token::Trait
+                | Token::Impl
+                | Token::Import
+                | Token::Hash
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_complete, Repl};
+
+    fn eval(repl: &mut Repl, input: &str) -> Option<String> {
+        repl.eval(input).unwrap()
+    }
+
+    #[test]
+    fn session() {
+        let mut repl = Repl::new();
+        assert_eq!(eval(&mut repl, "1 + 2"), Some("3: int".to_string()));
+        assert_eq!(eval(&mut repl, "let x = 4"), Some("x: int = 4".to_string()));
+        assert_eq!(eval(&mut repl, "fn double(n: int) -> int { n * 2 }"), None);
+        assert_eq!(
+            eval(&mut repl, "double(x) > 7"),
+            Some("true: bool".to_string())
+        );
+        assert_eq!(eval(&mut repl, "let add = |n| n + x;"), None);
+        assert_eq!(
+            eval(&mut repl, "let x = add(1)"),
+            Some("x: int = 5".to_string())
+        );
+        // `add` keeps the `x` it captured
+        assert_eq!(eval(&mut repl, "add(x)"), Some("9: int".to_string()));
+    }
+
+    #[test]
+    fn errors_change_nothing() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "let x = 1");
+
+        let error = repl.eval("let y = x + true").unwrap_err();
+        assert!(error.code().is_some());
+        let error = repl.eval("x / 0").unwrap_err();
+        assert_eq!(error.root_cause().to_string(), "Division by zero");
+        let error = repl.eval("fn broken -> int { y }").unwrap_err();
+        assert!(error.root_cause().to_string().contains('y'));
+
+        assert_eq!(eval(&mut repl, "x"), Some("1: int".to_string()));
+    }
+
+    #[test]
+    fn returns() {
+        let mut repl = Repl::new();
+        assert_eq!(eval(&mut repl, "return 3"), Some("3: int".to_string()));
+        assert_eq!(eval(&mut repl, "return 3; let y = 4"), None);
+        assert_eq!(
+            eval(&mut repl, "if 1 > 0 { return false; }; true"),
+            Some("false: bool".to_string())
+        );
+
+        // A line's returns have the type of its last expression
+        let error = repl.eval("if 1 > 0 { return 1; }; true").unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Expected Bool, found Number"
+        );
+    }
+
+    #[test]
+    fn closure_types_are_kept() {
+        let mut repl = Repl::new();
+        let error = repl.eval("let same = |a| a == a").unwrap_err();
+        let code = error.diagnostic_source().and_then(|error| error.code());
+        assert_eq!(code.unwrap().to_string(), "L0029");
+
+        assert_eq!(
+            eval(&mut repl, "let same = |a| a == a; same(true)"),
+            Some("true: bool".to_string())
+        );
+        assert_eq!(eval(&mut repl, "1"), Some("1: int".to_string()));
+        assert_eq!(
+            eval(&mut repl, "same(false)"),
+            Some("true: bool".to_string())
+        );
+        assert!(repl.eval("same(1)").is_err());
+    }
+
+    #[test]
+    fn multi_line_input() {
+        assert!(!is_complete("fn double(n: int) -> int {"));
+        assert!(!is_complete("/* a comment"));
+        assert!(is_complete("fn double(n: int) -> int {\n    n * 2\n}"));
+        assert!(is_complete("1 +"));
+    }
+}
@@ -1,63 +1,39 @@
-use std::{
-    fs::{read_to_string, OpenOptions},
-    path::PathBuf,
-};
+use std::path::PathBuf;
 
 use clap::Parser;
 use miette::*;
 use url_open::Url;
 
-use lqdc_codegem::{
-    codegem::{
-        arch::urcl::{UrclInstruction, UrclSelector},
-        ir::ModuleBuilder,
-        regalloc::RegAlloc,
-    },
-    codegen::CodegenPass,
-    CodegemError,
-};
-use lqdc_common::{
-    codepass::PassRunner, make_signatures::MakeSignaturesPass, parsepass::ParsePass,
-    type_check::TypeCheck,
-};
+use lqdc_common::emit::ErrorFormat;
+use lqdc_driver::{CompileOptions, Session, Target};
 
 #[derive(Parser)]
 struct Cli {
     input: PathBuf,
     #[clap(short, long)]
     output: PathBuf,
+    /// How to print errors, human or json
+    #[clap(long, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
     #[clap(long)]
     stdlib: Url,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let error_format = cli.error_format;
+    error_format.exit_on_error(run(cli))
+}
 
-    let without_ext = cli.input.with_extension("");
-    let name = without_ext.file_name().unwrap().to_str().unwrap();
-    let input = read_to_string(&cli.input).into_diagnostic()?;
-
-    let mut builder = ModuleBuilder::default().with_name(name);
-
-    PassRunner::<(), ()>::new(&input)
-        .run::<ParsePass>()?
-        .run::<MakeSignaturesPass>()?
-        .inject::<TypeCheck>()?
-        .set_arg(&mut builder)
-        .run::<CodegenPass>()?;
-
-    let module = builder.build().map_err(CodegemError::ModuleCreationError)?;
-
-    let mut vcode = module.lower_to_vcode::<UrclInstruction, UrclSelector>();
-    vcode.allocate_regs::<RegAlloc>();
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(&cli.output)
-        .into_diagnostic()?;
-
-    vcode.emit_assembly(&mut file).into_diagnostic()?;
+fn run(cli: Cli) -> Result<()> {
+    Session::new(CompileOptions {
+        inputs: vec![cli.input],
+        target: Target::Urcl,
+        output: Some(cli.output),
+        error_format: cli.error_format,
+        ..Default::default()
+    })
+    .compile()?;
 
     Ok(())
 }
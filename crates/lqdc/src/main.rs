@@ -1,129 +1,268 @@
 use std::{
-    fs::{self, read_to_string, remove_dir_all, OpenOptions},
-    io::Write,
-    path::PathBuf,
-    process::Command,
+    fs,
+    io::{stdin, stdout, Write},
+    path::{Path, PathBuf},
+    process::{exit, Command},
 };
 
-use chrono::Local;
-use clap::Parser;
-use lqdc_codegem::{
-    codegem::{
-        arch::x64::{X64Instruction, X64Selector},
-        ir::ModuleBuilder,
-        regalloc::RegAlloc,
-    },
-    codegen::CodegenPass,
-    CodegemError,
-};
+use clap::{Args, Parser, Subcommand};
 use miette::*;
 
 use lqdc_common::{
-    codepass::PassRunner, make_signatures::MakeSignaturesPass, parsepass::ParsePass,
-    type_check::TypeCheck,
+    doc::DocFormat,
+    emit::ErrorFormat,
+    explain::explain,
+    lint::{Level, LintLevels},
+    mangle::{demangle, demangle_all},
 };
+use lqdc_driver::{document, format_file, CompileOptions, Emit, Output, Session, Target};
+use lqdc_interp::repl::{is_complete, Repl};
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-
-    let tmp_folder = if cli.temp_dir.is_none() {
-        PathBuf::from(format!(
-            ".lqdc-tmp-{}",
-            Local::now().format("%d-%m-%Y_%H%M%S")
-        ))
-    } else {
-        cli.temp_dir.unwrap()
+    let error_format = match &cli.action {
+        Some(Action::Run { options, .. }) => options.error_format,
+        _ => cli.options.error_format,
     };
-    if tmp_folder.exists() {
-        fs::remove_dir_all(&tmp_folder)
-            .into_diagnostic()
-            .map_err(|e| e.wrap_err("Failed to delete temp folder"))?;
+    error_format.exit_on_error(run(cli))
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if let Some(Action::Demangle { symbols }) = cli.action {
+        if symbols.is_empty() {
+            for line in stdin().lines() {
+                println!("{}", demangle_all(&line.into_diagnostic()?));
+            }
+        } else {
+            for symbol in symbols {
+                println!("{}", demangle(&symbol).unwrap_or(symbol));
+            }
+        }
+        return Ok(());
     }
 
-    fs::create_dir(&tmp_folder)
-        .into_diagnostic()
-        .map_err(|e| e.wrap_err("Failed to create temp folder"))?;
-
-    let mut outputs = vec![];
-    for input in cli.input {
-        let without_ext = input.with_extension("");
-        let name = without_ext.file_name().unwrap().to_str().unwrap();
-        outputs.push(tmp_folder.join(format!("{name}.s")));
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(tmp_folder.join(format!("{name}.s")))
-            .into_diagnostic()
-            .map_err(|e| e.wrap_err("Failed to obtain handle to tmp file"))?;
-        let input = read_to_string(&input).into_diagnostic()?;
-        let mut module_builder = ModuleBuilder::default().with_name(name);
-
-        let runner = PassRunner::<(), ()>::new(&input)
-            .run::<ParsePass>()?
-            .run::<MakeSignaturesPass>()?
-            .inject::<TypeCheck>()?;
-
-        if !cli.check {
-            runner.set_arg(&mut module_builder).run::<CodegenPass>()?;
-
-            let module = module_builder
-                .build()
-                .map_err(CodegemError::ModuleCreationError)?;
-
-            if cli.emit_codegem {
-                fs::write(
-                    tmp_folder.join(format!("{name}.codegem")),
-                    format!("{module}"),
-                )
-                .into_diagnostic()
-                .map_err(|e| e.wrap_err("Failed to emit codegem IR"))?;
+    if let Some(Action::Fmt { check, files }) = cli.action {
+        let mut unformatted = 0;
+        for file in &files {
+            if format_file(file, check)? && check {
+                println!("{} isn't formatted", file.display());
+                unformatted += 1;
             }
+        }
+        if unformatted > 0 {
+            bail!("{unformatted} files aren't formatted, run `lqdc fmt` to format them");
+        }
+        return Ok(());
+    }
 
-            let mut vcode = module.lower_to_vcode::<X64Instruction, X64Selector>();
-            vcode.allocate_regs::<RegAlloc>();
+    if let Some(Action::Doc {
+        input,
+        out_dir,
+        mut format,
+    }) = cli.action
+    {
+        if format.is_empty() {
+            format = vec![DocFormat::Html, DocFormat::Markdown];
+        }
+        document(&input, &out_dir, &format)?;
+        return Ok(());
+    }
 
-            let mut buf = Vec::new();
+    if let Some(Action::Repl) = cli.action {
+        return repl();
+    }
 
-            {
-                vcode.emit_assembly(&mut buf).into_diagnostic()?;
-            }
+    if let Some(Action::Run {
+        input,
+        interp,
+        options,
+    }) = cli.action
+    {
+        let options = options.compile_options()?;
+        let code = if interp {
+            Session::new(options).interpret_file(&input, stdout())?
+        } else {
+            run_compiled(&input, options)?
+        };
+        exit(code);
+    }
 
-            // Codegem generates assembly with percentage signs, but clang does not support them
-            let str_buf = String::from_utf8(buf).into_diagnostic()?;
-            let str_buf = str_buf.replace("%", "");
-            file.write_all(str_buf.as_bytes()).into_diagnostic()?;
-            #[cfg(any(feature = "clang", feature = "gcc"))]
-            {
-                #[cfg(feature = "clang")]
-                let mut command = Command::new("clang");
-                #[cfg(feature = "gcc")]
-                let mut command = Command::new("gcc");
-                for output in &outputs {
-                    command.arg(output.to_str().unwrap());
+    if let Some(code) = cli.explain {
+        let explanation =
+            explain(&code).ok_or_else(|| miette!("{code} is not an lqdc error code"))?;
+        print!("{explanation}");
+        return Ok(());
+    }
+
+    let tmp_folder = TempFolder::new(cli.temp_dir, cli.keep_temp)?;
+
+    let mut emit = vec![Emit::Assembly];
+    if cli.emit_codegem {
+        emit.push(Emit::Codegem);
+    }
+    let session = Session::new(CompileOptions {
+        inputs: cli.input,
+        target: Target::X64,
+        emit,
+        out_dir: tmp_folder.path.clone(),
+        check: cli.check,
+        ..cli.options.compile_options()?
+    });
+    let outputs = session.compile()?;
+
+    #[cfg(any(feature = "clang", feature = "gcc"))]
+    if !cli.check {
+        link(
+            &outputs,
+            cli.output.as_ref().unwrap(),
+            &cli.linker_args,
+            session.options(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A folder for intermediate files, deleted when dropped unless it's kept, so
+/// it's cleaned up even when compilation fails
+struct TempFolder {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempFolder {
+    /// Creates the folder at `temp_dir`, replacing whatever was there, or a
+    /// uniquely named one in the current directory
+    fn new(temp_dir: Option<PathBuf>, keep: bool) -> Result<Self> {
+        let path = match temp_dir {
+            Some(path) => {
+                if path.exists() {
+                    fs::remove_dir_all(&path)
+                        .into_diagnostic()
+                        .map_err(|e| e.wrap_err("Failed to delete temp folder"))?;
                 }
-                command
-                    .args(["-o", &cli.output.to_str().unwrap()])
-                    .args(&cli.linker_args)
-                    .status()
-                    .unwrap();
+                fs::create_dir(&path)
+                    .into_diagnostic()
+                    .map_err(|e| e.wrap_err("Failed to create temp folder"))?;
+                path
             }
-        }
+            None => tempfile::Builder::new()
+                .prefix(".lqdc-tmp-")
+                .keep(true)
+                .tempdir_in(".")
+                .into_diagnostic()
+                .map_err(|e| e.wrap_err("Failed to create temp folder"))?
+                .path()
+                .to_path_buf(),
+        };
+        Ok(TempFolder { path, keep })
     }
+}
 
-    if !cli.keep_temp {
-        remove_dir_all(tmp_folder)
-            .into_diagnostic()
-            .map_err(|e| e.wrap_err("Failed to delete tmp folder"))?;
+impl Drop for TempFolder {
+    fn drop(&mut self) {
+        if !self.keep {
+            // There's nowhere to report this, and a leftover folder is harmless
+            let _ = fs::remove_dir_all(&self.path);
+        }
     }
+}
 
+/// Links the assembly of every output into an executable
+#[cfg(any(feature = "clang", feature = "gcc"))]
+fn link(
+    outputs: &[Output],
+    output: &Path,
+    linker_args: &[String],
+    options: &CompileOptions,
+) -> Result<()> {
+    #[cfg(feature = "clang")]
+    let mut command = Command::new("clang");
+    #[cfg(feature = "gcc")]
+    let mut command = Command::new("gcc");
+    for output in outputs {
+        if let Some(assembly) = output.file(Emit::Assembly) {
+            command.arg(assembly);
+        }
+    }
+    let status = command
+        .arg(format!("-O{}", options.opt_level))
+        .arg("-o")
+        .arg(output)
+        .args(linker_args)
+        .status()
+        .into_diagnostic()
+        .map_err(|e| e.wrap_err("Failed to run the linker"))?;
+    ensure!(status.success(), "Linking failed");
     Ok(())
 }
 
+#[cfg(not(any(feature = "clang", feature = "gcc")))]
+fn link(_: &[Output], _: &Path, _: &[String], _: &CompileOptions) -> Result<()> {
+    bail!("lqdc was built without a linker, enable the clang or gcc feature, or use --interp");
+}
+
+/// Compiles and links a program in a temp folder, then runs it, returning
+/// its exit code
+fn run_compiled(input: &Path, options: CompileOptions) -> Result<i32> {
+    let tmp_folder = TempFolder::new(None, false)?;
+    let session = Session::new(CompileOptions {
+        inputs: vec![input.to_path_buf()],
+        out_dir: tmp_folder.path.clone(),
+        ..options
+    });
+    let outputs = session.compile()?;
+    let executable = tmp_folder.path.join("program");
+    link(&outputs, &executable, &[], session.options())?;
+
+    let status = Command::new(&executable)
+        .status()
+        .into_diagnostic()
+        .map_err(|e| e.wrap_err(format!("Failed to run {}", executable.display())))?;
+    // There is no code if it was killed by a signal
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Reads lines until they are a whole entry, then runs it and prints the
+/// result, until `:quit` or the end of stdin
+fn repl() -> Result<()> {
+    let mut repl = Repl::new();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        stdout().flush().into_diagnostic()?;
+        let mut line = String::new();
+        if stdin().read_line(&mut line).into_diagnostic()? == 0 {
+            return Ok(());
+        }
+        if input.is_empty() && line.trim() == ":quit" {
+            return Ok(());
+        }
+
+        input.push_str(&line);
+        if !is_complete(&input) {
+            continue;
+        }
+        match repl.eval(&input) {
+            Ok(Some(output)) => println!("{output}"),
+            Ok(None) => {}
+            Err(error) => eprintln!("{error:?}"),
+        }
+        input.clear();
+    }
+}
+
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[clap(subcommand)]
+    action: Option<Action>,
     input: Vec<PathBuf>,
-    #[clap(short, long)]
-    output: PathBuf,
+    #[clap(short, long, required_unless_present_any = ["explain", "check"])]
+    output: Option<PathBuf>,
+    /// Explain an error code, like L0023
+    #[clap(long, value_name = "CODE")]
+    explain: Option<String>,
     /// Only output object files
     #[clap(short = 'c')]
     gen_obj_files: bool,
@@ -141,4 +280,120 @@ struct Cli {
     /// You probably want --keep-dir as well
     #[clap(long)]
     emit_codegem: bool,
+    #[clap(flatten)]
+    options: Options,
+}
+
+/// Options for checking and compiling a program, shared with `lqdc run`
+#[derive(Args)]
+struct Options {
+    /// Optimization level, passed to the assembler and linker
+    #[clap(short = 'O', value_name = "LEVEL", default_value_t = 0)]
+    opt_level: u8,
+    /// The most errors to report at once, 0 for no limit
+    #[clap(long, default_value_t = 20)]
+    error_limit: usize,
+    /// How to print errors and warnings, human or json
+    #[clap(long, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+    /// Allow a lint, `all` for every lint. Applied before -W and -D
+    #[clap(short = 'A', value_name = "LINT")]
+    allow: Vec<String>,
+    /// Warn about a lint, `all` for every lint
+    #[clap(short = 'W', value_name = "LINT")]
+    warn: Vec<String>,
+    /// Make a lint an error, `all` for every lint, `warnings` for every
+    /// lint that warns
+    #[clap(short = 'D', value_name = "LINT")]
+    deny: Vec<String>,
+    /// Unstable options: time-passes, disable-pass=PASS, enable-pass=PASS
+    #[clap(short = 'Z', value_name = "OPTION")]
+    unstable: Vec<String>,
+}
+
+/// Options set with `-Z`
+#[derive(Default)]
+struct Unstable {
+    time_passes: bool,
+    /// Passes to enable or disable, by name
+    passes: Vec<(String, bool)>,
+}
+
+impl Unstable {
+    fn parse(options: &[String]) -> Result<Self> {
+        let mut unstable = Self::default();
+        for option in options {
+            match option.split_once('=') {
+                None if option == "time-passes" => unstable.time_passes = true,
+                Some(("disable-pass", name)) => unstable.passes.push((name.to_string(), false)),
+                Some(("enable-pass", name)) => unstable.passes.push((name.to_string(), true)),
+                _ => bail!("Unknown option -Z {option}"),
+            }
+        }
+        Ok(unstable)
+    }
+}
+
+impl Options {
+    fn compile_options(&self) -> Result<CompileOptions> {
+        let mut levels = LintLevels::default();
+        for (names, level) in [
+            (&self.allow, Level::Allow),
+            (&self.warn, Level::Warn),
+            (&self.deny, Level::Deny),
+        ] {
+            for name in names {
+                levels.set(name, level)?;
+            }
+        }
+
+        let unstable = Unstable::parse(&self.unstable)?;
+
+        Ok(CompileOptions {
+            opt_level: self.opt_level,
+            error_limit: (self.error_limit > 0).then_some(self.error_limit),
+            error_format: self.error_format,
+            lint_levels: levels,
+            time_passes: unstable.time_passes,
+            passes: unstable.passes,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum Action {
+    /// Demangle symbol names, or every symbol in stdin if none are given
+    Demangle { symbols: Vec<String> },
+    /// Format source files in place
+    Fmt {
+        /// Only check that the files are formatted, failing if any aren't
+        #[clap(long)]
+        check: bool,
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Generate documentation for a program and its imports, a page for
+    /// each module
+    Doc {
+        input: PathBuf,
+        /// Where the pages are written
+        #[clap(short, long, default_value = "doc")]
+        out_dir: PathBuf,
+        /// html or markdown, both if not given
+        #[clap(long)]
+        format: Vec<DocFormat>,
+    },
+    /// Check and run expressions, `let` bindings and functions line by line,
+    /// printing each result with its type
+    Repl,
+    /// Compile and run a program, exiting with its exit code
+    Run {
+        input: PathBuf,
+        /// Run it with the interpreter instead, without assembling or linking
+        #[clap(long)]
+        interp: bool,
+        #[clap(flatten)]
+        options: Options,
+    },
 }